@@ -111,11 +111,11 @@ CREATE TABLE IF NOT EXISTS store_credentials (
 );
 
 -- Create indexes for common queries
-CREATE INDEX idx_user_app_user_id ON users(app_user_id);
-CREATE INDEX idx_subscriptions_user_id ON subscriptions(user_id);
-CREATE INDEX idx_subscriptions_status ON subscriptions(status);
-CREATE INDEX idx_subscriptions_expires_date ON subscriptions(expires_date);
-CREATE INDEX idx_user_entitlements_user_id ON user_entitlements(user_id);
-CREATE INDEX idx_user_entitlements_expires_at ON user_entitlements(expires_at);
-CREATE INDEX idx_transactions_user_id ON transactions(user_id);
-CREATE INDEX idx_transactions_subscription_id ON transactions(subscription_id);
+CREATE INDEX IF NOT EXISTS idx_user_app_user_id ON users(app_user_id);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_user_id ON subscriptions(user_id);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_status ON subscriptions(status);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_expires_date ON subscriptions(expires_date);
+CREATE INDEX IF NOT EXISTS idx_user_entitlements_user_id ON user_entitlements(user_id);
+CREATE INDEX IF NOT EXISTS idx_user_entitlements_expires_at ON user_entitlements(expires_at);
+CREATE INDEX IF NOT EXISTS idx_transactions_user_id ON transactions(user_id);
+CREATE INDEX IF NOT EXISTS idx_transactions_subscription_id ON transactions(subscription_id);
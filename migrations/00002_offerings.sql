@@ -0,0 +1,42 @@
+-- Apps table (each client app that serves paywalls)
+CREATE TABLE IF NOT EXISTS apps (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    apple_bundle_id TEXT,               -- Apple bundle identifier
+    google_package_name TEXT,           -- Google Play package name
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(apple_bundle_id),
+    UNIQUE(google_package_name)
+);
+
+-- Offerings table (a named set of packages shown on a paywall)
+CREATE TABLE IF NOT EXISTS offerings (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    identifier TEXT NOT NULL,           -- e.g. 'default', 'holiday_sale'
+    description TEXT,
+    metadata TEXT NOT NULL DEFAULT '{}', -- Arbitrary JSON used to configure the paywall remotely
+    is_current BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (app_id) REFERENCES apps(id) ON DELETE CASCADE,
+    UNIQUE(app_id, identifier)
+);
+
+-- Packages table (a slot in an offering pointing at a product)
+CREATE TABLE IF NOT EXISTS packages (
+    id TEXT PRIMARY KEY,
+    offering_id TEXT NOT NULL,
+    identifier TEXT NOT NULL,           -- e.g. '$rc_monthly', '$rc_annual', '$rc_lifetime' or a custom name
+    product_id TEXT NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0, -- Display order within the offering
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (offering_id) REFERENCES offerings(id) ON DELETE CASCADE,
+    FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE,
+    UNIQUE(offering_id, identifier)
+);
+
+CREATE INDEX IF NOT EXISTS idx_offerings_app_id ON offerings(app_id);
+CREATE INDEX IF NOT EXISTS idx_packages_offering_id ON packages(offering_id);
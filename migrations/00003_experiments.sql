@@ -0,0 +1,43 @@
+-- Experiments table (paywall A/B tests run against an app's offerings)
+CREATE TABLE IF NOT EXISTS experiments (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    status TEXT NOT NULL,               -- 'draft', 'running' or 'stopped'
+    started_at TIMESTAMP,
+    stopped_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (app_id) REFERENCES apps(id) ON DELETE CASCADE
+);
+
+-- Experiment variants (each serves a different offering)
+CREATE TABLE IF NOT EXISTS experiment_variants (
+    id TEXT PRIMARY KEY,
+    experiment_id TEXT NOT NULL,
+    identifier TEXT NOT NULL,           -- e.g. 'control', 'treatment'
+    offering_id TEXT NOT NULL,
+    weight INTEGER NOT NULL DEFAULT 1,  -- Relative share of users sent to this variant
+    position INTEGER NOT NULL DEFAULT 0, -- The variant at position 0 is the control
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (experiment_id) REFERENCES experiments(id) ON DELETE CASCADE,
+    FOREIGN KEY (offering_id) REFERENCES offerings(id) ON DELETE CASCADE,
+    UNIQUE(experiment_id, identifier)
+);
+
+-- Experiment assignments (which variant each user was put in)
+CREATE TABLE IF NOT EXISTS experiment_assignments (
+    id TEXT PRIMARY KEY,
+    experiment_id TEXT NOT NULL,
+    variant_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    assigned_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (experiment_id) REFERENCES experiments(id) ON DELETE CASCADE,
+    FOREIGN KEY (variant_id) REFERENCES experiment_variants(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(experiment_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_experiments_app_id ON experiments(app_id);
+CREATE INDEX IF NOT EXISTS idx_experiment_assignments_variant_id ON experiment_assignments(variant_id);
@@ -0,0 +1,2 @@
+-- Storefront country of the purchase, used to split cohort reports
+ALTER TABLE subscriptions ADD COLUMN country_code TEXT;  -- ISO 3166-1 alpha-2 code (US, DE, etc.)
@@ -0,0 +1,9 @@
+-- Exchange rates used to normalize store prices to USD
+CREATE TABLE IF NOT EXISTS exchange_rates (
+    currency TEXT PRIMARY KEY,           -- ISO 4217 code (EUR, JPY, etc.)
+    usd_rate REAL NOT NULL,              -- USD value of one unit of the currency
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Ledger amounts converted to USD at the rate known when they were recorded
+ALTER TABLE transactions ADD COLUMN amount_usd REAL;
@@ -0,0 +1,28 @@
+-- Store commission rules used to estimate proceeds
+CREATE TABLE IF NOT EXISTS commission_rules (
+    store TEXT PRIMARY KEY,              -- 'apple' or 'google'
+    standard_rate REAL NOT NULL,         -- Commission on a sale, e.g. 0.30
+    reduced_rate REAL NOT NULL,          -- Reduced commission, e.g. 0.15
+    small_business BOOLEAN NOT NULL DEFAULT FALSE,             -- Enrolled in the store's small business program
+    reduced_after_days INTEGER,          -- Subscriptions get the reduced rate after this many paid days
+    reduced_for_subscriptions BOOLEAN NOT NULL DEFAULT FALSE,  -- Subscriptions always get the reduced rate
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO commission_rules (store, standard_rate, reduced_rate, small_business, reduced_after_days, reduced_for_subscriptions)
+VALUES
+    ('apple', 0.30, 0.15, FALSE, 365, FALSE),
+    ('google', 0.30, 0.15, FALSE, NULL, TRUE);
+
+-- Sales tax or VAT included in store prices, by storefront country
+CREATE TABLE IF NOT EXISTS tax_rates (
+    country_code TEXT PRIMARY KEY,       -- ISO 3166-1 alpha-2 code (US, DE, etc.)
+    rate REAL NOT NULL,                  -- e.g. 0.19 for 19% VAT
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Estimated proceeds after tax and commission, in the transaction's currency and in USD
+ALTER TABLE transactions ADD COLUMN commission_rate REAL;
+ALTER TABLE transactions ADD COLUMN tax_rate REAL;
+ALTER TABLE transactions ADD COLUMN proceeds REAL;
+ALTER TABLE transactions ADD COLUMN proceeds_usd REAL;
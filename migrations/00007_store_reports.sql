@@ -0,0 +1,37 @@
+-- Financial reports imported from App Store Connect and the Play Console
+CREATE TABLE IF NOT EXISTS store_reports (
+    id TEXT PRIMARY KEY,
+    store TEXT NOT NULL,                 -- 'apple' or 'google'
+    report_type TEXT NOT NULL,           -- 'apple_sales', 'apple_subscription_events', 'google_earnings', 'google_sales'
+    filename TEXT,
+    content_hash TEXT NOT NULL,          -- Detects the same file being imported twice
+    start_date DATE,                     -- First and last event dates in the report
+    end_date DATE,
+    row_count INTEGER NOT NULL DEFAULT 0,
+    skipped_count INTEGER NOT NULL DEFAULT 0,  -- Rows that aren't sales or refunds
+    imported_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(store, content_hash)
+);
+
+-- Sales and refunds from a report, normalized across report formats
+CREATE TABLE IF NOT EXISTS store_report_rows (
+    id TEXT PRIMARY KEY,
+    report_id TEXT NOT NULL,
+    event_date DATE NOT NULL,
+    kind TEXT NOT NULL,                  -- 'sale' or 'refund'
+    order_id TEXT,                       -- Google order ID, when the report has one
+    store_product_id TEXT,
+    quantity INTEGER NOT NULL DEFAULT 1,
+    amount REAL,                         -- Customer price per unit, negative for refunds
+    currency TEXT,
+    proceeds REAL,                       -- Developer proceeds per unit
+    proceeds_currency TEXT,
+    country_code TEXT,
+    matched_quantity INTEGER NOT NULL DEFAULT 0,
+    matched_transaction_id TEXT,         -- First ledger transaction matched to the row
+    raw_data TEXT,
+    FOREIGN KEY (report_id) REFERENCES store_reports(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_store_report_rows_report_id ON store_report_rows(report_id);
+CREATE INDEX IF NOT EXISTS idx_store_report_rows_order_id ON store_report_rows(order_id);
@@ -0,0 +1,21 @@
+-- Consumable products grant an amount of a virtual currency
+ALTER TABLE products ADD COLUMN virtual_currency TEXT;            -- Currency code, e.g. 'COINS'
+ALTER TABLE products ADD COLUMN virtual_currency_amount INTEGER;  -- Amount granted per purchase
+
+-- Virtual currency ledger. Balances are the sum of a user's entries.
+CREATE TABLE IF NOT EXISTS wallet_entries (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    amount INTEGER NOT NULL,             -- Positive for credits, negative for debits
+    type TEXT NOT NULL,                  -- 'purchase', 'spend', 'refund', 'adjustment'
+    subscription_id TEXT,                -- The purchase that credited or was refunded
+    reference TEXT,                      -- Client supplied key so a spend is only applied once
+    description TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_wallet_entries_user_currency ON wallet_entries(user_id, currency);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_wallet_entries_reference ON wallet_entries(user_id, currency, reference) WHERE reference IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_wallet_entries_purchase ON wallet_entries(subscription_id, type) WHERE subscription_id IS NOT NULL;
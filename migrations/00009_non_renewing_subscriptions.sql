@@ -0,0 +1,3 @@
+-- Start of the access period a non-renewing purchase grants. Repeat purchases
+-- stack, so this can be later than the purchase date.
+ALTER TABLE subscriptions ADD COLUMN period_start_date TIMESTAMP;
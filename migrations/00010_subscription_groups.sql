@@ -0,0 +1,27 @@
+-- Subscription groups. A user can only have one subscription per group, and
+-- moving between products in a group is an upgrade, downgrade or crossgrade.
+-- Like the App Store, level 1 is the highest tier.
+ALTER TABLE products ADD COLUMN subscription_group TEXT;
+ALTER TABLE products ADD COLUMN group_level INTEGER;
+
+-- A product change that takes effect at the next renewal, e.g. a downgrade
+ALTER TABLE subscriptions ADD COLUMN pending_product_id TEXT;
+-- The subscription this one replaced after a product change
+ALTER TABLE subscriptions ADD COLUMN previous_subscription_id TEXT;
+
+-- Subscription lifecycle events
+CREATE TABLE IF NOT EXISTS subscription_events (
+    id TEXT PRIMARY KEY,
+    subscription_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    type TEXT NOT NULL,                  -- 'product_change_scheduled', 'product_change', etc.
+    from_product_id TEXT,
+    to_product_id TEXT,
+    change_type TEXT,                    -- 'upgrade', 'downgrade' or 'crossgrade'
+    effective_date TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (subscription_id) REFERENCES subscriptions(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_subscription_events_subscription ON subscription_events(subscription_id);
+CREATE INDEX IF NOT EXISTS idx_subscription_events_user ON subscription_events(user_id);
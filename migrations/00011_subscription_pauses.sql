@@ -0,0 +1,6 @@
+-- When a paused subscription resumes, or will resume if a pause is scheduled
+ALTER TABLE subscriptions ADD COLUMN auto_resume_date TIMESTAMP;
+
+-- New price for price change events
+ALTER TABLE subscription_events ADD COLUMN price REAL;
+ALTER TABLE subscription_events ADD COLUMN currency TEXT;
@@ -0,0 +1,4 @@
+-- When a failed renewal was first seen. Cleared once a payment goes through.
+ALTER TABLE subscriptions ADD COLUMN billing_issue_detected_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_subscriptions_billing_issue ON subscriptions(billing_issue_detected_at);
@@ -0,0 +1,4 @@
+-- In-App Purchase key used to sign Apple promotional offers. The private key
+-- is the PEM contents of the .p8 file downloaded from App Store Connect.
+ALTER TABLE apps ADD COLUMN apple_offer_key_id TEXT;
+ALTER TABLE apps ADD COLUMN apple_offer_private_key TEXT;
@@ -0,0 +1,29 @@
+-- Issuer ID that goes with the app's In-App Purchase key when calling the
+-- App Store Server API, and whether to answer Apple's consumption requests
+ALTER TABLE apps ADD COLUMN apple_issuer_id TEXT;
+ALTER TABLE apps ADD COLUMN apple_consumption_opt_in BOOLEAN NOT NULL DEFAULT 0;
+
+-- Apple only accepts consumption data the customer agreed to share
+ALTER TABLE users ADD COLUMN consumption_consent BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN play_time_minutes INTEGER;
+
+-- Consumption requests from Apple and the data we sent back for each
+CREATE TABLE IF NOT EXISTS consumption_requests (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    notification_uuid TEXT NOT NULL UNIQUE,
+    environment TEXT NOT NULL,           -- 'Production' or 'Sandbox'
+    transaction_id TEXT NOT NULL,
+    user_id TEXT,
+    subscription_id TEXT,
+    status TEXT NOT NULL,                -- 'pending', 'sent', 'skipped' or 'failed'
+    request_json TEXT,                   -- Body sent to Apple
+    error TEXT,
+    deadline TIMESTAMP NOT NULL,         -- Apple ignores responses after this
+    sent_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (app_id) REFERENCES apps(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_consumption_requests_app_id ON consumption_requests(app_id);
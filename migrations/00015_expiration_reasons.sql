@@ -0,0 +1,3 @@
+-- Why a subscription stopped renewing: 'voluntary', 'billing_error',
+-- 'price_increase', 'product_not_for_sale' or 'other'
+ALTER TABLE subscriptions ADD COLUMN expiration_reason TEXT;
@@ -0,0 +1,8 @@
+-- 'purchased' for the buyer's own subscription, 'family_shared' for a family
+-- member's access to it through Apple Family Sharing
+ALTER TABLE subscriptions ADD COLUMN ownership_type TEXT NOT NULL DEFAULT 'purchased';
+
+-- The purchaser's subscription a family shared subscription follows
+ALTER TABLE subscriptions ADD COLUMN family_owner_subscription_id TEXT REFERENCES subscriptions(id);
+
+CREATE INDEX IF NOT EXISTS idx_subscriptions_family_owner ON subscriptions(family_owner_subscription_id);
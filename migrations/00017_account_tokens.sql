@@ -0,0 +1,35 @@
+-- Account identifiers apps attach to store purchases, mapped to our users:
+-- Apple's appAccountToken, and Google's obfuscated account and profile IDs
+CREATE TABLE IF NOT EXISTS account_tokens (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    store TEXT NOT NULL,                 -- 'apple' or 'google'
+    token TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(store, token)
+);
+
+CREATE INDEX IF NOT EXISTS idx_account_tokens_user_id ON account_tokens(user_id);
+
+-- Purchases we couldn't match to a user, kept with their notification so
+-- they can be processed once the user is known
+CREATE TABLE IF NOT EXISTS unattributed_purchases (
+    id TEXT PRIMARY KEY,
+    store TEXT NOT NULL,                 -- 'apple' or 'google'
+    account_token TEXT,                  -- Token on the purchase, if any
+    store_product_id TEXT NOT NULL,
+    transaction_id TEXT NOT NULL,
+    original_transaction_id TEXT,
+    notification_json TEXT NOT NULL,
+    status TEXT NOT NULL,                -- 'pending' or 'attributed'
+    user_id TEXT,                        -- Set when the purchase is attributed
+    attributed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL,
+    UNIQUE(store, transaction_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_unattributed_purchases_account_token ON unattributed_purchases(store, account_token);
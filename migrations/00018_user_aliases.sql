@@ -0,0 +1,12 @@
+-- Other app user IDs a user is known by, e.g. the anonymous ID they had
+-- before logging in. Looking up either ID finds the same user.
+CREATE TABLE IF NOT EXISTS user_aliases (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    alias TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(alias)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_aliases_user_id ON user_aliases(user_id);
@@ -0,0 +1,18 @@
+-- Key/value attributes set by the app, such as display name, locale or
+-- attribution campaign. Keys starting with `$` are reserved. A NULL value
+-- records that the attribute was unset, so older writes can't bring it back.
+CREATE TABLE IF NOT EXISTS user_attributes (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT,
+    updated_at TIMESTAMP NOT NULL,      -- When the client set the value; the latest write wins
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(user_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_attributes_key_value ON user_attributes(key, value);
+
+-- The subscriber's attributes when the event happened, as a JSON object
+ALTER TABLE subscription_events ADD COLUMN subscriber_attributes TEXT;
@@ -0,0 +1,18 @@
+-- Manual changes made through the API: who made them, to what and why.
+-- Rows aren't tied to users by a foreign key so they outlive deletions.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id TEXT PRIMARY KEY,
+    actor TEXT,                         -- API key ID of the caller, if known
+    action TEXT NOT NULL,               -- e.g. 'entitlement.granted'
+    target_type TEXT NOT NULL,          -- 'user', 'user_entitlement', 'subscription' or 'product'
+    target_id TEXT NOT NULL,
+    user_id TEXT,                       -- The user affected, if any
+    before_json TEXT,
+    after_json TEXT,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_target ON audit_log(target_type, target_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor);
+CREATE INDEX IF NOT EXISTS idx_audit_log_user_id ON audit_log(user_id);
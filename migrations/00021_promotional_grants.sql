@@ -0,0 +1,3 @@
+-- Why an entitlement was granted outside of a purchase, e.g. a support
+-- ticket or a promotion
+ALTER TABLE user_entitlements ADD COLUMN reason TEXT;
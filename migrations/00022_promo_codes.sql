@@ -0,0 +1,40 @@
+-- Batches of promo codes for a campaign. Redeeming a code grants the
+-- batch's entitlement for its duration as a promotional grant.
+CREATE TABLE IF NOT EXISTS promo_code_batches (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    entitlement_id TEXT NOT NULL,
+    duration TEXT NOT NULL,              -- 'daily', 'weekly', 'monthly', 'yearly' or 'lifetime'
+    max_redemptions INTEGER NOT NULL DEFAULT 1,  -- How many users can redeem each code
+    expires_at TIMESTAMP,                -- Codes can't be redeemed after this
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (entitlement_id) REFERENCES entitlements(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS promo_codes (
+    id TEXT PRIMARY KEY,
+    batch_id TEXT NOT NULL,
+    code TEXT NOT NULL UNIQUE,
+    redemption_count INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (batch_id) REFERENCES promo_code_batches(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_promo_codes_batch_id ON promo_codes(batch_id);
+
+-- Each user can redeem one code from a batch
+CREATE TABLE IF NOT EXISTS promo_code_redemptions (
+    id TEXT PRIMARY KEY,
+    batch_id TEXT NOT NULL,
+    code_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    user_entitlement_id TEXT,            -- The grant the redemption created or extended
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (batch_id) REFERENCES promo_code_batches(id) ON DELETE CASCADE,
+    FOREIGN KEY (code_id) REFERENCES promo_codes(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(batch_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_promo_code_redemptions_code_id ON promo_code_redemptions(code_id);
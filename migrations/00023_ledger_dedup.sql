@@ -0,0 +1,11 @@
+-- Stores redeliver notifications that weren't acknowledged, so a ledger
+-- entry can be recorded more than once. Keep the first of each and make
+-- later ones no-ops.
+DELETE FROM transactions
+WHERE rowid NOT IN (
+    SELECT MIN(rowid) FROM transactions
+    GROUP BY store, store_transaction_id, type
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_transactions_store_transaction
+    ON transactions(store, store_transaction_id, type);
@@ -0,0 +1,167 @@
+// Cohort retention: users are grouped by the period of their first
+// subscription purchase, and each cohort is followed period by period to
+// see how many of its users are still paying and how much revenue they bring.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::db::models::Transaction;
+
+use super::{
+    in_period, load_products, load_subscriptions, load_transactions, periods, start_of_day, subscription_facts,
+    Dimension, GroupKey, Interval, MetricsFilter, SubscriptionFacts,
+};
+
+#[derive(Debug, Serialize)]
+pub struct CohortPeriod {
+    pub period: usize,  // Periods since the cohort started, 0 is the cohort's own period
+    pub period_start: NaiveDate,
+    pub paying_users: i64,
+    pub retention: f64,
+    pub revenue: f64,
+    pub cumulative_revenue: f64,
+    pub proceeds: f64,
+    pub cumulative_proceeds: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CohortRow {
+    pub cohort_start: NaiveDate,
+    #[serde(flatten)]
+    pub group: GroupKey,
+    pub users: i64,
+    pub periods: Vec<CohortPeriod>,
+}
+
+// Build cohorts for users whose first subscription was purchased in the
+// inclusive date range, split by the requested dimensions of that first
+// subscription. Each cohort is followed up to the period containing `now`.
+pub async fn compute_cohorts(
+    start: NaiveDate,
+    end: NaiveDate,
+    interval: Interval,
+    split_by: &[Dimension],
+    now: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Vec<CohortRow>, sqlx::Error> {
+    let filter = MetricsFilter::default();
+    let products = load_products(pool).await?;
+    let subscriptions = load_subscriptions(&filter, now, pool).await?;
+    let transactions = load_transactions(&filter, now, pool).await?;
+
+    let facts: Vec<SubscriptionFacts> = subscription_facts(&subscriptions, &products, &transactions)
+        .into_iter()
+        .filter(|fact| fact.is_subscription_product())
+        .collect();
+
+    let mut facts_by_user: HashMap<&str, Vec<&SubscriptionFacts>> = HashMap::new();
+    for fact in &facts {
+        facts_by_user
+            .entry(fact.subscription.user_id.as_str())
+            .or_default()
+            .push(fact);
+    }
+
+    // Place every user in the cohort of their first subscription
+    let range_start = start_of_day(start);
+    let range_end = start_of_day(end.succ_opt().unwrap_or(end));
+    let mut cohorts: BTreeMap<(NaiveDate, GroupKey), Vec<&str>> = BTreeMap::new();
+
+    for (user_id, user_facts) in &facts_by_user {
+        let first = match user_facts.iter().min_by_key(|fact| fact.subscription.purchase_date) {
+            Some(first) => first,
+            None => continue,
+        };
+
+        let purchased_at = first.subscription.purchase_date;
+        if purchased_at < range_start || purchased_at >= range_end {
+            continue;
+        }
+
+        let cohort_start = interval.period_start(purchased_at.date_naive());
+        cohorts
+            .entry((cohort_start, first.group_key(split_by)))
+            .or_default()
+            .push(user_id);
+    }
+
+    let mut transactions_by_user: HashMap<&str, Vec<&Transaction>> = HashMap::new();
+    for transaction in &transactions {
+        transactions_by_user
+            .entry(transaction.user_id.as_str())
+            .or_default()
+            .push(transaction);
+    }
+
+    let mut rows = Vec::new();
+
+    for ((cohort_start, group), user_ids) in cohorts {
+        let users = user_ids.len() as i64;
+        let mut cumulative_revenue = 0.0;
+        let mut cumulative_proceeds = 0.0;
+        let mut cohort_periods = Vec::new();
+
+        for (index, (period_start, period_end)) in periods(cohort_start, now.date_naive(), interval)
+            .into_iter()
+            .enumerate()
+        {
+            let starts_at = start_of_day(period_start);
+            let ends_at = start_of_day(period_end);
+
+            let mut paying_users = 0;
+            let mut revenue = 0.0;
+            let mut proceeds = 0.0;
+
+            for user_id in &user_ids {
+                let is_paying = facts_by_user
+                    .get(user_id)
+                    .is_some_and(|user_facts| {
+                        user_facts
+                            .iter()
+                            .any(|fact| fact.is_paying_during(starts_at, ends_at))
+                    });
+
+                if is_paying {
+                    paying_users += 1;
+                }
+
+                // Refunds are negative, so these are net amounts
+                let user_transactions = transactions_by_user
+                    .get(user_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|transaction| in_period(transaction.transaction_date, starts_at, ends_at));
+
+                for transaction in user_transactions {
+                    revenue += transaction.amount_usd.unwrap_or(0.0);
+                    proceeds += transaction.proceeds_usd.unwrap_or(0.0);
+                }
+            }
+
+            cumulative_revenue += revenue;
+            cumulative_proceeds += proceeds;
+
+            cohort_periods.push(CohortPeriod {
+                period: index,
+                period_start,
+                paying_users,
+                retention: paying_users as f64 / users as f64,
+                revenue,
+                cumulative_revenue,
+                proceeds,
+                cumulative_proceeds,
+            });
+        }
+
+        rows.push(CohortRow {
+            cohort_start,
+            group,
+            users,
+            periods: cohort_periods,
+        });
+    }
+
+    Ok(rows)
+}
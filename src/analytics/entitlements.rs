@@ -0,0 +1,59 @@
+// Entitlement reach: how many users have ever had each entitlement and how
+// many have it now, from purchases and promotional grants alike. Promotional
+// grants never count toward revenue or MRR, which come from subscriptions and
+// the transaction ledger, but a user who only ever had a promo still counts
+// as having had the entitlement here after the promo ends.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct EntitlementStats {
+    pub entitlement_id: String,
+    pub name: String,
+    pub users_ever: i64,
+    pub active_users: i64,
+    pub promotional_users_ever: i64,
+    pub active_promotional_users: i64,
+}
+
+// Stats for every entitlement as of `now`. Grants revoked before they
+// started were never had.
+pub async fn compute_entitlement_stats(
+    now: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Vec<EntitlementStats>, sqlx::Error> {
+    let stats = sqlx::query_as::<_, EntitlementStats>(
+        r#"
+        WITH had AS (
+            SELECT
+                user_id,
+                entitlement_id,
+                subscription_id IS NULL AS is_promotional,
+                expires_at IS NULL OR expires_at > ? AS is_active
+            FROM user_entitlements
+            WHERE starts_at <= ?
+              AND (expires_at IS NULL OR expires_at > starts_at)
+        )
+        SELECT
+            e.id AS entitlement_id,
+            e.name,
+            COUNT(DISTINCT had.user_id) AS users_ever,
+            COUNT(DISTINCT CASE WHEN had.is_active THEN had.user_id END) AS active_users,
+            COUNT(DISTINCT CASE WHEN had.is_promotional THEN had.user_id END) AS promotional_users_ever,
+            COUNT(DISTINCT CASE WHEN had.is_promotional AND had.is_active THEN had.user_id END)
+                AS active_promotional_users
+        FROM entitlements e
+        LEFT JOIN had ON had.entitlement_id = e.id
+        GROUP BY e.id, e.name
+        ORDER BY e.name
+        "#,
+    )
+    .bind(now)
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(stats)
+}
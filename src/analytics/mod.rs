@@ -0,0 +1,618 @@
+// Subscription analytics computed from the subscriptions table and the
+// transaction ledger: active subscriptions and trials, MRR, churn, refunds
+// and trial conversion, bucketed by day, week or month. Cohort retention
+// lives in `cohorts`, and entitlement reach in `entitlements`.
+
+pub mod cohorts;
+pub mod entitlements;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::QueryBuilder;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::db::models::{
+    ExchangeRate, ExchangeRates, ExpirationReason, Product, ProductType, Subscription, SubscriptionStatus, Transaction,
+    TransactionType,
+};
+
+// Average month length used to normalize subscription prices to MRR
+const DAYS_PER_MONTH: f64 = 365.25 / 12.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    // First day of the period containing the date (weeks start on Monday)
+    pub fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Interval::Day => date,
+            Interval::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Interval::Month => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    // First day of the period after the one starting on the date
+    pub fn next_period_start(&self, period_start: NaiveDate) -> NaiveDate {
+        match self {
+            Interval::Day => period_start + Duration::days(1),
+            Interval::Week => period_start + Duration::days(7),
+            Interval::Month => {
+                let (year, month) = if period_start.month() == 12 {
+                    (period_start.year() + 1, 1)
+                } else {
+                    (period_start.year(), period_start.month() + 1)
+                };
+                NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(period_start)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    Product,
+    Store,
+    Country,
+    IntroOffer,
+}
+
+impl FromStr for Dimension {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "product" => Ok(Dimension::Product),
+            "store" => Ok(Dimension::Store),
+            "country" => Ok(Dimension::Country),
+            "intro_offer" => Ok(Dimension::IntroOffer),
+            other => Err(format!("Unknown dimension: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MetricsFilter {
+    pub product_id: Option<String>,
+    pub store: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricsRow {
+    pub period_start: NaiveDate,
+    pub period_end: NaiveDate,  // Exclusive
+    #[serde(flatten)]
+    pub group: GroupKey,
+    pub active_subscriptions: i64,
+    pub active_trials: i64,
+    pub new_subscriptions: i64,
+    pub new_trials: i64,
+    pub mrr: f64,
+    pub churned_subscriptions: i64,
+    pub voluntary_churned_subscriptions: i64,  // Cancelled by the customer
+    pub involuntary_churned_subscriptions: i64,  // Payment failed
+    pub churn_rate: f64,
+    pub refunds: i64,
+    pub refunded_amount: f64,
+    pub trial_conversions: i64,
+    pub trial_conversion_rate: f64,
+    pub revenue: f64,
+    pub proceeds: f64,  // Net of refunds
+}
+
+// Split the inclusive date range into [start, end) periods
+pub fn periods(start: NaiveDate, end: NaiveDate, interval: Interval) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut periods = Vec::new();
+    let mut period_start = interval.period_start(start);
+
+    while period_start <= end {
+        let period_end = interval.next_period_start(period_start);
+        periods.push((period_start, period_end));
+        period_start = period_end;
+    }
+
+    periods
+}
+
+pub fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_time(NaiveTime::MIN).and_utc()
+}
+
+// A subscription along with what the ledger tells us about it
+struct SubscriptionFacts<'a> {
+    subscription: &'a Subscription,
+    product: Option<&'a Product>,
+    // When a trial first renewed into a paid period
+    converted_at: Option<DateTime<Utc>>,
+}
+
+impl SubscriptionFacts<'_> {
+    fn is_subscription_product(&self) -> bool {
+        self.product
+            .map(|product| product.type_ == ProductType::Subscription.to_string())
+            .unwrap_or(true)
+    }
+
+    // When the subscription was active as a trial, as a [from, until) window
+    fn trial_window(&self) -> Option<(DateTime<Utc>, Option<DateTime<Utc>>)> {
+        let subscription = self.subscription;
+        if !subscription.is_trial || subscription.status == SubscriptionStatus::Refunded.to_string() {
+            return None;
+        }
+
+        let until = match (subscription.expires_date, self.converted_at) {
+            (Some(expires), Some(converted)) => Some(expires.min(converted)),
+            (expires, converted) => expires.or(converted),
+        };
+
+        Some((subscription.purchase_date, until))
+    }
+
+    // When the subscription was active and paid for, as a [from, until) window
+    fn paid_window(&self) -> Option<(DateTime<Utc>, Option<DateTime<Utc>>)> {
+        let subscription = self.subscription;
+        if subscription.status == SubscriptionStatus::Refunded.to_string() {
+            return None;
+        }
+
+        let from = if subscription.is_trial {
+            self.converted_at?.max(subscription.purchase_date)
+        } else {
+            subscription.purchase_date
+        };
+
+        Some((from, subscription.expires_date))
+    }
+
+    // Whether the subscription was paid for at any point during [start, end)
+    fn is_paying_during(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+        let subscription = self.subscription;
+        let paid_from = if subscription.is_trial {
+            match self.converted_at {
+                Some(converted_at) => converted_at,
+                None => return false,
+            }
+        } else {
+            subscription.purchase_date
+        };
+
+        subscription.status != SubscriptionStatus::Refunded.to_string()
+            && paid_from < end
+            && subscription.expires_date.is_none_or(|expires| expires > start)
+    }
+
+    // Paid price in USD normalized to a month using the product's duration.
+    // Prices in currencies without a rate fall back to the product's USD price.
+    fn monthly_revenue(&self, rates: &ExchangeRates) -> f64 {
+        let product = match self.product {
+            Some(product) => product,
+            None => return 0.0,
+        };
+
+        let price = self
+            .subscription
+            .price_paid
+            .and_then(|price| rates.to_usd(price, self.subscription.currency.as_deref()))
+            .or(product.price_usd);
+
+        match (price, product.duration_days) {
+            (Some(price), Some(days)) if days > 0 => price * DAYS_PER_MONTH / days as f64,
+            _ => 0.0,
+        }
+    }
+
+    // When the trial period ended, either by converting or by expiring
+    fn trial_ended_at(&self) -> Option<DateTime<Utc>> {
+        if !self.subscription.is_trial {
+            return None;
+        }
+
+        self.converted_at.or(self.subscription.expires_date)
+    }
+
+    // When a paid subscription lapsed without renewing
+    fn churned_at(&self) -> Option<DateTime<Utc>> {
+        let subscription = self.subscription;
+        let is_paid = !subscription.is_trial || self.converted_at.is_some();
+        let has_lapsed = subscription.status == SubscriptionStatus::Expired.to_string()
+            || subscription.status == SubscriptionStatus::Cancelled.to_string();
+
+        if is_paid && has_lapsed {
+            subscription.expires_date
+        } else {
+            None
+        }
+    }
+
+    fn expiration_reason(&self) -> Option<ExpirationReason> {
+        self.subscription
+            .expiration_reason
+            .as_deref()
+            .and_then(ExpirationReason::parse)
+    }
+
+    fn group_key(&self, group_by: &[Dimension]) -> GroupKey {
+        GroupKey::for_subscription(group_by, self.subscription)
+    }
+}
+
+// The values of the requested dimensions for one report row.
+// Dimensions that weren't requested are left empty.
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GroupKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intro_offer: Option<bool>,
+}
+
+impl GroupKey {
+    fn for_subscription(group_by: &[Dimension], subscription: &Subscription) -> Self {
+        Self {
+            product_id: group_by
+                .contains(&Dimension::Product)
+                .then(|| subscription.product_id.clone()),
+            store: group_by
+                .contains(&Dimension::Store)
+                .then(|| subscription.store.clone()),
+            // Subscriptions recorded before the storefront was tracked have no country
+            country_code: group_by.contains(&Dimension::Country).then(|| {
+                subscription
+                    .country_code
+                    .clone()
+                    .unwrap_or_else(|| "unknown".to_string())
+            }),
+            // Free trials and discounted intro prices both count as intro offers
+            intro_offer: group_by
+                .contains(&Dimension::IntroOffer)
+                .then_some(subscription.is_trial || subscription.is_intro_offer),
+        }
+    }
+}
+
+fn in_period(date: DateTime<Utc>, start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+    date >= start && date < end
+}
+
+// The period containing the date, given the ascending period starts
+fn period_index(period_starts: &[DateTime<Utc>], range_end: DateTime<Utc>, date: DateTime<Utc>) -> Option<usize> {
+    if period_starts.first().is_none_or(|first| date < *first) || date >= range_end {
+        return None;
+    }
+
+    Some(period_starts.partition_point(|start| *start <= date) - 1)
+}
+
+// The run of ascending times that fall inside the [from, until) window
+fn window_span(times: &[DateTime<Utc>], from: DateTime<Utc>, until: Option<DateTime<Utc>>) -> Range<usize> {
+    let start = times.partition_point(|time| *time < from);
+    let end = match until {
+        Some(until) => times.partition_point(|time| *time < until),
+        None => times.len(),
+    };
+
+    start..end.max(start)
+}
+
+async fn load_products(pool: &SqlitePool) -> Result<HashMap<String, Product>, sqlx::Error> {
+    let products = Product::list_all(pool)
+        .await?
+        .into_iter()
+        .map(|product| (product.id.clone(), product))
+        .collect();
+
+    Ok(products)
+}
+
+// Attach products and trial conversion dates to the subscriptions.
+// The first renewal of a trial is its conversion to paid.
+fn subscription_facts<'a>(
+    subscriptions: &'a [Subscription],
+    products: &'a HashMap<String, Product>,
+    transactions: &[Transaction],
+) -> Vec<SubscriptionFacts<'a>> {
+    let mut first_renewals: HashMap<&str, DateTime<Utc>> = HashMap::new();
+    for transaction in transactions {
+        if transaction.type_ == TransactionType::Renewal.to_string() {
+            first_renewals
+                .entry(transaction.subscription_id.as_str())
+                .or_insert(transaction.transaction_date);
+        }
+    }
+
+    subscriptions
+        .iter()
+        .map(|subscription| SubscriptionFacts {
+            subscription,
+            product: products.get(&subscription.product_id),
+            converted_at: if subscription.is_trial {
+                first_renewals.get(subscription.id.as_str()).copied()
+            } else {
+                None
+            },
+        })
+        .collect()
+}
+
+async fn load_subscriptions(
+    filter: &MetricsFilter,
+    before: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Vec<Subscription>, sqlx::Error> {
+    // Family members' access isn't a sale, so only purchasers' subscriptions count
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT * FROM subscriptions WHERE ownership_type = 'purchased' AND purchase_date < ",
+    );
+    query.push_bind(before);
+
+    if let Some(product_id) = &filter.product_id {
+        query.push(" AND product_id = ").push_bind(product_id.clone());
+    }
+
+    if let Some(store) = &filter.store {
+        query.push(" AND store = ").push_bind(store.clone());
+    }
+
+    query.build_query_as::<Subscription>().fetch_all(pool).await
+}
+
+async fn load_transactions(
+    filter: &MetricsFilter,
+    before: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Vec<Transaction>, sqlx::Error> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT t.* FROM transactions t JOIN subscriptions s ON s.id = t.subscription_id WHERE t.transaction_date < ",
+    );
+    query.push_bind(before);
+
+    if let Some(product_id) = &filter.product_id {
+        query.push(" AND s.product_id = ").push_bind(product_id.clone());
+    }
+
+    if let Some(store) = &filter.store {
+        query.push(" AND t.store = ").push_bind(store.clone());
+    }
+
+    query.push(" ORDER BY t.transaction_date");
+
+    query.build_query_as::<Transaction>().fetch_all(pool).await
+}
+
+// Compute metrics for each period in the inclusive date range, split by the
+// requested dimensions. Point-in-time metrics (active counts, MRR) are taken
+// at the end of each period, or at `now` for the period in progress.
+pub async fn compute_metrics(
+    start: NaiveDate,
+    end: NaiveDate,
+    interval: Interval,
+    group_by: &[Dimension],
+    filter: &MetricsFilter,
+    now: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Vec<MetricsRow>, sqlx::Error> {
+    let periods = periods(start, end, interval);
+    let range_end = match periods.last() {
+        Some((_, period_end)) => start_of_day(*period_end),
+        None => return Ok(Vec::new()),
+    };
+
+    let products = load_products(pool).await?;
+    let rates = ExchangeRate::load_all(pool).await?;
+    let subscriptions = load_subscriptions(filter, range_end, pool).await?;
+    let transactions = load_transactions(filter, range_end, pool).await?;
+    let facts = subscription_facts(&subscriptions, &products, &transactions);
+
+    // Every period gets a row for every group seen in the data
+    let mut keys: BTreeSet<GroupKey> = facts.iter().map(|fact| fact.group_key(group_by)).collect();
+    if keys.is_empty() {
+        keys.insert(GroupKey::default());
+    }
+    let keys: Vec<GroupKey> = keys.into_iter().collect();
+    let key_indexes: HashMap<&GroupKey, usize> = keys.iter().enumerate().map(|(index, key)| (key, index)).collect();
+
+    let fact_keys: Vec<usize> = facts
+        .iter()
+        .map(|fact| key_indexes[&fact.group_key(group_by)])
+        .collect();
+    let subscription_keys: HashMap<&str, usize> = facts
+        .iter()
+        .zip(&fact_keys)
+        .map(|(fact, key_index)| (fact.subscription.id.as_str(), *key_index))
+        .collect();
+
+    // Both lists are in ascending order, so a point in time maps to its
+    // period and a time window to a run of periods by binary search
+    let period_starts: Vec<DateTime<Utc>> = periods.iter().map(|(period_start, _)| start_of_day(*period_start)).collect();
+    let snapshots: Vec<DateTime<Utc>> = periods
+        .iter()
+        .map(|(_, period_end)| start_of_day(*period_end).min(now))
+        .collect();
+
+    let mut rows: Vec<MetricsRow> = periods
+        .iter()
+        .flat_map(|(period_start, period_end)| {
+            keys.iter().map(|key| MetricsRow {
+                period_start: *period_start,
+                period_end: *period_end,
+                group: key.clone(),
+                active_subscriptions: 0,
+                active_trials: 0,
+                new_subscriptions: 0,
+                new_trials: 0,
+                mrr: 0.0,
+                churned_subscriptions: 0,
+                voluntary_churned_subscriptions: 0,
+                involuntary_churned_subscriptions: 0,
+                churn_rate: 0.0,
+                refunds: 0,
+                refunded_amount: 0.0,
+                trial_conversions: 0,
+                trial_conversion_rate: 0.0,
+                revenue: 0.0,
+                proceeds: 0.0,
+            })
+        })
+        .collect();
+    let row_index = |period: usize, key: usize| period * keys.len() + key;
+
+    // Point-in-time counts are spread over runs of periods, so they're kept
+    // as differences per row and summed across periods at the end
+    let mut active_subscriptions = vec![0i64; rows.len() + keys.len()];
+    let mut active_trials = vec![0i64; rows.len() + keys.len()];
+    let mut mrr = vec![0.0f64; rows.len() + keys.len()];
+    let mut active_at_start = vec![0i64; rows.len() + keys.len()];
+    let mut trials_ended = vec![0i64; rows.len()];
+
+    for (fact, &key) in facts.iter().zip(&fact_keys) {
+        if !fact.is_subscription_product() {
+            continue;
+        }
+
+        if let Some((from, until)) = fact.trial_window() {
+            let snapshot_span = window_span(&snapshots, from, until);
+            active_trials[row_index(snapshot_span.start, key)] += 1;
+            active_trials[row_index(snapshot_span.end, key)] -= 1;
+        }
+
+        if let Some((from, until)) = fact.paid_window() {
+            let monthly_revenue = fact.monthly_revenue(&rates);
+
+            let snapshot_span = window_span(&snapshots, from, until);
+            active_subscriptions[row_index(snapshot_span.start, key)] += 1;
+            active_subscriptions[row_index(snapshot_span.end, key)] -= 1;
+            mrr[row_index(snapshot_span.start, key)] += monthly_revenue;
+            mrr[row_index(snapshot_span.end, key)] -= monthly_revenue;
+
+            let start_span = window_span(&period_starts, from, until);
+            active_at_start[row_index(start_span.start, key)] += 1;
+            active_at_start[row_index(start_span.end, key)] -= 1;
+        }
+
+        if let Some(period) = period_index(&period_starts, range_end, fact.subscription.purchase_date) {
+            let row = &mut rows[row_index(period, key)];
+            if fact.subscription.is_trial {
+                row.new_trials += 1;
+            } else {
+                row.new_subscriptions += 1;
+            }
+        }
+
+        if let Some(churned_at) = fact.churned_at().filter(|churned_at| *churned_at <= now) {
+            if let Some(period) = period_index(&period_starts, range_end, churned_at) {
+                let row = &mut rows[row_index(period, key)];
+                row.churned_subscriptions += 1;
+
+                match fact.expiration_reason() {
+                    Some(reason) if reason.is_voluntary() => row.voluntary_churned_subscriptions += 1,
+                    Some(reason) if reason.is_involuntary() => row.involuntary_churned_subscriptions += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(ended_at) = fact.trial_ended_at().filter(|ended_at| *ended_at <= now) {
+            if let Some(period) = period_index(&period_starts, range_end, ended_at) {
+                trials_ended[row_index(period, key)] += 1;
+
+                if fact.converted_at.is_some() {
+                    rows[row_index(period, key)].trial_conversions += 1;
+                }
+            }
+        }
+    }
+
+    for transaction in &transactions {
+        let Some(&key) = subscription_keys.get(transaction.subscription_id.as_str()) else {
+            continue;
+        };
+        let Some(period) = period_index(&period_starts, range_end, transaction.transaction_date) else {
+            continue;
+        };
+
+        let row = &mut rows[row_index(period, key)];
+        let amount = transaction.amount_usd.unwrap_or(0.0);
+        row.proceeds += transaction.proceeds_usd.unwrap_or(0.0);
+
+        if transaction.type_ == TransactionType::Refund.to_string() {
+            row.refunds += 1;
+            row.refunded_amount += amount.abs();
+        } else {
+            row.revenue += amount;
+        }
+    }
+
+    for key in 0..keys.len() {
+        let mut running = (0i64, 0i64, 0.0f64, 0i64);
+
+        for period in 0..periods.len() {
+            let index = row_index(period, key);
+            running.0 += active_subscriptions[index];
+            running.1 += active_trials[index];
+            running.2 += mrr[index];
+            running.3 += active_at_start[index];
+
+            let row = &mut rows[index];
+            row.active_subscriptions = running.0;
+            row.active_trials = running.1;
+            // Differences can leave rounding residue once every subscription has ended
+            row.mrr = if running.0 > 0 { running.2 } else { 0.0 };
+
+            if running.3 > 0 {
+                row.churn_rate = row.churned_subscriptions as f64 / running.3 as f64;
+            }
+
+            if trials_ended[index] > 0 {
+                row.trial_conversion_rate = row.trial_conversions as f64 / trials_ended[index] as f64;
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> DateTime<Utc> {
+        start_of_day(NaiveDate::from_ymd_opt(2024, 1, day).unwrap())
+    }
+
+    #[test]
+    fn period_index_finds_the_containing_period() {
+        let starts = [day(1), day(8), day(15)];
+
+        assert_eq!(period_index(&starts, day(22), day(1)), Some(0));
+        assert_eq!(period_index(&starts, day(22), day(7) + Duration::hours(23)), Some(0));
+        assert_eq!(period_index(&starts, day(22), day(8)), Some(1));
+        assert_eq!(period_index(&starts, day(22), day(21)), Some(2));
+        assert_eq!(period_index(&starts, day(22), day(22)), None);
+        assert_eq!(period_index(&starts, day(22), day(1) - Duration::seconds(1)), None);
+        assert_eq!(period_index(&[], day(22), day(1)), None);
+    }
+
+    #[test]
+    fn window_span_covers_times_inside_the_window() {
+        let times = [day(2), day(3), day(4), day(5)];
+
+        assert_eq!(window_span(&times, day(3), Some(day(5))), 1..3);
+        assert_eq!(window_span(&times, day(1), None), 0..4);
+        assert_eq!(window_span(&times, day(6), None), 4..4);
+        assert_eq!(window_span(&times, day(3), Some(day(3))), 1..1);
+        // A window that ends before it starts covers nothing
+        assert_eq!(window_span(&times, day(4), Some(day(2))), 2..2);
+    }
+}
@@ -0,0 +1,245 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::{AccountToken, UnattributedPurchase, User};
+use crate::error::{AppError, Result};
+use crate::webhooks;
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterAccountTokenRequest {
+    pub store: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountTokenResponse {
+    pub id: String,
+    pub user_id: String,
+    pub store: String,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountTokensResponse {
+    pub account_tokens: Vec<AccountTokenResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterAccountTokenResponse {
+    pub account_token: AccountTokenResponse,
+    pub attributed_purchases: Vec<UnattributedPurchaseResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnattributedPurchasesQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+    pub starting_after: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttributePurchaseRequest {
+    pub user_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnattributedPurchaseResponse {
+    pub id: String,
+    pub store: String,
+    pub account_token: Option<String>,
+    pub store_product_id: String,
+    pub transaction_id: String,
+    pub original_transaction_id: Option<String>,
+    pub status: String,
+    pub user_id: Option<String>,
+    pub attributed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnattributedPurchasesResponse {
+    pub purchases: Vec<UnattributedPurchaseResponse>,
+    pub has_more: bool,
+}
+
+impl From<AccountToken> for AccountTokenResponse {
+    fn from(account_token: AccountToken) -> Self {
+        Self {
+            id: account_token.id,
+            user_id: account_token.user_id,
+            store: account_token.store,
+            token: account_token.token,
+            created_at: account_token.created_at,
+        }
+    }
+}
+
+impl From<UnattributedPurchase> for UnattributedPurchaseResponse {
+    fn from(purchase: UnattributedPurchase) -> Self {
+        Self {
+            id: purchase.id,
+            store: purchase.store,
+            account_token: purchase.account_token,
+            store_product_id: purchase.store_product_id,
+            transaction_id: purchase.transaction_id,
+            original_transaction_id: purchase.original_transaction_id,
+            status: purchase.status,
+            user_id: purchase.user_id,
+            attributed_at: purchase.attributed_at,
+            created_at: purchase.created_at,
+        }
+    }
+}
+
+// Get the account tokens registered for a user
+pub async fn get_user_account_tokens(
+    Path(user_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<AccountTokensResponse>> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    let limit = page_limit(page.limit)?;
+    check_cursor("account_tokens", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra account token to tell whether there's another page
+    let account_tokens = AccountToken::list_by_user(
+        &user.id,
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (account_tokens, has_more) = split_page(account_tokens, limit);
+
+    Ok(Json(AccountTokensResponse {
+        account_tokens: account_tokens.into_iter().map(AccountTokenResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Register the token the app attaches to a user's purchases: the
+// appAccountToken for Apple, or the obfuscated account or profile ID for
+// Google. Purchases that arrived with the token before it was registered are
+// processed for the user.
+pub async fn register_account_token(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<RegisterAccountTokenRequest>,
+) -> Result<(StatusCode, Json<RegisterAccountTokenResponse>)> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    let token = match request.store.as_str() {
+        "apple" => AccountToken::normalize("apple", &request.token)
+            .ok_or_else(|| AppError::ValidationError("Apple app account tokens must be UUIDs".to_string()))?,
+        "google" => AccountToken::normalize("google", &request.token).ok_or_else(|| {
+            AppError::ValidationError("Google account tokens must be 1 to 64 characters".to_string())
+        })?,
+        store => return Err(AppError::ValidationError(format!("Unknown store: {}", store))),
+    };
+
+    // Registering the same token again is fine, but a token can only map to one user
+    let (status, account_token) = match AccountToken::find_by_token(&request.store, &token, &pool).await? {
+        Some(existing) if existing.user_id == user.id => (StatusCode::OK, existing),
+        Some(_) => {
+            return Err(AppError::ValidationError(format!(
+                "Token is already registered to another user: {}",
+                token
+            )))
+        }
+        None => {
+            let account_token = AccountToken::new(user.id.clone(), request.store.clone(), token.clone());
+            account_token.create(&pool).await?;
+            (StatusCode::CREATED, account_token)
+        }
+    };
+
+    let mut attributed_purchases = Vec::new();
+    for mut purchase in UnattributedPurchase::list_pending_by_token(&request.store, &token, &pool).await? {
+        // Leave purchases that can't be processed yet parked, e.g. when
+        // their product hasn't been set up
+        match webhooks::attribute_purchase(&mut purchase, &user.id, &pool).await {
+            Ok(()) => attributed_purchases.push(UnattributedPurchaseResponse::from(purchase)),
+            Err(e) => tracing::warn!("Failed to attribute purchase {}: {}", purchase.id, e),
+        }
+    }
+
+    Ok((
+        status,
+        Json(RegisterAccountTokenResponse {
+            account_token: account_token.into(),
+            attributed_purchases,
+        }),
+    ))
+}
+
+// Remove a token, e.g. when the app signs the user out. Purchases already
+// attributed to the user stay theirs.
+pub async fn delete_account_token(
+    Path(token_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let account_token = AccountToken::find_by_id(&token_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Account token not found: {}", token_id)))?;
+
+    account_token.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Get purchases that couldn't be matched to a user, optionally by status
+pub async fn get_unattributed_purchases(
+    Query(query): Query<UnattributedPurchasesQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<UnattributedPurchasesResponse>> {
+    let limit = page_limit(query.limit)?;
+    check_cursor("unattributed_purchases", query.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra purchase to tell whether there's another page
+    let purchases = UnattributedPurchase::list(
+        query.status.as_deref(),
+        query.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (purchases, has_more) = split_page(purchases, limit);
+
+    Ok(Json(UnattributedPurchasesResponse {
+        purchases: purchases.into_iter().map(UnattributedPurchaseResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Assign a parked purchase to a user, e.g. one that arrived without a token
+pub async fn attribute_unattributed_purchase(
+    Path(purchase_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<AttributePurchaseRequest>,
+) -> Result<Json<UnattributedPurchaseResponse>> {
+    let mut purchase = UnattributedPurchase::find_by_id(&purchase_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Unattributed purchase not found: {}", purchase_id)))?;
+
+    let user = User::find_by_id(&request.user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", request.user_id)))?;
+
+    webhooks::attribute_purchase(&mut purchase, &user.id, &pool).await?;
+
+    Ok(Json(purchase.into()))
+}
@@ -0,0 +1,226 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::analytics::{
+    self, cohorts::CohortRow, entitlements::EntitlementStats, Dimension, Interval, MetricsFilter, MetricsRow,
+};
+use crate::error::{AppError, Result};
+use crate::utils::csv;
+
+// Upper bound on the number of periods in one report
+const MAX_PERIODS: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct MetricsQuery {
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+    pub interval: Option<Interval>,
+    pub group_by: Option<String>,  // Comma separated: 'product', 'store', 'country', 'intro_offer'
+    pub product_id: Option<String>,
+    pub store: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricsResponse {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub interval: Interval,
+    pub metrics: Vec<MetricsRow>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CohortsQuery {
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+    pub interval: Option<Interval>,
+    pub split_by: Option<String>,  // Same dimensions as group_by
+}
+
+#[derive(Debug, Serialize)]
+pub struct CohortsResponse {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub interval: Interval,
+    pub cohorts: Vec<CohortRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntitlementStatsResponse {
+    pub entitlements: Vec<EntitlementStats>,
+}
+
+// Parse a comma separated list of dimensions to group by
+pub fn parse_group_by<T: std::str::FromStr<Err = String>>(group_by: Option<&str>) -> Result<Vec<T>> {
+    group_by
+        .unwrap_or_default()
+        .split(',')
+        .filter(|dimension| !dimension.trim().is_empty())
+        .map(|dimension| dimension.parse::<T>().map_err(AppError::ValidationError))
+        .collect()
+}
+
+// Resolve the inclusive date range, defaulting to the last 30 days
+pub fn date_range(start: Option<NaiveDate>, end: Option<NaiveDate>) -> Result<(NaiveDate, NaiveDate)> {
+    let end = end.unwrap_or_else(|| Utc::now().date_naive());
+    let start = start.unwrap_or(end - Duration::days(29));
+
+    if start > end {
+        return Err(AppError::ValidationError(
+            "start must be on or before end".to_string(),
+        ));
+    }
+
+    Ok((start, end))
+}
+
+// Get subscription metrics over a date range
+pub async fn get_metrics(
+    Query(query): Query<MetricsQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<MetricsResponse>> {
+    let (start, end) = date_range(query.start, query.end)?;
+    let interval = query.interval.unwrap_or(Interval::Day);
+    let group_by: Vec<Dimension> = parse_group_by(query.group_by.as_deref())?;
+
+    if analytics::periods(start, end, interval).len() > MAX_PERIODS {
+        return Err(AppError::ValidationError(format!(
+            "Date range covers more than {} periods, use a larger interval",
+            MAX_PERIODS
+        )));
+    }
+
+    let filter = MetricsFilter {
+        product_id: query.product_id,
+        store: query.store,
+    };
+
+    let metrics = analytics::compute_metrics(
+        start,
+        end,
+        interval,
+        &group_by,
+        &filter,
+        Utc::now(),
+        &pool,
+    )
+    .await?;
+
+    Ok(Json(MetricsResponse {
+        start,
+        end,
+        interval,
+        metrics,
+    }))
+}
+
+async fn load_cohorts(query: CohortsQuery, pool: &SqlitePool) -> Result<CohortsResponse> {
+    // Cohorts default to the last 12 months
+    let end = query.end.unwrap_or_else(|| Utc::now().date_naive());
+    let default_start = end
+        .with_day(1)
+        .and_then(|date| date.checked_sub_months(chrono::Months::new(11)))
+        .unwrap_or(end);
+    let (start, end) = date_range(Some(query.start.unwrap_or(default_start)), Some(end))?;
+    let interval = query.interval.unwrap_or(Interval::Month);
+    let split_by: Vec<Dimension> = parse_group_by(query.split_by.as_deref())?;
+
+    if analytics::periods(start, Utc::now().date_naive(), interval).len() > MAX_PERIODS {
+        return Err(AppError::ValidationError(format!(
+            "Cohorts cover more than {} periods, use a larger interval",
+            MAX_PERIODS
+        )));
+    }
+
+    let cohorts = analytics::cohorts::compute_cohorts(start, end, interval, &split_by, Utc::now(), pool).await?;
+
+    Ok(CohortsResponse {
+        start,
+        end,
+        interval,
+        cohorts,
+    })
+}
+
+// Get retention and revenue of users grouped by their first purchase
+pub async fn get_cohorts(
+    Query(query): Query<CohortsQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<CohortsResponse>> {
+    Ok(Json(load_cohorts(query, &pool).await?))
+}
+
+// Export cohorts as CSV, one line per cohort and period
+pub async fn export_cohorts_csv(
+    Query(query): Query<CohortsQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse> {
+    let response = load_cohorts(query, &pool).await?;
+
+    let mut output = String::new();
+    csv::write_row(
+        &mut output,
+        &[
+            "cohort_start",
+            "product_id",
+            "store",
+            "country_code",
+            "intro_offer",
+            "users",
+            "period",
+            "period_start",
+            "paying_users",
+            "retention",
+            "revenue",
+            "cumulative_revenue",
+            "proceeds",
+            "cumulative_proceeds",
+        ],
+    );
+
+    for cohort in &response.cohorts {
+        for period in &cohort.periods {
+            csv::write_row(
+                &mut output,
+                &[
+                    cohort.cohort_start.to_string(),
+                    cohort.group.product_id.clone().unwrap_or_default(),
+                    cohort.group.store.clone().unwrap_or_default(),
+                    cohort.group.country_code.clone().unwrap_or_default(),
+                    cohort.group.intro_offer.map(|value| value.to_string()).unwrap_or_default(),
+                    cohort.users.to_string(),
+                    period.period.to_string(),
+                    period.period_start.to_string(),
+                    period.paying_users.to_string(),
+                    format!("{:.4}", period.retention),
+                    format!("{:.2}", period.revenue),
+                    format!("{:.2}", period.cumulative_revenue),
+                    format!("{:.2}", period.proceeds),
+                    format!("{:.2}", period.cumulative_proceeds),
+                ],
+            );
+        }
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"cohorts.csv\""),
+        ],
+        output,
+    ))
+}
+
+// Get how many users have ever had and currently have each entitlement,
+// including through promotional grants
+pub async fn get_entitlement_stats(State(pool): State<SqlitePool>) -> Result<Json<EntitlementStatsResponse>> {
+    let entitlements = analytics::entitlements::compute_entitlement_stats(Utc::now(), &pool).await?;
+
+    Ok(Json(EntitlementStatsResponse { entitlements }))
+}
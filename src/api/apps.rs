@@ -0,0 +1,240 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use uuid::Uuid;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::App;
+use crate::error::{AppError, Result};
+use crate::utils::offer_signature::{self, OfferSignatureParams};
+
+#[derive(Debug, Serialize)]
+pub struct AppResponse {
+    pub id: String,
+    pub name: String,
+    pub apple_bundle_id: Option<String>,
+    pub google_package_name: Option<String>,
+    pub apple_offer_key_id: Option<String>,
+    pub has_apple_offer_key: bool,
+    pub apple_issuer_id: Option<String>,
+    pub apple_consumption_opt_in: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AppsResponse {
+    pub apps: Vec<AppResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAppRequest {
+    pub name: String,
+    pub apple_bundle_id: Option<String>,
+    pub google_package_name: Option<String>,
+    pub apple_offer_key_id: Option<String>,
+    pub apple_offer_private_key: Option<String>,
+    pub apple_issuer_id: Option<String>,
+    pub apple_consumption_opt_in: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAppRequest {
+    pub name: Option<String>,
+    pub apple_bundle_id: Option<String>,
+    pub google_package_name: Option<String>,
+    pub apple_offer_key_id: Option<String>,
+    pub apple_offer_private_key: Option<String>,
+    pub apple_issuer_id: Option<String>,
+    pub apple_consumption_opt_in: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OfferSignatureRequest {
+    pub product_identifier: String,
+    pub offer_identifier: String,
+    #[serde(default)]
+    pub application_username: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OfferSignatureResponse {
+    pub key_identifier: String,
+    pub nonce: String,
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+impl From<App> for AppResponse {
+    fn from(app: App) -> Self {
+        Self {
+            id: app.id,
+            name: app.name,
+            apple_bundle_id: app.apple_bundle_id,
+            google_package_name: app.google_package_name,
+            apple_offer_key_id: app.apple_offer_key_id,
+            has_apple_offer_key: app.apple_offer_private_key.is_some(),
+            apple_issuer_id: app.apple_issuer_id,
+            apple_consumption_opt_in: app.apple_consumption_opt_in,
+        }
+    }
+}
+
+// Get all apps
+pub async fn get_apps(
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<AppsResponse>> {
+    let limit = page_limit(page.limit)?;
+    check_cursor("apps", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra app to tell whether there's another page
+    let apps = App::list(page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (apps, has_more) = split_page(apps, limit);
+
+    Ok(Json(AppsResponse {
+        apps: apps.into_iter().map(AppResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Get a specific app
+pub async fn get_app(
+    Path(app_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<AppResponse>> {
+    let app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    Ok(Json(app.into()))
+}
+
+// Create a new app
+pub async fn create_app(
+    State(pool): State<SqlitePool>,
+    Json(request): Json<CreateAppRequest>,
+) -> Result<(StatusCode, Json<AppResponse>)> {
+    let mut app = App::new(request.name, request.apple_bundle_id, request.google_package_name);
+    app.apple_offer_key_id = request.apple_offer_key_id;
+    app.apple_offer_private_key = request.apple_offer_private_key;
+    app.apple_issuer_id = request.apple_issuer_id;
+    app.apple_consumption_opt_in = request.apple_consumption_opt_in.unwrap_or(false);
+
+    app.create(&pool).await?;
+
+    Ok((StatusCode::CREATED, Json(app.into())))
+}
+
+// Update an app
+pub async fn update_app(
+    Path(app_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<UpdateAppRequest>,
+) -> Result<Json<AppResponse>> {
+    let mut app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    // Update fields if provided
+    if let Some(name) = request.name {
+        app.name = name;
+    }
+
+    if let Some(apple_bundle_id) = request.apple_bundle_id {
+        app.apple_bundle_id = Some(apple_bundle_id);
+    }
+
+    if let Some(google_package_name) = request.google_package_name {
+        app.google_package_name = Some(google_package_name);
+    }
+
+    if let Some(apple_offer_key_id) = request.apple_offer_key_id {
+        app.apple_offer_key_id = Some(apple_offer_key_id);
+    }
+
+    if let Some(apple_offer_private_key) = request.apple_offer_private_key {
+        app.apple_offer_private_key = Some(apple_offer_private_key);
+    }
+
+    if let Some(apple_issuer_id) = request.apple_issuer_id {
+        app.apple_issuer_id = Some(apple_issuer_id);
+    }
+
+    if let Some(apple_consumption_opt_in) = request.apple_consumption_opt_in {
+        app.apple_consumption_opt_in = apple_consumption_opt_in;
+    }
+
+    app.update(&pool).await?;
+
+    Ok(Json(app.into()))
+}
+
+// Delete an app
+pub async fn delete_app(
+    Path(app_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    app.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Sign an Apple promotional offer so the app can present it through StoreKit.
+// A fresh nonce and timestamp are generated for every signature.
+pub async fn sign_promotional_offer(
+    Path(app_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<OfferSignatureRequest>,
+) -> Result<Json<OfferSignatureResponse>> {
+    let app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    if request.product_identifier.trim().is_empty() || request.offer_identifier.trim().is_empty() {
+        return Err(AppError::ValidationError(
+            "Product identifier and offer identifier are required".to_string(),
+        ));
+    }
+
+    let (Some(app_bundle_id), Some(key_identifier), Some(private_key)) = (
+        app.apple_bundle_id,
+        app.apple_offer_key_id,
+        app.apple_offer_private_key,
+    ) else {
+        return Err(AppError::BadRequest(format!(
+            "App {} needs an Apple bundle ID and subscription offer key to sign offers",
+            app_id
+        )));
+    };
+
+    let nonce = Uuid::new_v4().to_string();
+    let timestamp = Utc::now().timestamp_millis();
+
+    let params = OfferSignatureParams {
+        app_bundle_id: &app_bundle_id,
+        key_identifier: &key_identifier,
+        product_identifier: &request.product_identifier,
+        offer_identifier: &request.offer_identifier,
+        application_username: &request.application_username,
+        nonce: &nonce,
+        timestamp,
+    };
+
+    let signature = offer_signature::sign(&params, &private_key).map_err(AppError::BadRequest)?;
+
+    Ok(Json(OfferSignatureResponse {
+        key_identifier,
+        nonce,
+        timestamp,
+        signature,
+    }))
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::collections::BTreeMap;
+
+use crate::db::models::{User, UserAttribute, EMAIL_ATTRIBUTE};
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Deserialize)]
+pub struct SetAttributeRequest {
+    pub value: Option<String>,  // None unsets the attribute
+    pub updated_at: Option<DateTime<Utc>>,  // When the client set it, defaults to now
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAttributesRequest {
+    pub attributes: BTreeMap<String, SetAttributeRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttributeResponse {
+    pub value: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttributesResponse {
+    pub attributes: BTreeMap<String, AttributeResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetAttributesResponse {
+    pub attributes: BTreeMap<String, AttributeResponse>,
+    pub stale_keys: Vec<String>,  // Keys that already had a newer value
+}
+
+// Attribute values by key, the way they're shown on users and events
+pub fn attribute_values(attributes: Vec<UserAttribute>) -> BTreeMap<String, String> {
+    attributes
+        .into_iter()
+        .filter_map(|attribute| attribute.value.map(|value| (attribute.key, value)))
+        .collect()
+}
+
+fn attribute_responses(attributes: Vec<UserAttribute>) -> BTreeMap<String, AttributeResponse> {
+    attributes
+        .into_iter()
+        .filter_map(|attribute| {
+            let updated_at = attribute.updated_at;
+            attribute.value.map(|value| (attribute.key, AttributeResponse { value, updated_at }))
+        })
+        .collect()
+}
+
+// Get a user's attributes
+pub async fn get_user_attributes(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<AttributesResponse>> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    let attributes = UserAttribute::list_by_user(&user.id, &pool).await?;
+
+    Ok(Json(AttributesResponse {
+        attributes: attribute_responses(attributes),
+    }))
+}
+
+// Set several attributes at once. Each value carries the time the client
+// set it, and a value older than the one already saved is skipped, so
+// devices syncing late don't overwrite newer values.
+pub async fn set_user_attributes(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<SetAttributesRequest>,
+) -> Result<Json<SetAttributesResponse>> {
+    let mut user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    // Check everything first so a bad key doesn't leave a batch half set
+    for (key, attribute) in &request.attributes {
+        if !UserAttribute::is_valid_key(key) {
+            return Err(AppError::ValidationError(format!(
+                "Invalid attribute key: {}. Keys can't be empty or start with $ unless reserved",
+                key
+            )));
+        }
+
+        if attribute.value.as_deref().is_some_and(|value| !UserAttribute::is_valid_value(value)) {
+            return Err(AppError::ValidationError(format!("Value is too long for attribute: {}", key)));
+        }
+    }
+
+    let now = Utc::now();
+    let mut stale_keys = Vec::new();
+
+    for (key, attribute) in request.attributes {
+        let updated_at = attribute.updated_at.unwrap_or(now);
+        let user_attribute = UserAttribute::new(user.id.clone(), key.clone(), attribute.value, updated_at);
+
+        if !user_attribute.set(&pool).await? {
+            stale_keys.push(key);
+            continue;
+        }
+
+        // The email attribute is the user's email
+        if key == EMAIL_ATTRIBUTE {
+            user.email = user_attribute.value;
+            user.update(&pool).await?;
+        }
+    }
+
+    let attributes = UserAttribute::list_by_user(&user.id, &pool).await?;
+
+    Ok(Json(SetAttributesResponse {
+        attributes: attribute_responses(attributes),
+        stale_keys,
+    }))
+}
@@ -0,0 +1,135 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query, State},
+    http::request::Parts,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use std::convert::Infallible;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page};
+use crate::db::models::{AuditLogEntry, AuditLogFilter};
+use crate::error::Result;
+
+// Set to the ID of the key used by the proxy that authenticates API requests.
+// The service can't check it, so the proxy must strip any value the client
+// sent, or anyone could record changes under another key.
+pub const ACTOR_HEADER: &str = "x-api-key-id";
+pub const REASON_HEADER: &str = "x-audit-reason";
+
+// Who is making a manual change and why, taken from the request headers
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub actor: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> std::result::Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        Ok(Self {
+            actor: header(ACTOR_HEADER),
+            reason: header(REASON_HEADER),
+        })
+    }
+}
+
+impl AuditContext {
+    // Save the entry with the caller, and the reason from the header unless
+    // the entry already has one
+    pub async fn record(&self, mut entry: AuditLogEntry, executor: impl SqliteExecutor<'_>) -> Result<()> {
+        entry.actor = self.actor.clone();
+        entry.reason = entry.reason.or_else(|| self.reason.clone());
+        entry.create(executor).await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub user_id: Option<String>,
+    pub limit: Option<i64>,
+    pub starting_after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogEntryResponse {
+    pub id: String,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: String,
+    pub user_id: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditLogEntryResponse>,
+    pub has_more: bool,
+}
+
+impl From<AuditLogEntry> for AuditLogEntryResponse {
+    fn from(entry: AuditLogEntry) -> Self {
+        Self {
+            id: entry.id,
+            actor: entry.actor,
+            action: entry.action,
+            target_type: entry.target_type,
+            target_id: entry.target_id,
+            user_id: entry.user_id,
+            before: entry.before.map(|before| before.0),
+            after: entry.after.map(|after| after.0),
+            reason: entry.reason,
+            created_at: entry.created_at,
+        }
+    }
+}
+
+// Get manual changes, newest first, filtered by who made them or what they
+// changed
+pub async fn get_audit_log(
+    Query(query): Query<AuditLogQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<AuditLogResponse>> {
+    let limit = page_limit(query.limit)?;
+    check_cursor("audit_log", query.starting_after.as_deref(), &pool).await?;
+
+    let filter = AuditLogFilter {
+        actor: query.actor,
+        action: query.action,
+        target_type: query.target_type,
+        target_id: query.target_id,
+        user_id: query.user_id,
+    };
+
+    // Fetch one extra entry to tell whether there's another page
+    let entries = AuditLogEntry::list(&filter, query.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (entries, has_more) = split_page(entries, limit);
+
+    Ok(Json(AuditLogResponse {
+        entries: entries.into_iter().map(AuditLogEntryResponse::from).collect(),
+        has_more,
+    }))
+}
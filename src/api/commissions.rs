@@ -0,0 +1,223 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::pagination::{page_limit, split_page, PageQuery};
+use crate::db::models::{CommissionRule, TaxRate};
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Serialize)]
+pub struct CommissionRuleResponse {
+    pub store: String,
+    pub standard_rate: f64,
+    pub reduced_rate: f64,
+    pub small_business: bool,
+    pub reduced_after_days: Option<i64>,
+    pub reduced_for_subscriptions: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommissionRulesResponse {
+    pub commission_rules: Vec<CommissionRuleResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCommissionRuleRequest {
+    pub standard_rate: Option<f64>,
+    pub reduced_rate: Option<f64>,
+    pub small_business: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub reduced_after_days: Option<Option<i64>>,  // null turns the rule off
+    pub reduced_for_subscriptions: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxRateResponse {
+    pub country_code: String,
+    pub rate: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxRatesResponse {
+    pub tax_rates: Vec<TaxRateResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetTaxRateRequest {
+    pub rate: f64,
+}
+
+impl From<CommissionRule> for CommissionRuleResponse {
+    fn from(rule: CommissionRule) -> Self {
+        Self {
+            store: rule.store,
+            standard_rate: rule.standard_rate,
+            reduced_rate: rule.reduced_rate,
+            small_business: rule.small_business,
+            reduced_after_days: rule.reduced_after_days,
+            reduced_for_subscriptions: rule.reduced_for_subscriptions,
+            updated_at: rule.updated_at,
+        }
+    }
+}
+
+impl From<TaxRate> for TaxRateResponse {
+    fn from(rate: TaxRate) -> Self {
+        Self {
+            country_code: rate.country_code,
+            rate: rate.rate,
+            updated_at: rate.updated_at,
+        }
+    }
+}
+
+// Distinguish an explicit null from a missing field
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// Rates are fractions of the price, so 0.15 is 15%
+fn validate_rate(name: &str, rate: f64) -> Result<()> {
+    if !(0.0..1.0).contains(&rate) {
+        return Err(AppError::ValidationError(format!(
+            "{} must be at least 0 and less than 1",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+// Get the commission rules for all stores
+pub async fn get_commission_rules(
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<CommissionRulesResponse>> {
+    let limit = page_limit(page.limit)?;
+
+    // Fetch one extra rule to tell whether there's another page
+    let rules = CommissionRule::list(page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (rules, has_more) = split_page(rules, limit);
+
+    Ok(Json(CommissionRulesResponse {
+        commission_rules: rules.into_iter().map(CommissionRuleResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Update the commission rule for a store. Only new transactions use the new rule.
+pub async fn update_commission_rule(
+    Path(store): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<UpdateCommissionRuleRequest>,
+) -> Result<Json<CommissionRuleResponse>> {
+    if store != "apple" && store != "google" {
+        return Err(AppError::ValidationError(format!("Unknown store: {}", store)));
+    }
+
+    let mut rule = CommissionRule::find_by_store(&store, &pool)
+        .await?
+        .unwrap_or_else(|| CommissionRule::standard(&store));
+
+    // Update fields if provided
+    if let Some(standard_rate) = request.standard_rate {
+        validate_rate("standard_rate", standard_rate)?;
+        rule.standard_rate = standard_rate;
+    }
+
+    if let Some(reduced_rate) = request.reduced_rate {
+        validate_rate("reduced_rate", reduced_rate)?;
+        rule.reduced_rate = reduced_rate;
+    }
+
+    if let Some(small_business) = request.small_business {
+        rule.small_business = small_business;
+    }
+
+    if let Some(reduced_after_days) = request.reduced_after_days {
+        if reduced_after_days.is_some_and(|days| days < 0) {
+            return Err(AppError::ValidationError(
+                "reduced_after_days must not be negative".to_string(),
+            ));
+        }
+
+        rule.reduced_after_days = reduced_after_days;
+    }
+
+    if let Some(reduced_for_subscriptions) = request.reduced_for_subscriptions {
+        rule.reduced_for_subscriptions = reduced_for_subscriptions;
+    }
+
+    rule.upsert(&pool).await?;
+
+    let rule = CommissionRule::find_by_store(&store, &pool)
+        .await?
+        .ok_or_else(|| AppError::InternalServerError("Failed to save commission rule".to_string()))?;
+
+    Ok(Json(CommissionRuleResponse::from(rule)))
+}
+
+// Get all tax rates
+pub async fn get_tax_rates(
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<TaxRatesResponse>> {
+    let limit = page_limit(page.limit)?;
+
+    // Fetch one extra rate to tell whether there's another page
+    let rates = TaxRate::list(page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (rates, has_more) = split_page(rates, limit);
+
+    Ok(Json(TaxRatesResponse {
+        tax_rates: rates.into_iter().map(TaxRateResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Set the tax or VAT rate included in prices for a country
+pub async fn set_tax_rate(
+    Path(country_code): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<SetTaxRateRequest>,
+) -> Result<Json<TaxRateResponse>> {
+    if country_code.len() != 2 || !country_code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(AppError::ValidationError(format!(
+            "Invalid country code: {}",
+            country_code
+        )));
+    }
+
+    validate_rate("rate", request.rate)?;
+
+    let rate = TaxRate::new(&country_code, request.rate);
+    rate.upsert(&pool).await?;
+
+    Ok(Json(TaxRateResponse::from(rate)))
+}
+
+// Delete the tax rate for a country
+pub async fn delete_tax_rate(
+    Path(country_code): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let rate = TaxRate::find_by_country(&country_code, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Tax rate not found: {}", country_code)))?;
+
+    rate.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
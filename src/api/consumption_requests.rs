@@ -0,0 +1,117 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::{App, ConsumptionRequest, ConsumptionRequestStatus};
+use crate::error::{AppError, Result};
+use crate::providers::apple::send_consumption_request;
+
+#[derive(Debug, Serialize)]
+pub struct ConsumptionRequestResponse {
+    pub id: String,
+    pub notification_uuid: String,
+    pub environment: String,
+    pub transaction_id: String,
+    pub user_id: Option<String>,
+    pub subscription_id: Option<String>,
+    pub status: String,
+    pub consumption_information: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub deadline: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsumptionRequestsResponse {
+    pub consumption_requests: Vec<ConsumptionRequestResponse>,
+    pub has_more: bool,
+}
+
+impl From<ConsumptionRequest> for ConsumptionRequestResponse {
+    fn from(request: ConsumptionRequest) -> Self {
+        Self {
+            id: request.id,
+            notification_uuid: request.notification_uuid,
+            environment: request.environment,
+            transaction_id: request.transaction_id,
+            user_id: request.user_id,
+            subscription_id: request.subscription_id,
+            status: request.status,
+            consumption_information: request
+                .request_json
+                .and_then(|json| serde_json::from_str(&json).ok()),
+            error: request.error,
+            deadline: request.deadline,
+            sent_at: request.sent_at,
+            created_at: request.created_at,
+        }
+    }
+}
+
+// Get the consumption requests Apple sent for an app, newest first
+pub async fn get_app_consumption_requests(
+    Path(app_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ConsumptionRequestsResponse>> {
+    let app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    let limit = page_limit(page.limit)?;
+    check_cursor("consumption_requests", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra request to tell whether there's another page
+    let requests = ConsumptionRequest::list_by_app(
+        &app.id,
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (requests, has_more) = split_page(requests, limit);
+
+    Ok(Json(ConsumptionRequestsResponse {
+        consumption_requests: requests.into_iter().map(ConsumptionRequestResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Send a failed consumption request again, e.g. after fixing the app's keys.
+// Only possible while Apple's 12 hour window is still open.
+pub async fn retry_consumption_request(
+    Path(request_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ConsumptionRequestResponse>> {
+    let mut request = ConsumptionRequest::find_by_id(&request_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Consumption request not found: {}", request_id)))?;
+
+    if request.status != ConsumptionRequestStatus::Failed.to_string() {
+        return Err(AppError::BadRequest(format!(
+            "Only failed consumption requests can be retried, this one is {}",
+            request.status
+        )));
+    }
+
+    if Utc::now() > request.deadline {
+        return Err(AppError::BadRequest(
+            "The 12 hour window to respond to this consumption request has passed".to_string(),
+        ));
+    }
+
+    let app = App::find_by_id(&request.app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", request.app_id)))?;
+
+    send_consumption_request(&mut request, &app).await;
+    request.update(&pool).await?;
+
+    Ok(Json(request.into()))
+}
@@ -0,0 +1,115 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+
+use crate::db::models::{Product, ProductType, Subscription, User};
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IntroEligibility {
+    Eligible,
+    Ineligible,
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntroEligibilityRequest {
+    pub app_user_id: String,
+    pub product_ids: Vec<String>,
+    // 'apple' or 'google' when product_ids are store product ids
+    pub store: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductEligibilityResponse {
+    pub product_id: String,
+    pub status: IntroEligibility,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntroEligibilityResponse {
+    pub app_user_id: String,
+    pub products: Vec<ProductEligibilityResponse>,
+}
+
+// Work out whether a user can still get a free trial or introductory price for
+// each product. Stores allow one introductory offer per subscription group, so
+// a trial or intro offer on any product in the group, bought through either
+// store, uses it up.
+pub async fn check_intro_eligibility(
+    State(pool): State<SqlitePool>,
+    Json(request): Json<IntroEligibilityRequest>,
+) -> Result<Json<IntroEligibilityResponse>> {
+    if request.product_ids.is_empty() {
+        return Err(AppError::ValidationError("At least one product id is required".to_string()));
+    }
+
+    if let Some(store) = &request.store {
+        if store != "apple" && store != "google" {
+            return Err(AppError::ValidationError(format!("Unknown store: {}", store)));
+        }
+    }
+
+    let user = User::find_by_app_user_id(&request.app_user_id, &pool).await?;
+
+    // Products the user has used an introductory offer on
+    let mut redeemed: HashMap<String, Product> = HashMap::new();
+
+    if let Some(user) = &user {
+        for subscription in Subscription::list_by_user(&user.id, &pool).await? {
+            if !(subscription.is_trial || subscription.is_intro_offer)
+                || redeemed.contains_key(&subscription.product_id)
+            {
+                continue;
+            }
+
+            if let Some(product) = Product::find_by_id(&subscription.product_id, &pool).await? {
+                redeemed.insert(product.id.clone(), product);
+            }
+        }
+    }
+
+    let mut products = Vec::with_capacity(request.product_ids.len());
+
+    for product_id in request.product_ids {
+        let product = match &request.store {
+            Some(store) => Product::find_by_store_product_id(store, &product_id, &pool).await?,
+            None => Product::find_by_id(&product_id, &pool).await?,
+        };
+
+        let (status, reason) = match product {
+            None => (IntroEligibility::Unknown, "product_not_found"),
+            Some(product) if product.type_ != ProductType::Subscription.to_string() => {
+                (IntroEligibility::Ineligible, "not_a_subscription")
+            }
+            Some(_) if user.is_none() => (IntroEligibility::Unknown, "user_not_found"),
+            Some(product) => {
+                let used = redeemed.values().any(|redeemed| {
+                    redeemed.id == product.id
+                        || (product.subscription_group.is_some()
+                            && redeemed.subscription_group == product.subscription_group)
+                });
+
+                if used {
+                    (IntroEligibility::Ineligible, "intro_offer_used")
+                } else {
+                    (IntroEligibility::Eligible, "no_intro_offer_used")
+                }
+            }
+        };
+
+        products.push(ProductEligibilityResponse {
+            product_id,
+            status,
+            reason: reason.to_string(),
+        });
+    }
+
+    Ok(Json(IntroEligibilityResponse {
+        app_user_id: request.app_user_id,
+        products,
+    }))
+}
@@ -7,7 +7,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqlitePool;
 
-use crate::db::models::{UserEntitlement, Entitlement};
+use crate::api::audit::AuditContext;
+use crate::db::models::{AuditAction, AuditLogEntry, GrantDuration, User, UserEntitlement, Entitlement};
 use crate::error::{AppError, Result};
 
 #[derive(Debug, Serialize)]
@@ -42,11 +43,151 @@ pub struct CreateEntitlementRequest {
     pub description: Option<String>,
 }
 
+// Most users a bulk grant can go to at once
+const MAX_BULK_GRANT_USERS: usize = 1000;
+
+// What a promotional grant gives. Without a duration or expires_at it
+// lasts for life.
+#[derive(Debug, Deserialize)]
+pub struct GrantTerms {
+    pub entitlement_id: String,
+    pub duration: Option<GrantDuration>,
+    pub expires_at: Option<DateTime<Utc>>,  // Instead of a duration
+    pub starts_at: Option<DateTime<Utc>>,  // Defaults to now
+    pub reason: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GrantEntitlementRequest {
+    pub user_id: String,
+    #[serde(flatten)]
+    pub terms: GrantTerms,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkGrantEntitlementRequest {
+    pub user_ids: Vec<String>,
+    #[serde(flatten)]
+    pub terms: GrantTerms,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrantResponse {
+    pub id: String,
     pub user_id: String,
     pub entitlement_id: String,
+    pub starts_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+    pub extended: bool,  // An existing grant was extended instead of a new one created
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkGrantFailure {
+    pub user_id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkGrantResponse {
+    pub grants: Vec<GrantResponse>,
+    pub failed: Vec<BulkGrantFailure>,
+}
+
+impl GrantResponse {
+    pub fn new(grant: UserEntitlement, extended: bool) -> Self {
+        Self {
+            id: grant.id,
+            user_id: grant.user_id,
+            entitlement_id: grant.entitlement_id,
+            starts_at: grant.starts_at,
+            expires_at: grant.expires_at,
+            reason: grant.reason,
+            extended,
+        }
+    }
+}
+
+impl GrantTerms {
+    fn validate(&self) -> Result<()> {
+        if self.duration.is_some() && self.expires_at.is_some() {
+            return Err(AppError::ValidationError(
+                "Give either a duration or expires_at, not both".to_string(),
+            ));
+        }
+
+        let starts_at = self.starts_at.unwrap_or_else(Utc::now);
+        if self.expires_at.is_some_and(|expires_at| expires_at <= starts_at) {
+            return Err(AppError::ValidationError("expires_at must be after the grant starts".to_string()));
+        }
+
+        Ok(())
+    }
+
+    // When a grant starting at `start` ends, None for lifetime
+    fn end(&self, start: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match (self.duration, self.expires_at) {
+            (Some(duration), _) => duration.end(start),
+            (None, expires_at) => expires_at,
+        }
+    }
+}
+
+// Grant an entitlement outside of a purchase. If the user already has a
+// promotional grant of it that has started by the time the new one starts
+// and hasn't ended by then, that grant is extended instead of a second one being stacked on top:
+// durations are added to its end, and an absolute end only ever lengthens
+// it. Returns the grant and whether an existing one was extended. Runs in
+// the caller's transaction, so it's saved along with whatever it's for.
+pub async fn grant_promotional(
+    user_id: &str,
+    terms: &GrantTerms,
+    audit: &AuditContext,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<(UserEntitlement, bool)> {
+    let now = Utc::now();
+    let starts_at = terms.starts_at.unwrap_or(now);
+
+    let current = UserEntitlement::find_current_promotional(user_id, &terms.entitlement_id, starts_at, now, &mut **tx)
+        .await?
+        .filter(|grant| grant.expires_at.is_none_or(|expires_at| expires_at >= starts_at));
+
+    if let Some(mut grant) = current {
+        // A lifetime grant can't be extended any further
+        let Some(current_end) = grant.expires_at else {
+            return Ok((grant, true));
+        };
+
+        let mut entry = AuditLogEntry::new(AuditAction::EntitlementExtended, "user_entitlement", &grant.id)
+            .for_user(&grant.user_id)
+            .before(&grant);
+
+        let expires_at = terms.end(current_end).map(|end| end.max(current_end));
+        grant.update_expiry(expires_at, &mut **tx).await?;
+
+        entry.reason = terms.reason.clone();
+        audit.record(entry.after(&grant), &mut **tx).await?;
+
+        return Ok((grant, true));
+    }
+
+    let mut grant = UserEntitlement::new(
+        user_id.to_string(),
+        terms.entitlement_id.clone(),
+        None, // Not tied to a subscription
+        starts_at,
+        terms.end(starts_at),
+    );
+    grant.reason = terms.reason.clone();
+    grant.create(&mut **tx).await?;
+
+    let mut entry = AuditLogEntry::new(AuditAction::EntitlementGranted, "user_entitlement", &grant.id)
+        .for_user(&grant.user_id)
+        .after(&grant);
+    entry.reason = terms.reason.clone();
+    audit.record(entry, &mut **tx).await?;
+
+    Ok((grant, false))
 }
 
 // Get all entitlements for a user
@@ -128,35 +269,85 @@ pub async fn create_entitlement(
     ))
 }
 
-// Manually grant an entitlement to a user
+// Manually grant an entitlement to a user, or extend their current grant
 pub async fn grant_entitlement(
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<GrantEntitlementRequest>,
-) -> Result<StatusCode> {
+) -> Result<(StatusCode, Json<GrantResponse>)> {
+    request.terms.validate()?;
+    
     // Check if the entitlement exists
-    let _entitlement = Entitlement::find_by_id(&request.entitlement_id, &pool)
+    let _entitlement = Entitlement::find_by_id(&request.terms.entitlement_id, &pool)
         .await?
         .ok_or_else(|| {
-            AppError::NotFound(format!("Entitlement not found: {}", request.entitlement_id))
+            AppError::NotFound(format!("Entitlement not found: {}", request.terms.entitlement_id))
         })?;
     
-    // Create the user entitlement
-    let user_entitlement = UserEntitlement::new(
-        request.user_id,
-        request.entitlement_id,
-        None, // Not tied to a subscription
-        Utc::now(),
-        request.expires_at,
-    );
+    let user = User::find_by_id(&request.user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", request.user_id)))?;
     
-    user_entitlement.create(&pool).await?;
+    let mut tx = pool.begin().await?;
+    let (grant, extended) = grant_promotional(&user.id, &request.terms, &audit, &mut tx).await?;
+    tx.commit().await?;
     
-    Ok(StatusCode::CREATED)
+    let status = if extended { StatusCode::OK } else { StatusCode::CREATED };
+    
+    Ok((status, Json(GrantResponse::new(grant, extended))))
+}
+
+// Grant an entitlement to many users at once, e.g. for a promotion. Users
+// that can't be found are reported without stopping the others.
+pub async fn bulk_grant_entitlement(
+    audit: AuditContext,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<BulkGrantEntitlementRequest>,
+) -> Result<Json<BulkGrantResponse>> {
+    request.terms.validate()?;
+    
+    if request.user_ids.is_empty() || request.user_ids.len() > MAX_BULK_GRANT_USERS {
+        return Err(AppError::ValidationError(format!(
+            "user_ids must have between 1 and {} users",
+            MAX_BULK_GRANT_USERS
+        )));
+    }
+    
+    let _entitlement = Entitlement::find_by_id(&request.terms.entitlement_id, &pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Entitlement not found: {}", request.terms.entitlement_id))
+        })?;
+    
+    let mut user_ids = request.user_ids;
+    user_ids.sort();
+    user_ids.dedup();
+    
+    let mut grants = Vec::new();
+    let mut failed = Vec::new();
+    
+    for user_id in user_ids {
+        if User::find_by_id(&user_id, &pool).await?.is_none() {
+            failed.push(BulkGrantFailure {
+                user_id,
+                error: "User not found".to_string(),
+            });
+            continue;
+        }
+        
+        let mut tx = pool.begin().await?;
+        let (grant, extended) = grant_promotional(&user_id, &request.terms, &audit, &mut tx).await?;
+        tx.commit().await?;
+        grants.push(GrantResponse::new(grant, extended));
+    }
+    
+    Ok(Json(BulkGrantResponse { grants, failed }))
 }
 
 // Revoke an entitlement from a user
 pub async fn revoke_entitlement(
     Path((user_id, entitlement_id)): Path<(String, String)>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
 ) -> Result<StatusCode> {
     let now = Utc::now();
@@ -171,9 +362,18 @@ pub async fn revoke_entitlement(
             ))
         })?;
     
+    let entry = AuditLogEntry::new(AuditAction::EntitlementRevoked, "user_entitlement", &user_entitlement.id)
+        .for_user(&user_entitlement.user_id)
+        .before(&user_entitlement);
+    
     // Revoke the entitlement
     let mut user_entitlement_mut = user_entitlement;
-    user_entitlement_mut.revoke(&pool).await?;
+    let mut tx = pool.begin().await?;
+
+    user_entitlement_mut.revoke(&mut *tx).await?;
+    audit.record(entry.after(&user_entitlement_mut), &mut *tx).await?;
+
+    tx.commit().await?;
     
     Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,133 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+
+use crate::api::pagination::{page_limit, split_page, PageQuery};
+use crate::db::models::{ExchangeRate, Transaction};
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Serialize)]
+pub struct ExchangeRateResponse {
+    pub currency: String,
+    pub usd_rate: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExchangeRatesResponse {
+    pub rates: Vec<ExchangeRateResponse>,
+    pub has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backfilled_transactions: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportExchangeRatesRequest {
+    pub rates: HashMap<String, f64>,  // Currency code to USD value of one unit
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetExchangeRateRequest {
+    pub usd_rate: f64,
+}
+
+impl From<ExchangeRate> for ExchangeRateResponse {
+    fn from(rate: ExchangeRate) -> Self {
+        Self {
+            currency: rate.currency,
+            usd_rate: rate.usd_rate,
+            updated_at: rate.updated_at,
+        }
+    }
+}
+
+// Get a page of exchange rates
+pub async fn get_exchange_rates(
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExchangeRatesResponse>> {
+    let limit = page_limit(page.limit)?;
+
+    // Fetch one extra rate to tell whether there's another page
+    let rates = ExchangeRate::list(page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (rates, has_more) = split_page(rates, limit);
+
+    Ok(Json(ExchangeRatesResponse {
+        rates: rates.into_iter().map(ExchangeRateResponse::from).collect(),
+        has_more,
+        backfilled_transactions: None,
+    }))
+}
+
+// Import a set of exchange rates, replacing existing rates for the same currencies.
+// Transactions that were waiting on one of these rates get their USD amount filled in.
+pub async fn import_exchange_rates(
+    State(pool): State<SqlitePool>,
+    Json(request): Json<ImportExchangeRatesRequest>,
+) -> Result<Json<ExchangeRatesResponse>> {
+    // Validate everything before storing anything
+    for (currency, usd_rate) in &request.rates {
+        ExchangeRate::validate(currency, *usd_rate).map_err(AppError::ValidationError)?;
+    }
+
+    for (currency, usd_rate) in &request.rates {
+        ExchangeRate::new(currency, *usd_rate).upsert(&pool).await?;
+    }
+
+    let backfilled = Transaction::backfill_usd_amounts(&pool).await?;
+    let rates = ExchangeRate::list_all(&pool).await?;
+
+    Ok(Json(ExchangeRatesResponse {
+        rates: rates.into_iter().map(ExchangeRateResponse::from).collect(),
+        has_more: false,  // Every rate is returned after an import
+        backfilled_transactions: Some(backfilled),
+    }))
+}
+
+// Get the exchange rate for a currency
+pub async fn get_exchange_rate(
+    Path(currency): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExchangeRateResponse>> {
+    let rate = ExchangeRate::find_by_currency(&currency, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Exchange rate not found: {}", currency)))?;
+
+    Ok(Json(ExchangeRateResponse::from(rate)))
+}
+
+// Set the exchange rate for a currency
+pub async fn set_exchange_rate(
+    Path(currency): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<SetExchangeRateRequest>,
+) -> Result<Json<ExchangeRateResponse>> {
+    ExchangeRate::validate(&currency, request.usd_rate).map_err(AppError::ValidationError)?;
+
+    let rate = ExchangeRate::new(&currency, request.usd_rate);
+    rate.upsert(&pool).await?;
+
+    Transaction::backfill_usd_amounts(&pool).await?;
+
+    Ok(Json(ExchangeRateResponse::from(rate)))
+}
+
+// Delete the exchange rate for a currency. Amounts already converted are kept.
+pub async fn delete_exchange_rate(
+    Path(currency): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let rate = ExchangeRate::find_by_currency(&currency, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Exchange rate not found: {}", currency)))?;
+
+    rate.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
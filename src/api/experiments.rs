@@ -0,0 +1,367 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::{App, Experiment, ExperimentStatus, ExperimentVariant, Offering};
+use crate::error::{AppError, Result};
+use crate::utils::stats::{rate, two_proportion_z_test, wilson_interval};
+
+// p-value below which a variant's difference from control is reported as significant
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+#[derive(Debug, Serialize)]
+pub struct VariantResponse {
+    pub id: String,
+    pub identifier: String,
+    pub offering_id: String,
+    pub weight: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExperimentResponse {
+    pub id: String,
+    pub app_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub variants: Vec<VariantResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExperimentsResponse {
+    pub experiments: Vec<ExperimentResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ControlComparisonResponse {
+    pub conversion_rate_lift: Option<f64>,
+    pub z_score: Option<f64>,
+    pub p_value: Option<f64>,
+    pub significant: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantResultResponse {
+    pub variant_id: String,
+    pub identifier: String,
+    pub offering_id: String,
+    pub is_control: bool,
+    pub users: i64,
+    pub trial_starts: i64,
+    pub trial_start_rate: f64,
+    pub conversions: i64,
+    pub conversion_rate: f64,
+    pub conversion_rate_interval: Option<(f64, f64)>,
+    pub revenue: f64,
+    pub revenue_per_user: f64,
+    pub proceeds: f64,
+    pub vs_control: Option<ControlComparisonResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExperimentResultsResponse {
+    pub experiment_id: String,
+    pub status: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub variants: Vec<VariantResultResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateVariantRequest {
+    pub identifier: String,
+    pub offering_id: String,
+    pub weight: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateExperimentRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub variants: Vec<CreateVariantRequest>,
+}
+
+async fn build_experiment_response(experiment: Experiment, pool: &SqlitePool) -> Result<ExperimentResponse> {
+    let variants = experiment.get_variants(pool).await?;
+
+    Ok(ExperimentResponse {
+        id: experiment.id,
+        app_id: experiment.app_id,
+        name: experiment.name,
+        description: experiment.description,
+        status: experiment.status,
+        started_at: experiment.started_at,
+        stopped_at: experiment.stopped_at,
+        variants: variants
+            .into_iter()
+            .map(|variant| VariantResponse {
+                id: variant.id,
+                identifier: variant.identifier,
+                offering_id: variant.offering_id,
+                weight: variant.weight,
+            })
+            .collect(),
+    })
+}
+
+// Get all experiments for an app
+pub async fn get_app_experiments(
+    Path(app_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExperimentsResponse>> {
+    let _app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    let limit = page_limit(page.limit)?;
+    check_cursor("experiments", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra experiment to tell whether there's another page
+    let experiments = Experiment::list_by_app(&app_id, page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (experiments, has_more) = split_page(experiments, limit);
+
+    let mut experiment_responses = Vec::new();
+
+    for experiment in experiments {
+        experiment_responses.push(build_experiment_response(experiment, &pool).await?);
+    }
+
+    Ok(Json(ExperimentsResponse {
+        experiments: experiment_responses,
+        has_more,
+    }))
+}
+
+// Create a new experiment. The first variant is treated as the control.
+pub async fn create_experiment(
+    Path(app_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<CreateExperimentRequest>,
+) -> Result<(StatusCode, Json<ExperimentResponse>)> {
+    let _app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    if request.variants.len() < 2 {
+        return Err(AppError::ValidationError(
+            "An experiment needs at least two variants".to_string(),
+        ));
+    }
+
+    // Verify the variants before creating anything
+    for (index, variant) in request.variants.iter().enumerate() {
+        if request.variants[..index].iter().any(|other| other.identifier == variant.identifier) {
+            return Err(AppError::ValidationError(format!(
+                "Duplicate variant identifier: {}",
+                variant.identifier
+            )));
+        }
+
+        if variant.weight.unwrap_or(1) <= 0 {
+            return Err(AppError::ValidationError(format!(
+                "Variant {} must have a positive weight",
+                variant.identifier
+            )));
+        }
+
+        let offering = Offering::find_by_id(&variant.offering_id, &pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", variant.offering_id)))?;
+
+        if offering.app_id != app_id {
+            return Err(AppError::BadRequest(format!(
+                "Offering {} does not belong to app {}",
+                offering.id, app_id
+            )));
+        }
+    }
+
+    let experiment = Experiment::new(app_id, request.name, request.description);
+    experiment.create(&pool).await?;
+
+    for (index, variant_request) in request.variants.into_iter().enumerate() {
+        let variant = ExperimentVariant::new(
+            experiment.id.clone(),
+            variant_request.identifier,
+            variant_request.offering_id,
+            variant_request.weight.unwrap_or(1),
+            index as i32,
+        );
+
+        variant.create(&pool).await?;
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(build_experiment_response(experiment, &pool).await?),
+    ))
+}
+
+// Get a specific experiment
+pub async fn get_experiment(
+    Path(experiment_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExperimentResponse>> {
+    let experiment = Experiment::find_by_id(&experiment_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Experiment not found: {}", experiment_id)))?;
+
+    Ok(Json(build_experiment_response(experiment, &pool).await?))
+}
+
+// Start an experiment so the current offering endpoint begins splitting users
+pub async fn start_experiment(
+    Path(experiment_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExperimentResponse>> {
+    let mut experiment = Experiment::find_by_id(&experiment_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Experiment not found: {}", experiment_id)))?;
+
+    // Only draft experiments can be started
+    if experiment.status != ExperimentStatus::Draft.to_string() {
+        return Err(AppError::BadRequest(format!(
+            "Experiment is not a draft, current status: {}",
+            experiment.status
+        )));
+    }
+
+    // Users can only be split by one experiment per app at a time
+    if let Some(running) = Experiment::find_running_for_app(&experiment.app_id, &pool).await? {
+        return Err(AppError::BadRequest(format!(
+            "Experiment {} is already running for this app",
+            running.id
+        )));
+    }
+
+    experiment.update_status(ExperimentStatus::Running, &pool).await?;
+
+    Ok(Json(build_experiment_response(experiment, &pool).await?))
+}
+
+// Stop a running experiment. Existing assignments are kept for the results.
+pub async fn stop_experiment(
+    Path(experiment_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExperimentResponse>> {
+    let mut experiment = Experiment::find_by_id(&experiment_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Experiment not found: {}", experiment_id)))?;
+
+    if experiment.status != ExperimentStatus::Running.to_string() {
+        return Err(AppError::BadRequest(format!(
+            "Experiment is not running, current status: {}",
+            experiment.status
+        )));
+    }
+
+    experiment.update_status(ExperimentStatus::Stopped, &pool).await?;
+
+    Ok(Json(build_experiment_response(experiment, &pool).await?))
+}
+
+// Delete an experiment and its assignments
+pub async fn delete_experiment(
+    Path(experiment_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let experiment = Experiment::find_by_id(&experiment_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Experiment not found: {}", experiment_id)))?;
+
+    experiment.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Get trial starts, conversions and revenue per variant, compared against the control
+pub async fn get_experiment_results(
+    Path(experiment_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<ExperimentResultsResponse>> {
+    let experiment = Experiment::find_by_id(&experiment_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Experiment not found: {}", experiment_id)))?;
+
+    let variants = experiment.get_variants(&pool).await?;
+
+    let mut variant_stats = Vec::new();
+    for variant in variants {
+        let stats = variant.get_stats(&pool).await?;
+        variant_stats.push((variant, stats));
+    }
+
+    // Variants are ordered by position, so the control comes first
+    let control = variant_stats
+        .first()
+        .map(|(_, stats)| (stats.conversions, stats.users));
+
+    let variant_results = variant_stats
+        .into_iter()
+        .enumerate()
+        .map(|(index, (variant, stats))| {
+            let is_control = index == 0;
+            let conversion_rate = rate(stats.conversions, stats.users);
+
+            let vs_control = match control {
+                Some((control_conversions, control_users)) if !is_control => {
+                    let control_rate = rate(control_conversions, control_users);
+                    let test = two_proportion_z_test(
+                        control_conversions,
+                        control_users,
+                        stats.conversions,
+                        stats.users,
+                    );
+
+                    Some(ControlComparisonResponse {
+                        conversion_rate_lift: (control_rate > 0.0)
+                            .then(|| (conversion_rate - control_rate) / control_rate),
+                        z_score: test.map(|(z, _)| z),
+                        p_value: test.map(|(_, p)| p),
+                        significant: test.is_some_and(|(_, p)| p < SIGNIFICANCE_LEVEL),
+                    })
+                }
+                _ => None,
+            };
+
+            VariantResultResponse {
+                variant_id: variant.id,
+                identifier: variant.identifier,
+                offering_id: variant.offering_id,
+                is_control,
+                users: stats.users,
+                trial_starts: stats.trial_starts,
+                trial_start_rate: rate(stats.trial_starts, stats.users),
+                conversions: stats.conversions,
+                conversion_rate,
+                conversion_rate_interval: wilson_interval(stats.conversions, stats.users),
+                revenue: stats.revenue,
+                revenue_per_user: if stats.users > 0 {
+                    stats.revenue / stats.users as f64
+                } else {
+                    0.0
+                },
+                proceeds: stats.proceeds,
+                vs_control,
+            }
+        })
+        .collect();
+
+    Ok(Json(ExperimentResultsResponse {
+        experiment_id: experiment.id,
+        status: experiment.status,
+        started_at: experiment.started_at,
+        stopped_at: experiment.stopped_at,
+        variants: variant_results,
+    }))
+}
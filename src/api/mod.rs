@@ -2,14 +2,33 @@ pub mod users;
 pub mod products;
 pub mod subscriptions;
 pub mod entitlements;
+pub mod apps;
+pub mod offerings;
+pub mod experiments;
+pub mod analytics;
+pub mod exchange_rates;
+pub mod commissions;
+pub mod store_reports;
+pub mod wallets;
+pub mod eligibility;
+pub mod consumption_requests;
+pub mod account_tokens;
+pub mod attributes;
+pub mod pagination;
+pub mod audit;
+pub mod promo_codes;
 
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post, put, delete},
     Router,
 };
 use sqlx::sqlite::SqlitePool;
 use tower_http::cors::{Any, CorsLayer};
 
+// Store reports cover a month of sales, so allow much larger bodies than the default
+const STORE_REPORT_MAX_BYTES: usize = 100 * 1024 * 1024;
+
 pub fn routes(pool: SqlitePool) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -24,16 +43,39 @@ pub fn routes(pool: SqlitePool) -> Router {
         .route("/users/:user_id", put(users::update_user))
         .route("/users/:user_id", delete(users::delete_user))
         .route("/users/app_id/:app_user_id", get(users::get_user_by_app_id))
+        .route("/users/login", post(users::login))
+        .route("/users/:user_id/aliases", get(users::get_user_aliases))
+        .route("/users/:user_id/aliases", post(users::create_user_alias))
+        .route("/users/:user_id/attributes", get(attributes::get_user_attributes))
+        .route("/users/:user_id/attributes", post(attributes::set_user_attributes))
         .route("/users/:user_id/subscriptions", get(users::get_user_subscriptions))
         .route("/users/:user_id/subscriptions/active", get(users::get_user_active_subscriptions))
         
+        // Account token routes
+        .route("/users/:user_id/account-tokens", get(account_tokens::get_user_account_tokens))
+        .route("/users/:user_id/account-tokens", post(account_tokens::register_account_token))
+        .route("/account-tokens/:token_id", delete(account_tokens::delete_account_token))
+        .route("/unattributed-purchases", get(account_tokens::get_unattributed_purchases))
+        .route("/unattributed-purchases/:purchase_id/attribute", post(account_tokens::attribute_unattributed_purchase))
+        
         // Entitlement routes
         .route("/entitlements", post(entitlements::create_entitlement))
         .route("/users/:user_id/entitlements", get(entitlements::get_user_entitlements))
         .route("/users/:user_id/entitlements/:entitlement_id", get(entitlements::check_entitlement_access))
         .route("/entitlements/grant", post(entitlements::grant_entitlement))
+        .route("/entitlements/grant/bulk", post(entitlements::bulk_grant_entitlement))
         .route("/users/:user_id/entitlements/:entitlement_id/revoke", post(entitlements::revoke_entitlement))
         
+        // Promo code routes
+        .route("/promo-code-batches", get(promo_codes::get_promo_code_batches))
+        .route("/promo-code-batches", post(promo_codes::create_promo_code_batch))
+        .route("/promo-code-batches/:batch_id", get(promo_codes::get_promo_code_batch))
+        .route("/promo-code-batches/:batch_id/codes", get(promo_codes::get_promo_codes))
+        .route("/promo-code-batches/:batch_id/codes", post(promo_codes::generate_promo_codes))
+        .route("/promo-code-batches/:batch_id/codes.csv", get(promo_codes::export_promo_codes_csv))
+        .route("/promo-code-batches/:batch_id/stats", get(promo_codes::get_promo_code_batch_stats))
+        .route("/promo-codes/redeem", post(promo_codes::redeem_promo_code))
+        
         // Product routes
         .route("/products", get(products::get_products))
         .route("/products", post(products::create_product))
@@ -45,9 +87,86 @@ pub fn routes(pool: SqlitePool) -> Router {
         
         // Subscription routes
         .route("/subscriptions", get(subscriptions::get_subscriptions))
+        .route("/subscriptions/billing-issues", get(subscriptions::get_subscriptions_with_billing_issues))
         .route("/subscriptions/:subscription_id", get(subscriptions::get_subscription))
         .route("/subscriptions/:subscription_id/cancel", post(subscriptions::cancel_subscription))
         .route("/subscriptions/:subscription_id/refund", post(subscriptions::refund_subscription))
+        .route("/subscriptions/:subscription_id/events", get(subscriptions::get_subscription_events))
+        .route("/users/:user_id/subscription-events", get(subscriptions::get_user_subscription_events))
+
+        // Eligibility routes
+        .route("/eligibility/intro-offers", post(eligibility::check_intro_eligibility))
+        
+        // App routes
+        .route("/apps", get(apps::get_apps))
+        .route("/apps", post(apps::create_app))
+        .route("/apps/:app_id", get(apps::get_app))
+        .route("/apps/:app_id", put(apps::update_app))
+        .route("/apps/:app_id", delete(apps::delete_app))
+        .route("/apps/:app_id/promotional-offers/signature", post(apps::sign_promotional_offer))
+
+        // Consumption request routes
+        .route("/apps/:app_id/consumption-requests", get(consumption_requests::get_app_consumption_requests))
+        .route("/consumption-requests/:request_id/retry", post(consumption_requests::retry_consumption_request))
+        
+        // Offering routes
+        .route("/apps/:app_id/offerings", get(offerings::get_app_offerings))
+        .route("/apps/:app_id/offerings", post(offerings::create_offering))
+        .route("/apps/:app_id/offerings/current", get(offerings::get_current_offering))
+        .route("/offerings/:offering_id", get(offerings::get_offering))
+        .route("/offerings/:offering_id", put(offerings::update_offering))
+        .route("/offerings/:offering_id", delete(offerings::delete_offering))
+        .route("/offerings/:offering_id/current", post(offerings::make_offering_current))
+        .route("/offerings/:offering_id/packages", post(offerings::add_offering_package))
+        .route("/offerings/:offering_id/packages/:package_id", delete(offerings::remove_offering_package))
+        
+        // Experiment routes
+        .route("/apps/:app_id/experiments", get(experiments::get_app_experiments))
+        .route("/apps/:app_id/experiments", post(experiments::create_experiment))
+        .route("/experiments/:experiment_id", get(experiments::get_experiment))
+        .route("/experiments/:experiment_id", delete(experiments::delete_experiment))
+        .route("/experiments/:experiment_id/start", post(experiments::start_experiment))
+        .route("/experiments/:experiment_id/stop", post(experiments::stop_experiment))
+        .route("/experiments/:experiment_id/results", get(experiments::get_experiment_results))
+        
+        // Analytics routes
+        .route("/analytics/metrics", get(analytics::get_metrics))
+        .route("/analytics/cohorts", get(analytics::get_cohorts))
+        .route("/analytics/cohorts.csv", get(analytics::export_cohorts_csv))
+        .route("/analytics/entitlements", get(analytics::get_entitlement_stats))
+        
+        // Exchange rate routes
+        .route("/exchange-rates", get(exchange_rates::get_exchange_rates))
+        .route("/exchange-rates", put(exchange_rates::import_exchange_rates))
+        .route("/exchange-rates/:currency", get(exchange_rates::get_exchange_rate))
+        .route("/exchange-rates/:currency", put(exchange_rates::set_exchange_rate))
+        .route("/exchange-rates/:currency", delete(exchange_rates::delete_exchange_rate))
+        
+        // Commission and tax routes
+        .route("/commission-rules", get(commissions::get_commission_rules))
+        .route("/commission-rules/:store", put(commissions::update_commission_rule))
+        .route("/tax-rates", get(commissions::get_tax_rates))
+        .route("/tax-rates/:country_code", put(commissions::set_tax_rate))
+        .route("/tax-rates/:country_code", delete(commissions::delete_tax_rate))
+        
+        // Store report routes
+        .route("/store-reports", get(store_reports::get_store_reports))
+        .route(
+            "/store-reports",
+            post(store_reports::import_store_report).layer(DefaultBodyLimit::max(STORE_REPORT_MAX_BYTES)),
+        )
+        .route("/store-reports/:report_id", get(store_reports::get_store_report))
+        .route("/store-reports/:report_id", delete(store_reports::delete_store_report))
+        .route("/store-reports/:report_id/discrepancies", get(store_reports::get_store_report_discrepancies))
+        
+        // Audit log routes
+        .route("/audit-log", get(audit::get_audit_log))
+        
+        // Wallet routes
+        .route("/users/:user_id/wallet", get(wallets::get_wallet))
+        .route("/users/:user_id/wallet/entries", get(wallets::get_wallet_entries))
+        .route("/users/:user_id/wallet/spend", post(wallets::spend))
+        .route("/users/:user_id/wallet/adjust", post(wallets::adjust))
         
         .layer(cors)
         .with_state(pool)
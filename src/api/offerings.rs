@@ -0,0 +1,409 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use sqlx::types::Json as DbJson;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::{App, Experiment, Offering, Package, Product, User};
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Serialize)]
+pub struct PackageProductResponse {
+    pub id: String,
+    pub name: String,
+    pub apple_product_id: Option<String>,
+    pub google_product_id: Option<String>,
+    pub type_: String,
+    pub price_usd: Option<f64>,
+    pub duration_days: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageResponse {
+    pub id: String,
+    pub identifier: String,
+    pub position: i32,
+    pub product: PackageProductResponse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OfferingResponse {
+    pub id: String,
+    pub app_id: String,
+    pub identifier: String,
+    pub description: Option<String>,
+    pub metadata: serde_json::Value,
+    pub is_current: bool,
+    pub packages: Vec<PackageResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<OfferingExperimentResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OfferingExperimentResponse {
+    pub experiment_id: String,
+    pub variant_id: String,
+    pub variant: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OfferingsResponse {
+    pub offerings: Vec<OfferingResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePackageRequest {
+    pub identifier: String,
+    pub product_id: String,
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOfferingRequest {
+    pub identifier: String,
+    pub description: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub packages: Vec<CreatePackageRequest>,
+    #[serde(default)]
+    pub is_current: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CurrentOfferingQuery {
+    pub app_user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOfferingRequest {
+    pub identifier: Option<String>,
+    pub description: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+// Paywall metadata is read by the app as a dictionary, so only accept objects
+fn validate_metadata(metadata: &serde_json::Value) -> Result<()> {
+    if !metadata.is_object() {
+        return Err(AppError::ValidationError(
+            "Offering metadata must be a JSON object".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn build_offering_response(offering: Offering, pool: &SqlitePool) -> Result<OfferingResponse> {
+    let packages = offering.get_packages(pool).await?;
+
+    let mut package_responses = Vec::new();
+
+    for package in packages {
+        let product = Product::find_by_id(&package.product_id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", package.product_id)))?;
+
+        package_responses.push(PackageResponse {
+            id: package.id,
+            identifier: package.identifier,
+            position: package.position,
+            product: PackageProductResponse {
+                id: product.id,
+                name: product.name,
+                apple_product_id: product.apple_product_id,
+                google_product_id: product.google_product_id,
+                type_: product.type_,
+                price_usd: product.price_usd,
+                duration_days: product.duration_days,
+            },
+        });
+    }
+
+    Ok(OfferingResponse {
+        id: offering.id,
+        app_id: offering.app_id,
+        identifier: offering.identifier,
+        description: offering.description,
+        metadata: offering.metadata.0,
+        is_current: offering.is_current,
+        packages: package_responses,
+        experiment: None,
+    })
+}
+
+async fn create_package(
+    offering: &Offering,
+    request: CreatePackageRequest,
+    default_position: i32,
+    pool: &SqlitePool,
+) -> Result<Package> {
+    // Check if the product exists
+    let _product = Product::find_by_id(&request.product_id, pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", request.product_id)))?;
+
+    let existing = offering.get_packages(pool).await?;
+    if existing.iter().any(|package| package.identifier == request.identifier) {
+        return Err(AppError::BadRequest(format!(
+            "Package {} already exists in offering {}",
+            request.identifier, offering.identifier
+        )));
+    }
+
+    let package = Package::new(
+        offering.id.clone(),
+        request.identifier,
+        request.product_id,
+        request.position.unwrap_or(default_position),
+    );
+
+    package.create(pool).await?;
+
+    Ok(package)
+}
+
+// Get all offerings for an app
+pub async fn get_app_offerings(
+    Path(app_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<OfferingsResponse>> {
+    let _app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    let limit = page_limit(page.limit)?;
+    check_cursor("offerings", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra offering to tell whether there's another page
+    let offerings = Offering::list_by_app(&app_id, page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (offerings, has_more) = split_page(offerings, limit);
+
+    let mut offering_responses = Vec::new();
+
+    for offering in offerings {
+        offering_responses.push(build_offering_response(offering, &pool).await?);
+    }
+
+    Ok(Json(OfferingsResponse {
+        offerings: offering_responses,
+        has_more,
+    }))
+}
+
+// Get the offering the app should currently show on its paywall.
+// When an experiment is running and app_user_id is given, the user is
+// bucketed into a variant and gets that variant's offering instead.
+pub async fn get_current_offering(
+    Path(app_id): Path<String>,
+    Query(query): Query<CurrentOfferingQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<OfferingResponse>> {
+    let _app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    if let Some(app_user_id) = query.app_user_id {
+        if let Some(experiment) = Experiment::find_running_for_app(&app_id, &pool).await? {
+            // Only known users get a stored assignment. Fetching a paywall
+            // shouldn't create users, so unknown IDs are bucketed on the fly.
+            let variant = match User::find_by_app_user_id(&app_user_id, &pool).await? {
+                Some(user) => experiment.assign_variant(&user, &pool).await?,
+                None => experiment.preview_variant(&app_user_id, &pool).await?,
+            };
+
+            if let Some(variant) = variant {
+                let offering = Offering::find_by_id(&variant.offering_id, &pool)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", variant.offering_id)))?;
+
+                let mut response = build_offering_response(offering, &pool).await?;
+                response.experiment = Some(OfferingExperimentResponse {
+                    experiment_id: experiment.id,
+                    variant_id: variant.id,
+                    variant: variant.identifier,
+                });
+
+                return Ok(Json(response));
+            }
+        }
+    }
+
+    let offering = Offering::find_current_for_app(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No current offering for app: {}", app_id)))?;
+
+    Ok(Json(build_offering_response(offering, &pool).await?))
+}
+
+// Create a new offering for an app
+pub async fn create_offering(
+    Path(app_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<CreateOfferingRequest>,
+) -> Result<(StatusCode, Json<OfferingResponse>)> {
+    let _app = App::find_by_id(&app_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("App not found: {}", app_id)))?;
+
+    // Check if an offering with this identifier already exists
+    if Offering::find_by_identifier(&app_id, &request.identifier, &pool).await?.is_some() {
+        return Err(AppError::BadRequest(format!(
+            "Offering with identifier {} already exists",
+            request.identifier
+        )));
+    }
+
+    let metadata = request.metadata.unwrap_or_else(|| serde_json::json!({}));
+    validate_metadata(&metadata)?;
+
+    // Validate every package before writing anything, so a bad package
+    // doesn't leave a half-built offering behind
+    let mut identifiers = std::collections::HashSet::new();
+    for package_request in &request.packages {
+        if !identifiers.insert(package_request.identifier.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "Package {} is listed more than once",
+                package_request.identifier
+            )));
+        }
+
+        Product::find_by_id(&package_request.product_id, &pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", package_request.product_id)))?;
+    }
+
+    let mut offering = Offering::new(app_id, request.identifier, request.description, metadata);
+    offering.create(&pool).await?;
+
+    // Add the packages in the order they were given
+    for (index, package_request) in request.packages.into_iter().enumerate() {
+        create_package(&offering, package_request, index as i32, &pool).await?;
+    }
+
+    if request.is_current {
+        offering.make_current(&pool).await?;
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(build_offering_response(offering, &pool).await?),
+    ))
+}
+
+// Get a specific offering
+pub async fn get_offering(
+    Path(offering_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<OfferingResponse>> {
+    let offering = Offering::find_by_id(&offering_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", offering_id)))?;
+
+    Ok(Json(build_offering_response(offering, &pool).await?))
+}
+
+// Update an offering
+pub async fn update_offering(
+    Path(offering_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<UpdateOfferingRequest>,
+) -> Result<Json<OfferingResponse>> {
+    let mut offering = Offering::find_by_id(&offering_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", offering_id)))?;
+
+    // Update fields if provided
+    if let Some(identifier) = request.identifier {
+        if identifier != offering.identifier
+            && Offering::find_by_identifier(&offering.app_id, &identifier, &pool).await?.is_some()
+        {
+            return Err(AppError::BadRequest(format!(
+                "Offering with identifier {} already exists",
+                identifier
+            )));
+        }
+
+        offering.identifier = identifier;
+    }
+
+    if let Some(description) = request.description {
+        offering.description = Some(description);
+    }
+
+    if let Some(metadata) = request.metadata {
+        validate_metadata(&metadata)?;
+        offering.metadata = DbJson(metadata);
+    }
+
+    offering.update(&pool).await?;
+
+    Ok(Json(build_offering_response(offering, &pool).await?))
+}
+
+// Make an offering the current one for its app
+pub async fn make_offering_current(
+    Path(offering_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<OfferingResponse>> {
+    let mut offering = Offering::find_by_id(&offering_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", offering_id)))?;
+
+    offering.make_current(&pool).await?;
+
+    Ok(Json(build_offering_response(offering, &pool).await?))
+}
+
+// Delete an offering
+pub async fn delete_offering(
+    Path(offering_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let offering = Offering::find_by_id(&offering_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", offering_id)))?;
+
+    offering.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Add a package to an offering
+pub async fn add_offering_package(
+    Path(offering_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<CreatePackageRequest>,
+) -> Result<(StatusCode, Json<OfferingResponse>)> {
+    let offering = Offering::find_by_id(&offering_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Offering not found: {}", offering_id)))?;
+
+    // New packages go to the end unless a position is given
+    let next_position = offering.get_packages(&pool).await?.len() as i32;
+    create_package(&offering, request, next_position, &pool).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(build_offering_response(offering, &pool).await?),
+    ))
+}
+
+// Remove a package from an offering
+pub async fn remove_offering_package(
+    Path((offering_id, package_id)): Path<(String, String)>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let package = Package::find_by_id(&package_id, &pool)
+        .await?
+        .filter(|package| package.offering_id == offering_id)
+        .ok_or_else(|| AppError::NotFound(format!("Package not found: {}", package_id)))?;
+
+    package.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,81 @@
+use serde::Deserialize;
+use sqlx::sqlite::SqlitePool;
+
+use crate::error::{AppError, Result};
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 100;
+pub const MAX_PAGE_LIMIT: i64 = 1000;
+
+// Query parameters for list endpoints with no filters of their own. Pass
+// `starting_after` the ID of the last item on a page to get the next one.
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub starting_after: Option<String>,
+}
+
+pub fn page_limit(limit: Option<i64>) -> Result<i64> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+
+    Ok(limit)
+}
+
+// Trim items fetched with one extra row down to the page, and report
+// whether there are more after it
+pub fn split_page<T>(mut items: Vec<T>, limit: i64) -> (Vec<T>, bool) {
+    let has_more = items.len() as i64 > limit;
+    items.truncate(limit as usize);
+    (items, has_more)
+}
+
+// Cursors are item IDs, so the page they start can only be found while the
+// item exists. Lists are read a page at a time with a keyset query on
+// `(sort key, rowid)` relative to the cursor's row.
+pub async fn check_cursor(table: &str, starting_after: Option<&str>, pool: &SqlitePool) -> Result<()> {
+    let Some(cursor) = starting_after else {
+        return Ok(());
+    };
+
+    let exists = sqlx::query_scalar::<_, bool>(&format!("SELECT EXISTS(SELECT 1 FROM {} WHERE id = ?)", table))
+        .bind(cursor)
+        .fetch_one(pool)
+        .await?;
+
+    if !exists {
+        return Err(AppError::BadRequest(format!("Unknown starting_after: {}", cursor)));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+
+    // How rows after the cursor compare to it
+    pub fn after(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        }
+    }
+}
@@ -1,12 +1,14 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqlitePool;
 
-use crate::db::models::{Product, ProductType, Entitlement};
+use crate::api::audit::AuditContext;
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::{AuditAction, AuditLogEntry, Product, ProductType, Entitlement};
 use crate::error::{AppError, Result};
 
 #[derive(Debug, Serialize)]
@@ -19,12 +21,17 @@ pub struct ProductResponse {
     pub type_: String,
     pub price_usd: Option<f64>,
     pub duration_days: Option<i32>,
+    pub virtual_currency: Option<String>,
+    pub virtual_currency_amount: Option<i64>,
+    pub subscription_group: Option<String>,
+    pub group_level: Option<i32>,
     pub entitlements: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ProductsResponse {
     pub products: Vec<ProductResponse>,
+    pub has_more: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,6 +43,10 @@ pub struct CreateProductRequest {
     pub type_: String,
     pub price_usd: Option<f64>,
     pub duration_days: Option<i32>,
+    pub virtual_currency: Option<String>,
+    pub virtual_currency_amount: Option<i64>,
+    pub subscription_group: Option<String>,
+    pub group_level: Option<i32>,
     pub entitlement_ids: Vec<String>,
 }
 
@@ -47,6 +58,10 @@ pub struct UpdateProductRequest {
     pub google_product_id: Option<String>,
     pub price_usd: Option<f64>,
     pub duration_days: Option<i32>,
+    pub virtual_currency: Option<String>,
+    pub virtual_currency_amount: Option<i64>,
+    pub subscription_group: Option<String>,
+    pub group_level: Option<i32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -56,9 +71,15 @@ pub struct AddEntitlementRequest {
 
 // Get all products
 pub async fn get_products(
+    Query(page): Query<PageQuery>,
     State(pool): State<SqlitePool>,
 ) -> Result<Json<ProductsResponse>> {
-    let products = Product::list_all(&pool).await?;
+    let limit = page_limit(page.limit)?;
+    check_cursor("products", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra product to tell whether there's another page
+    let products = Product::list(page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (products, has_more) = split_page(products, limit);
     
     let mut product_responses = Vec::new();
     
@@ -74,12 +95,17 @@ pub async fn get_products(
             type_: product.type_,
             price_usd: product.price_usd,
             duration_days: product.duration_days,
+            virtual_currency: product.virtual_currency,
+            virtual_currency_amount: product.virtual_currency_amount,
+            subscription_group: product.subscription_group,
+            group_level: product.group_level,
             entitlements,
         });
     }
     
     Ok(Json(ProductsResponse {
         products: product_responses,
+        has_more,
     }))
 }
 
@@ -103,6 +129,10 @@ pub async fn get_product(
         type_: product.type_,
         price_usd: product.price_usd,
         duration_days: product.duration_days,
+        virtual_currency: product.virtual_currency,
+        virtual_currency_amount: product.virtual_currency_amount,
+        subscription_group: product.subscription_group,
+        group_level: product.group_level,
         entitlements,
     }))
 }
@@ -110,31 +140,43 @@ pub async fn get_product(
 // Delete a product
 pub async fn delete_product(
     Path(product_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
 ) -> Result<StatusCode> {
     let product = Product::find_by_id(&product_id, &pool)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", product_id)))?;
     
-    product.delete(&pool).await?;
+    let mut tx = pool.begin().await?;
+
+    product.delete(&mut *tx).await?;
+
+    audit
+        .record(AuditLogEntry::new(AuditAction::ProductDeleted, "product", &product.id).before(&product), &mut *tx)
+        .await?;
+
+    tx.commit().await?;
     
     Ok(StatusCode::NO_CONTENT)
 }
 
 // Create a new product
 pub async fn create_product(
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<CreateProductRequest>,
 ) -> Result<(StatusCode, Json<ProductResponse>)> {
     // Parse product type
     let product_type = match request.type_.to_lowercase().as_str() {
         "subscription" => ProductType::Subscription,
+        "non_renewing" => ProductType::NonRenewing,
         "one_time" => ProductType::OneTime,
+        "consumable" => ProductType::Consumable,
         _ => return Err(AppError::BadRequest("Invalid product type".to_string())),
     };
     
     // Create the product
-    let product = Product::new(
+    let mut product = Product::new(
         request.name,
         request.description,
         request.apple_product_id,
@@ -143,24 +185,39 @@ pub async fn create_product(
         request.price_usd,
         request.duration_days,
     );
+    product.virtual_currency = request.virtual_currency;
+    product.virtual_currency_amount = request.virtual_currency_amount;
+    product.subscription_group = request.subscription_group;
+    product.group_level = request.group_level;
+    validate_virtual_currency(&product)?;
+    validate_duration(&product)?;
+    validate_subscription_group(&product)?;
     
-    product.create(&pool).await?;
-    
-    // Verify and add entitlements
+    // Check the entitlements exist before creating anything
     for entitlement_id in &request.entitlement_ids {
-        // Check if the entitlement exists
         let _entitlement = Entitlement::find_by_id(entitlement_id, &pool)
             .await?
             .ok_or_else(|| {
                 AppError::NotFound(format!("Entitlement not found: {}", entitlement_id))
             })?;
-        
-        // Add the entitlement to the product
-        product.add_entitlement(entitlement_id, &pool).await?;
     }
-    
+
+    let mut tx = pool.begin().await?;
+
+    product.create(&mut *tx).await?;
+
+    for entitlement_id in &request.entitlement_ids {
+        product.add_entitlement(entitlement_id, &mut *tx).await?;
+    }
+
     // Get all entitlements for response
-    let entitlements = product.get_entitlements(&pool).await?;
+    let entitlements = product.get_entitlements(&mut *tx).await?;
+
+    audit
+        .record(AuditLogEntry::new(AuditAction::ProductCreated, "product", &product.id).after(&product), &mut *tx)
+        .await?;
+
+    tx.commit().await?;
     
     Ok((
         StatusCode::CREATED,
@@ -173,6 +230,10 @@ pub async fn create_product(
             type_: product.type_,
             price_usd: product.price_usd,
             duration_days: product.duration_days,
+            virtual_currency: product.virtual_currency,
+            virtual_currency_amount: product.virtual_currency_amount,
+            subscription_group: product.subscription_group,
+            group_level: product.group_level,
             entitlements,
         }),
     ))
@@ -181,6 +242,7 @@ pub async fn create_product(
 // Update a product
 pub async fn update_product(
     Path(product_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<UpdateProductRequest>,
 ) -> Result<Json<ProductResponse>> {
@@ -188,6 +250,8 @@ pub async fn update_product(
         .await?
         .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", product_id)))?;
     
+    let entry = AuditLogEntry::new(AuditAction::ProductUpdated, "product", &product.id).before(&product);
+    
     // Update fields if provided
     if let Some(name) = request.name {
         product.name = name;
@@ -213,7 +277,32 @@ pub async fn update_product(
         product.duration_days = Some(duration_days);
     }
     
-    product.update(&pool).await?;
+    if let Some(virtual_currency) = request.virtual_currency {
+        product.virtual_currency = Some(virtual_currency);
+    }
+    
+    if let Some(virtual_currency_amount) = request.virtual_currency_amount {
+        product.virtual_currency_amount = Some(virtual_currency_amount);
+    }
+    
+    if let Some(subscription_group) = request.subscription_group {
+        product.subscription_group = Some(subscription_group);
+    }
+    
+    if let Some(group_level) = request.group_level {
+        product.group_level = Some(group_level);
+    }
+    
+    validate_virtual_currency(&product)?;
+    validate_duration(&product)?;
+    validate_subscription_group(&product)?;
+    
+    let mut tx = pool.begin().await?;
+
+    product.update(&mut *tx).await?;
+    audit.record(entry.after(&product), &mut *tx).await?;
+
+    tx.commit().await?;
     
     let entitlements = product.get_entitlements(&pool).await?;
     
@@ -226,13 +315,69 @@ pub async fn update_product(
         type_: product.type_,
         price_usd: product.price_usd,
         duration_days: product.duration_days,
+        virtual_currency: product.virtual_currency,
+        virtual_currency_amount: product.virtual_currency_amount,
+        subscription_group: product.subscription_group,
+        group_level: product.group_level,
         entitlements,
     }))
 }
 
+// Consumables must grant a positive amount of a named currency, and only
+// consumables can grant currency
+fn validate_virtual_currency(product: &Product) -> Result<()> {
+    if product.type_ == ProductType::Consumable.to_string() {
+        match (&product.virtual_currency, product.virtual_currency_amount) {
+            (Some(currency), Some(amount)) if !currency.trim().is_empty() && amount > 0 => Ok(()),
+            _ => Err(AppError::ValidationError(
+                "Consumable products need a virtual_currency and a positive virtual_currency_amount".to_string(),
+            )),
+        }
+    } else if product.virtual_currency.is_some() || product.virtual_currency_amount.is_some() {
+        Err(AppError::ValidationError(
+            "Only consumable products can grant virtual currency".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Non-renewing products grant access for a fixed number of days
+fn validate_duration(product: &Product) -> Result<()> {
+    let is_non_renewing = product.type_ == ProductType::NonRenewing.to_string();
+
+    if is_non_renewing && product.duration_days.is_none_or(|days| days <= 0) {
+        return Err(AppError::ValidationError(
+            "Non-renewing products need a positive duration_days".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Products in a subscription group are ranked by level, with 1 the highest
+fn validate_subscription_group(product: &Product) -> Result<()> {
+    match (&product.subscription_group, product.group_level) {
+        (None, None) => Ok(()),
+        (Some(_), Some(level)) if level >= 1 => {
+            if product.type_ == ProductType::Subscription.to_string() {
+                Ok(())
+            } else {
+                Err(AppError::ValidationError(
+                    "Only subscription products can be in a subscription group".to_string(),
+                ))
+            }
+        }
+        _ => Err(AppError::ValidationError(
+            "Subscription groups need both a subscription_group and a group_level of at least 1".to_string(),
+        )),
+    }
+}
+
 // Add an entitlement to a product
 pub async fn add_product_entitlement(
     Path(product_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<AddEntitlementRequest>,
 ) -> Result<StatusCode> {
@@ -248,7 +393,15 @@ pub async fn add_product_entitlement(
         })?;
     
     // Add the entitlement to the product
-    product.add_entitlement(&request.entitlement_id, &pool).await?;
+    let mut tx = pool.begin().await?;
+
+    product.add_entitlement(&request.entitlement_id, &mut *tx).await?;
+
+    let entry = AuditLogEntry::new(AuditAction::ProductEntitlementAdded, "product", &product.id)
+        .after(&serde_json::json!({ "entitlement_id": request.entitlement_id }));
+    audit.record(entry, &mut *tx).await?;
+
+    tx.commit().await?;
     
     Ok(StatusCode::OK)
 }
@@ -256,6 +409,7 @@ pub async fn add_product_entitlement(
 // Remove an entitlement from a product
 pub async fn remove_product_entitlement(
     Path((product_id, entitlement_id)): Path<(String, String)>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
 ) -> Result<StatusCode> {
     let product = Product::find_by_id(&product_id, &pool)
@@ -268,7 +422,15 @@ pub async fn remove_product_entitlement(
         .ok_or_else(|| AppError::NotFound(format!("Entitlement not found: {}", entitlement_id)))?;
     
     // Remove the entitlement from the product
-    product.remove_entitlement(&entitlement_id, &pool).await?;
+    let mut tx = pool.begin().await?;
+
+    product.remove_entitlement(&entitlement_id, &mut *tx).await?;
+
+    let entry = AuditLogEntry::new(AuditAction::ProductEntitlementRemoved, "product", &product.id)
+        .before(&serde_json::json!({ "entitlement_id": entitlement_id }));
+    audit.record(entry, &mut *tx).await?;
+
+    tx.commit().await?;
     
     Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,361 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::audit::AuditContext;
+use crate::api::entitlements::{grant_promotional, GrantResponse, GrantTerms};
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::{
+    AuditAction, AuditLogEntry, Entitlement, GrantDuration, PromoCode, PromoCodeBatch, PromoCodeBatchStats,
+    PromoCodeRedemption, User,
+};
+use crate::error::{AppError, Result};
+use crate::utils::csv;
+
+// Most codes one request can generate
+const MAX_GENERATED_CODES: usize = 10_000;
+
+#[derive(Debug, Serialize)]
+pub struct PromoCodeBatchResponse {
+    pub id: String,
+    pub name: String,
+    pub entitlement_id: String,
+    pub duration: String,
+    pub max_redemptions: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromoCodeBatchesResponse {
+    pub batches: Vec<PromoCodeBatchResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromoCodeResponse {
+    pub id: String,
+    pub code: String,
+    pub redemption_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromoCodesResponse {
+    pub codes: Vec<PromoCodeResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromoCodeBatchStatsResponse {
+    pub batch_id: String,
+    #[serde(flatten)]
+    pub stats: PromoCodeBatchStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RedeemPromoCodeResponse {
+    pub batch_id: String,
+    pub code: String,
+    pub grant: GrantResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePromoCodeBatchRequest {
+    pub name: String,
+    pub entitlement_id: String,
+    pub duration: GrantDuration,
+    pub max_redemptions: Option<i64>,  // Per code, defaults to 1
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateCodesRequest {
+    pub count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedeemPromoCodeRequest {
+    pub user_id: String,
+    pub code: String,
+}
+
+impl From<PromoCodeBatch> for PromoCodeBatchResponse {
+    fn from(batch: PromoCodeBatch) -> Self {
+        Self {
+            id: batch.id,
+            name: batch.name,
+            entitlement_id: batch.entitlement_id,
+            duration: batch.duration,
+            max_redemptions: batch.max_redemptions,
+            expires_at: batch.expires_at,
+            created_at: batch.created_at,
+        }
+    }
+}
+
+impl From<PromoCode> for PromoCodeResponse {
+    fn from(code: PromoCode) -> Self {
+        Self {
+            id: code.id,
+            code: code.code,
+            redemption_count: code.redemption_count,
+            created_at: code.created_at,
+        }
+    }
+}
+
+async fn find_batch(batch_id: &str, pool: &SqlitePool) -> Result<PromoCodeBatch> {
+    PromoCodeBatch::find_by_id(batch_id, pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Promo code batch not found: {}", batch_id)))
+}
+
+// Get all promo code batches, newest first
+pub async fn get_promo_code_batches(
+    Query(query): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<PromoCodeBatchesResponse>> {
+    let limit = page_limit(query.limit)?;
+    check_cursor("promo_code_batches", query.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra batch to tell whether there's another page
+    let batches = PromoCodeBatch::list(query.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (batches, has_more) = split_page(batches, limit);
+
+    Ok(Json(PromoCodeBatchesResponse {
+        batches: batches.into_iter().map(PromoCodeBatchResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Create a batch of promo codes for an entitlement. Codes are added to it
+// separately.
+pub async fn create_promo_code_batch(
+    audit: AuditContext,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<CreatePromoCodeBatchRequest>,
+) -> Result<(StatusCode, Json<PromoCodeBatchResponse>)> {
+    if request.name.trim().is_empty() {
+        return Err(AppError::ValidationError("name can't be empty".to_string()));
+    }
+
+    let max_redemptions = request.max_redemptions.unwrap_or(1);
+    if max_redemptions < 1 {
+        return Err(AppError::ValidationError("max_redemptions must be at least 1".to_string()));
+    }
+
+    let _entitlement = Entitlement::find_by_id(&request.entitlement_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Entitlement not found: {}", request.entitlement_id)))?;
+
+    let batch = PromoCodeBatch::new(
+        request.name,
+        request.entitlement_id,
+        request.duration,
+        max_redemptions,
+        request.expires_at,
+    );
+    let mut tx = pool.begin().await?;
+
+    batch.create(&mut *tx).await?;
+
+    let entry = AuditLogEntry::new(AuditAction::PromoCodeBatchCreated, "promo_code_batch", &batch.id).after(&batch);
+    audit.record(entry, &mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(PromoCodeBatchResponse::from(batch))))
+}
+
+// Get a promo code batch
+pub async fn get_promo_code_batch(
+    Path(batch_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<PromoCodeBatchResponse>> {
+    let batch = find_batch(&batch_id, &pool).await?;
+
+    Ok(Json(PromoCodeBatchResponse::from(batch)))
+}
+
+// Add unique random codes to a batch
+pub async fn generate_promo_codes(
+    Path(batch_id): Path<String>,
+    audit: AuditContext,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<GenerateCodesRequest>,
+) -> Result<(StatusCode, Json<PromoCodesResponse>)> {
+    if !(1..=MAX_GENERATED_CODES).contains(&request.count) {
+        return Err(AppError::ValidationError(format!(
+            "count must be between 1 and {}",
+            MAX_GENERATED_CODES
+        )));
+    }
+
+    let batch = find_batch(&batch_id, &pool).await?;
+    let mut tx = pool.begin().await?;
+
+    let codes = batch.generate_codes(request.count, &mut tx).await?;
+
+    let entry = AuditLogEntry::new(AuditAction::PromoCodesGenerated, "promo_code_batch", &batch.id)
+        .after(&serde_json::json!({ "count": codes.len() }));
+    audit.record(entry, &mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PromoCodesResponse {
+            codes: codes.into_iter().map(PromoCodeResponse::from).collect(),
+            has_more: false,
+        }),
+    ))
+}
+
+// Get a batch's codes in the order they were generated
+pub async fn get_promo_codes(
+    Path(batch_id): Path<String>,
+    Query(query): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<PromoCodesResponse>> {
+    let batch = find_batch(&batch_id, &pool).await?;
+    let limit = page_limit(query.limit)?;
+
+    if let Some(cursor) = &query.starting_after {
+        if PromoCode::find_by_id(cursor, &pool).await?.is_none_or(|code| code.batch_id != batch.id) {
+            return Err(AppError::BadRequest(format!("Unknown starting_after: {}", cursor)));
+        }
+    }
+
+    // Fetch one extra code to tell whether there's another page
+    let codes = PromoCode::list_by_batch(&batch.id, query.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (codes, has_more) = split_page(codes, limit);
+
+    Ok(Json(PromoCodesResponse {
+        codes: codes.into_iter().map(PromoCodeResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Export all of a batch's codes as CSV, for handing out
+pub async fn export_promo_codes_csv(
+    Path(batch_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse> {
+    let batch = find_batch(&batch_id, &pool).await?;
+    let codes = PromoCode::list_by_batch(&batch.id, None, i64::MAX, &pool).await?;
+
+    let mut output = String::new();
+    csv::write_row(&mut output, &["code", "redemption_count", "max_redemptions", "created_at"]);
+
+    for code in codes {
+        csv::write_row(
+            &mut output,
+            &[
+                code.code,
+                code.redemption_count.to_string(),
+                batch.max_redemptions.to_string(),
+                code.created_at.to_rfc3339(),
+            ],
+        );
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"promo-codes-{}.csv\"", batch.id),
+            ),
+        ],
+        output,
+    ))
+}
+
+// Get how many of a batch's codes have been redeemed
+pub async fn get_promo_code_batch_stats(
+    Path(batch_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<PromoCodeBatchStatsResponse>> {
+    let batch = find_batch(&batch_id, &pool).await?;
+    let stats = batch.stats(&pool).await?;
+
+    Ok(Json(PromoCodeBatchStatsResponse {
+        batch_id: batch.id,
+        stats,
+    }))
+}
+
+// Redeem a code for a user, granting the batch's entitlement as a
+// promotional grant, or extending the one they already have
+pub async fn redeem_promo_code(
+    audit: AuditContext,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<RedeemPromoCodeRequest>,
+) -> Result<Json<RedeemPromoCodeResponse>> {
+    let code = PromoCode::find_by_code(&request.code, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Promo code not found: {}", request.code)))?;
+
+    let batch = find_batch(&code.batch_id, &pool).await?;
+
+    if batch.is_expired(Utc::now()) {
+        return Err(AppError::BadRequest(format!("Promo code has expired: {}", code.code)));
+    }
+
+    let user = User::find_by_id(&request.user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", request.user_id)))?;
+
+    let already_redeemed = || {
+        AppError::BadRequest(format!(
+            "User has already redeemed a code from this batch: {}",
+            batch.id
+        ))
+    };
+
+    if PromoCodeRedemption::find_by_batch_and_user(&batch.id, &user.id, &pool).await?.is_some() {
+        return Err(already_redeemed());
+    }
+
+    let duration = batch.grant_duration().ok_or_else(|| {
+        AppError::InternalServerError(format!("Unknown duration on promo code batch: {}", batch.duration))
+    })?;
+
+    // The code is only used up if the grant is saved with it
+    let mut tx = pool.begin().await?;
+
+    let mut redemption = match code.redeem(&user.id, batch.max_redemptions, &mut tx).await {
+        Ok(Some(redemption)) => redemption,
+        Ok(None) => {
+            return Err(AppError::BadRequest(format!("Promo code has been fully redeemed: {}", code.code)));
+        }
+        // Another request by the same user got in first
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Err(already_redeemed()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let terms = GrantTerms {
+        entitlement_id: batch.entitlement_id.clone(),
+        duration: Some(duration),
+        expires_at: None,
+        starts_at: None,
+        reason: Some(format!("Redeemed promo code {} from {}", code.code, batch.name)),
+    };
+    let (grant, extended) = grant_promotional(&user.id, &terms, &audit, &mut tx).await?;
+    redemption.set_user_entitlement(&grant.id, &mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(RedeemPromoCodeResponse {
+        batch_id: batch.id,
+        code: code.code,
+        grant: GrantResponse::new(grant, extended),
+    }))
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery};
+use crate::db::models::StoreReport;
+use crate::error::{AppError, Result};
+use crate::reports::{self, Reconciliation};
+
+#[derive(Debug, Serialize)]
+pub struct StoreReportResponse {
+    pub id: String,
+    pub store: String,
+    pub report_type: String,
+    pub filename: Option<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub row_count: i64,
+    pub skipped_count: i64,
+    pub imported_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoreReportsResponse {
+    pub reports: Vec<StoreReportResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportStoreReportResponse {
+    pub report: StoreReportResponse,
+    pub reconciliation: Reconciliation,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportStoreReportQuery {
+    pub filename: Option<String>,
+}
+
+impl From<StoreReport> for StoreReportResponse {
+    fn from(report: StoreReport) -> Self {
+        Self {
+            id: report.id,
+            store: report.store,
+            report_type: report.report_type,
+            filename: report.filename,
+            start_date: report.start_date,
+            end_date: report.end_date,
+            row_count: report.row_count,
+            skipped_count: report.skipped_count,
+            imported_at: report.imported_at,
+        }
+    }
+}
+
+// Get all imported store reports
+pub async fn get_store_reports(
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<StoreReportsResponse>> {
+    let limit = page_limit(page.limit)?;
+    check_cursor("store_reports", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra report to tell whether there's another page
+    let reports = StoreReport::list(page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (reports, has_more) = split_page(reports, limit);
+
+    Ok(Json(StoreReportsResponse {
+        reports: reports.into_iter().map(StoreReportResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Import a store report sent as the request body. The format is detected
+// from the report's columns.
+pub async fn import_store_report(
+    Query(query): Query<ImportStoreReportQuery>,
+    State(pool): State<SqlitePool>,
+    body: String,
+) -> Result<(StatusCode, Json<ImportStoreReportResponse>)> {
+    let (report, reconciliation) = reports::import_report(&body, query.filename, &pool).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ImportStoreReportResponse {
+            report: StoreReportResponse::from(report),
+            reconciliation,
+        }),
+    ))
+}
+
+// Get a specific store report
+pub async fn get_store_report(
+    Path(report_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<StoreReportResponse>> {
+    let report = StoreReport::find_by_id(&report_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Store report not found: {}", report_id)))?;
+
+    Ok(Json(StoreReportResponse::from(report)))
+}
+
+// Match the report against the current ledger and list the differences.
+// Running this again picks up transactions recorded since the import.
+pub async fn get_store_report_discrepancies(
+    Path(report_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Reconciliation>> {
+    let report = StoreReport::find_by_id(&report_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Store report not found: {}", report_id)))?;
+
+    Ok(Json(reports::reconcile(&report, &pool).await?))
+}
+
+// Delete a store report and its rows
+pub async fn delete_store_report(
+    Path(report_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode> {
+    let report = StoreReport::find_by_id(&report_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Store report not found: {}", report_id)))?;
+
+    report.delete(&pool).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
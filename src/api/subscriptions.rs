@@ -1,13 +1,19 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqlitePool;
+use std::collections::BTreeMap;
 
-use crate::db::models::{Subscription, SubscriptionStatus, UserEntitlement};
+use crate::api::audit::AuditContext;
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery, SortOrder};
+use crate::db::models::{
+    AuditAction, AuditLogEntry, Subscription, SubscriptionEvent, SubscriptionStatus, Transaction, TransactionType,
+    User, UserEntitlement, WalletEntry,
+};
 use crate::error::{AppError, Result};
 
 #[derive(Debug, Serialize)]
@@ -28,37 +34,82 @@ pub struct SubscriptionDetailResponse {
     pub currency: Option<String>,
     pub is_trial: bool,
     pub is_intro_offer: bool,
+    pub country_code: Option<String>,
+    pub pending_product_id: Option<String>,
+    pub previous_subscription_id: Option<String>,
+    pub auto_resume_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub billing_issue_detected_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub ownership_type: String,
+    pub family_owner_subscription_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionEventResponse {
+    pub id: String,
+    pub subscription_id: String,
+    pub type_: String,
+    pub from_product_id: Option<String>,
+    pub to_product_id: Option<String>,
+    pub change_type: Option<String>,
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    pub effective_date: chrono::DateTime<chrono::Utc>,
+    pub subscriber_attributes: BTreeMap<String, String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionEventsResponse {
+    pub events: Vec<SubscriptionEventResponse>,
+    pub has_more: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SubscriptionsResponse {
     pub subscriptions: Vec<SubscriptionDetailResponse>,
+    pub has_more: bool,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct CancelSubscriptionRequest {
-    pub cancellation_date: Option<chrono::DateTime<chrono::Utc>>,
+pub struct SubscriptionsQuery {
+    pub status: Option<String>,
+    pub store: Option<String>,
+    pub product_id: Option<String>,
+    pub auto_renew_status: Option<bool>,
+    pub purchased_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub purchased_before: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_before: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub order: SortOrder,  // By purchase date, newest first by default
+    pub limit: Option<i64>,
+    pub starting_after: Option<String>,
 }
 
-// Get all subscriptions (with pagination)
-pub async fn get_subscriptions(
-    State(pool): State<SqlitePool>,
-) -> Result<Json<SubscriptionsResponse>> {
-    // In a real application, you'd implement pagination
-    // For simplicity, we'll just limit to the first 100 subscriptions
-    let subscriptions = sqlx::query_as::<_, Subscription>(
-        r#"
-        SELECT * FROM subscriptions
-        ORDER BY purchase_date DESC
-        LIMIT 100
-        "#,
-    )
-    .fetch_all(&pool)
-    .await?;
-    
-    let subscription_responses = subscriptions
-        .into_iter()
-        .map(|subscription| SubscriptionDetailResponse {
+impl From<SubscriptionEvent> for SubscriptionEventResponse {
+    fn from(event: SubscriptionEvent) -> Self {
+        Self {
+            id: event.id,
+            subscription_id: event.subscription_id,
+            type_: event.type_,
+            from_product_id: event.from_product_id,
+            to_product_id: event.to_product_id,
+            change_type: event.change_type,
+            price: event.price,
+            currency: event.currency,
+            effective_date: event.effective_date,
+            subscriber_attributes: event
+                .subscriber_attributes
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+            created_at: event.created_at,
+        }
+    }
+}
+
+impl From<Subscription> for SubscriptionDetailResponse {
+    fn from(subscription: Subscription) -> Self {
+        Self {
             id: subscription.id,
             user_id: subscription.user_id,
             product_id: subscription.product_id,
@@ -75,11 +126,110 @@ pub async fn get_subscriptions(
             currency: subscription.currency,
             is_trial: subscription.is_trial,
             is_intro_offer: subscription.is_intro_offer,
-        })
+            country_code: subscription.country_code,
+            pending_product_id: subscription.pending_product_id,
+            previous_subscription_id: subscription.previous_subscription_id,
+            auto_resume_date: subscription.auto_resume_date,
+            billing_issue_detected_at: subscription.billing_issue_detected_at,
+            ownership_type: subscription.ownership_type,
+            family_owner_subscription_id: subscription.family_owner_subscription_id,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelSubscriptionRequest {
+    pub cancellation_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Get a page of subscriptions, optionally filtered
+pub async fn get_subscriptions(
+    Query(query): Query<SubscriptionsQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<SubscriptionsResponse>> {
+    let limit = page_limit(query.limit)?;
+    
+    if let Some(cursor) = &query.starting_after {
+        if Subscription::find_by_id(cursor, &pool).await?.is_none() {
+            return Err(AppError::BadRequest(format!("Unknown starting_after: {}", cursor)));
+        }
+    }
+    
+    // Fetch one extra subscription to tell whether there's another page
+    let subscriptions = sqlx::query_as::<_, Subscription>(&format!(
+        r#"
+        SELECT * FROM subscriptions
+        WHERE (? IS NULL OR status = ?)
+          AND (? IS NULL OR store = ?)
+          AND (? IS NULL OR product_id = ?)
+          AND (? IS NULL OR auto_renew_status = ?)
+          AND (? IS NULL OR purchase_date >= ?)
+          AND (? IS NULL OR purchase_date < ?)
+          AND (? IS NULL OR expires_date >= ?)
+          AND (? IS NULL OR expires_date < ?)
+          AND (? IS NULL OR (purchase_date, rowid) {after} (SELECT purchase_date, rowid FROM subscriptions WHERE id = ?))
+        ORDER BY purchase_date {order}, rowid {order}
+        LIMIT ?
+        "#,
+        after = query.order.after(),
+        order = query.order.sql(),
+    ))
+    .bind(&query.status)
+    .bind(&query.status)
+    .bind(&query.store)
+    .bind(&query.store)
+    .bind(&query.product_id)
+    .bind(&query.product_id)
+    .bind(query.auto_renew_status)
+    .bind(query.auto_renew_status)
+    .bind(query.purchased_after)
+    .bind(query.purchased_after)
+    .bind(query.purchased_before)
+    .bind(query.purchased_before)
+    .bind(query.expires_after)
+    .bind(query.expires_after)
+    .bind(query.expires_before)
+    .bind(query.expires_before)
+    .bind(&query.starting_after)
+    .bind(&query.starting_after)
+    .bind(limit + 1)
+    .fetch_all(&pool)
+    .await?;
+    
+    let (subscriptions, has_more) = split_page(subscriptions, limit);
+    
+    let subscription_responses = subscriptions
+        .into_iter()
+        .map(SubscriptionDetailResponse::from)
         .collect();
     
     Ok(Json(SubscriptionsResponse {
         subscriptions: subscription_responses,
+        has_more,
+    }))
+}
+
+// Get subscriptions whose renewal payment is failing, so their users can be
+// asked to update their payment method
+pub async fn get_subscriptions_with_billing_issues(
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<SubscriptionsResponse>> {
+    let limit = page_limit(page.limit)?;
+    check_cursor("subscriptions", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra subscription to tell whether there's another page
+    let subscriptions = Subscription::list_with_billing_issues(
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (subscriptions, has_more) = split_page(subscriptions, limit);
+    
+    Ok(Json(SubscriptionsResponse {
+        subscriptions: subscriptions.into_iter().map(SubscriptionDetailResponse::from).collect(),
+        has_more,
     }))
 }
 
@@ -92,29 +242,71 @@ pub async fn get_subscription(
         .await?
         .ok_or_else(|| AppError::NotFound(format!("Subscription not found: {}", subscription_id)))?;
     
-    Ok(Json(SubscriptionDetailResponse {
-        id: subscription.id,
-        user_id: subscription.user_id,
-        product_id: subscription.product_id,
-        original_transaction_id: subscription.original_transaction_id,
-        store_transaction_id: subscription.store_transaction_id,
-        store: subscription.store,
-        purchase_date: subscription.purchase_date,
-        expires_date: subscription.expires_date,
-        cancellation_date: subscription.cancellation_date,
-        renewal_grace_period_expires_date: subscription.renewal_grace_period_expires_date,
-        status: subscription.status,
-        auto_renew_status: subscription.auto_renew_status,
-        price_paid: subscription.price_paid,
-        currency: subscription.currency,
-        is_trial: subscription.is_trial,
-        is_intro_offer: subscription.is_intro_offer,
+    Ok(Json(SubscriptionDetailResponse::from(subscription)))
+}
+
+// Get a subscription's lifecycle events, such as product changes
+pub async fn get_subscription_events(
+    Path(subscription_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<SubscriptionEventsResponse>> {
+    let subscription = Subscription::find_by_id(&subscription_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Subscription not found: {}", subscription_id)))?;
+    
+    let limit = page_limit(page.limit)?;
+    check_cursor("subscription_events", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra event to tell whether there's another page
+    let events = SubscriptionEvent::list_by_subscription(
+        &subscription.id,
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (events, has_more) = split_page(events, limit);
+    
+    Ok(Json(SubscriptionEventsResponse {
+        events: events.into_iter().map(SubscriptionEventResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Get the lifecycle events of all of a user's subscriptions
+pub async fn get_user_subscription_events(
+    Path(user_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<SubscriptionEventsResponse>> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+    
+    let limit = page_limit(page.limit)?;
+    check_cursor("subscription_events", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra event to tell whether there's another page
+    let events = SubscriptionEvent::list_by_user(
+        &user.id,
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (events, has_more) = split_page(events, limit);
+    
+    Ok(Json(SubscriptionEventsResponse {
+        events: events.into_iter().map(SubscriptionEventResponse::from).collect(),
+        has_more,
     }))
 }
 
 // Cancel a subscription
 pub async fn cancel_subscription(
     Path(subscription_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<CancelSubscriptionRequest>,
 ) -> Result<StatusCode> {
@@ -133,8 +325,17 @@ pub async fn cancel_subscription(
     // Use provided cancellation date or current time
     let cancellation_date = request.cancellation_date.unwrap_or_else(Utc::now);
     
+    let entry = AuditLogEntry::new(AuditAction::SubscriptionCanceled, "subscription", &subscription.id)
+        .for_user(&subscription.user_id)
+        .before(&subscription);
+    
+    let mut tx = pool.begin().await?;
+
     // Cancel the subscription
-    subscription.cancel(cancellation_date, &pool).await?;
+    subscription.cancel(cancellation_date, &mut *tx).await?;
+    audit.record(entry.after(&subscription), &mut *tx).await?;
+
+    tx.commit().await?;
     
     // Note: We don't immediately revoke entitlements on cancellation
     // They remain active until the expiration date
@@ -145,29 +346,51 @@ pub async fn cancel_subscription(
 // Refund a subscription
 pub async fn refund_subscription(
     Path(subscription_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
 ) -> Result<StatusCode> {
     let mut subscription = Subscription::find_by_id(&subscription_id, &pool)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("Subscription not found: {}", subscription_id)))?;
     
+    let entry = AuditLogEntry::new(AuditAction::SubscriptionRefunded, "subscription", &subscription.id)
+        .for_user(&subscription.user_id)
+        .before(&subscription);
+    
+    let mut tx = pool.begin().await?;
+    
     // Update subscription status
-    subscription.update_status(SubscriptionStatus::Refunded, &pool).await?;
+    subscription.update_status(SubscriptionStatus::Refunded, &mut *tx).await?;
+    
+    // Record the refund in the transaction ledger
+    Transaction::for_subscription(&subscription, TransactionType::Refund, Utc::now())
+        .create(&mut tx)
+        .await?;
+    
+    // Take back any virtual currency the purchase credited
+    WalletEntry::reverse_purchase(&subscription, &mut tx).await?;
+    
+    // Give back the unused part of a non-renewing period to later purchases
+    subscription.roll_back_period(Utc::now(), &mut tx).await?;
     
     // Revoke user entitlements immediately
     let user_entitlements = UserEntitlement::list_active_for_user(
         &subscription.user_id, 
         Utc::now(), 
-        &pool
+        &mut *tx
     ).await?;
     
     for mut entitlement in user_entitlements {
         if let Some(sub_id) = &entitlement.subscription_id {
             if sub_id == &subscription.id {
-                entitlement.revoke(&pool).await?;
+                entitlement.revoke(&mut *tx).await?;
             }
         }
     }
     
+    audit.record(entry.after(&subscription), &mut *tx).await?;
+    
+    tx.commit().await?;
+    
     Ok(StatusCode::OK)
 }
@@ -1,24 +1,48 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use std::collections::BTreeMap;
 
-use crate::db::models::{User, Subscription, SubscriptionStatus};
+use crate::api::attributes::attribute_values;
+use crate::api::audit::AuditContext;
+use crate::api::pagination::{check_cursor, page_limit, split_page, PageQuery, SortOrder};
+use crate::db::models::{
+    AuditAction, AuditLogEntry, User, UserAlias, UserAttribute, Subscription, SubscriptionStatus, ANONYMOUS_ID_PREFIX, EMAIL_ATTRIBUTE,
+};
 use crate::error::{AppError, Result};
 
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: String,
     pub app_user_id: String,
+    pub is_anonymous: bool,
     pub email: Option<String>,
+    pub consumption_consent: bool,
+    pub play_time_minutes: Option<i64>,
+    pub attributes: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct UsersResponse {
     pub users: Vec<UserResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsersQuery {
+    pub email: Option<String>,
+    pub created_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+    pub attribute: Option<String>,  // Only users with this attribute set
+    pub attribute_value: Option<String>,  // ...to this value
+    #[serde(default)]
+    pub order: SortOrder,  // By creation date, newest first by default
+    pub limit: Option<i64>,
+    pub starting_after: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,11 +54,13 @@ pub struct SubscriptionResponse {
     pub expires_date: Option<chrono::DateTime<chrono::Utc>>,
     pub status: String,
     pub auto_renew_status: Option<bool>,
+    pub ownership_type: String,
 }
 
 #[derive(Debug, Serialize)]
 pub struct UserSubscriptionsResponse {
     pub subscriptions: Vec<SubscriptionResponse>,
+    pub has_more: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -43,38 +69,165 @@ pub struct CreateUserRequest {
     pub email: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub app_user_id: String,  // The ID the app has been using, often anonymous
+    pub new_app_user_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    pub user: UserResponse,
+    pub created: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAliasRequest {
+    pub alias: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasResponse {
+    pub alias: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasesResponse {
+    pub aliases: Vec<AliasResponse>,
+    pub has_more: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UpdateUserRequest {
     pub email: Option<String>,
+    pub consumption_consent: Option<bool>,
+    pub play_time_minutes: Option<i64>,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            is_anonymous: user.is_anonymous(),
+            id: user.id,
+            app_user_id: user.app_user_id,
+            email: user.email,
+            consumption_consent: user.consumption_consent,
+            play_time_minutes: user.play_time_minutes,
+            attributes: BTreeMap::new(),
+        }
+    }
+}
+
+impl UserResponse {
+    // Build the response along with the user's attributes
+    async fn load(user: User, pool: &SqlitePool) -> Result<Self> {
+        let attributes = UserAttribute::list_by_user(&user.id, pool).await?;
+
+        let mut response = Self::from(user);
+        response.attributes = attribute_values(attributes);
+
+        Ok(response)
+    }
 }
 
-// Get all users
+impl From<UserAlias> for AliasResponse {
+    fn from(alias: UserAlias) -> Self {
+        Self {
+            alias: alias.alias,
+            created_at: alias.created_at,
+        }
+    }
+}
+
+// Anonymous IDs must be `$anon:` followed by a UUID
+fn validate_app_user_id(app_user_id: &str) -> Result<()> {
+    if app_user_id.starts_with(ANONYMOUS_ID_PREFIX) && !User::is_anonymous_id(app_user_id) {
+        return Err(AppError::ValidationError(format!(
+            "Anonymous app user IDs must be {}<uuid>: {}",
+            ANONYMOUS_ID_PREFIX, app_user_id
+        )));
+    }
+
+    Ok(())
+}
+
+// Merge an anonymous user into another user. A purchase can't be owned
+// twice, so merging is refused if both have the same purchase recorded.
+async fn merge_anonymous_user(anonymous: &User, target: &mut User, pool: &SqlitePool) -> Result<()> {
+    if anonymous.shares_purchases_with(target, pool).await? {
+        return Err(AppError::ValidationError(format!(
+            "Users {} and {} have the same purchase recorded and can't be merged",
+            anonymous.app_user_id, target.app_user_id
+        )));
+    }
+
+    anonymous.merge_into(target, pool).await?;
+
+    Ok(())
+}
+
+// Record the user's email as the email attribute too, so both agree
+async fn set_email_attribute(user: &User, executor: impl SqliteExecutor<'_>) -> Result<()> {
+    UserAttribute::new(user.id.clone(), EMAIL_ATTRIBUTE.to_string(), user.email.clone(), chrono::Utc::now())
+        .set(executor)
+        .await?;
+
+    Ok(())
+}
+
+// Get a page of users, optionally filtered by email, creation date or attribute
 pub async fn get_users(
+    Query(query): Query<UsersQuery>,
     State(pool): State<SqlitePool>,
 ) -> Result<Json<UsersResponse>> {
-    // In a real application, you'd implement pagination
-    // For simplicity, we'll just limit to the first 100 users
-    let users = sqlx::query_as::<_, User>(
+    let limit = page_limit(query.limit)?;
+    check_cursor("users", query.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra user to tell whether there's another page
+    let users = sqlx::query_as::<_, User>(&format!(
         r#"
         SELECT * FROM users
-        ORDER BY created_at DESC
-        LIMIT 100
+        WHERE (? IS NULL OR email = ?)
+          AND (? IS NULL OR created_at >= ?)
+          AND (? IS NULL OR created_at < ?)
+          AND (? IS NULL OR id IN (
+              SELECT user_id FROM user_attributes
+              WHERE key = ? AND value IS NOT NULL AND (? IS NULL OR value = ?)
+          ))
+          AND (? IS NULL OR (created_at, rowid) {after} (SELECT created_at, rowid FROM users WHERE id = ?))
+        ORDER BY created_at {order}, rowid {order}
+        LIMIT ?
         "#,
-    )
+        after = query.order.after(),
+        order = query.order.sql(),
+    ))
+    .bind(&query.email)
+    .bind(&query.email)
+    .bind(query.created_after)
+    .bind(query.created_after)
+    .bind(query.created_before)
+    .bind(query.created_before)
+    .bind(&query.attribute)
+    .bind(&query.attribute)
+    .bind(&query.attribute_value)
+    .bind(&query.attribute_value)
+    .bind(&query.starting_after)
+    .bind(&query.starting_after)
+    .bind(limit + 1)
     .fetch_all(&pool)
     .await?;
     
-    let user_responses = users
-        .into_iter()
-        .map(|user| UserResponse {
-            id: user.id,
-            app_user_id: user.app_user_id,
-            email: user.email,
-        })
-        .collect();
+    let (users, has_more) = split_page(users, limit);
+    
+    let mut user_responses = Vec::new();
+    for user in users {
+        user_responses.push(UserResponse::load(user, &pool).await?);
+    }
     
     Ok(Json(UsersResponse {
         users: user_responses,
+        has_more,
     }))
 }
 
@@ -87,11 +240,7 @@ pub async fn get_user(
         .await?
         .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
     
-    Ok(Json(UserResponse {
-        id: user.id,
-        app_user_id: user.app_user_id,
-        email: user.email,
-    }))
+    Ok(Json(UserResponse::load(user, &pool).await?))
 }
 
 // Get user by app_user_id
@@ -103,18 +252,17 @@ pub async fn get_user_by_app_id(
         .await?
         .ok_or_else(|| AppError::NotFound(format!("User not found with app_user_id: {}", app_user_id)))?;
     
-    Ok(Json(UserResponse {
-        id: user.id,
-        app_user_id: user.app_user_id,
-        email: user.email,
-    }))
+    Ok(Json(UserResponse::load(user, &pool).await?))
 }
 
 // Create a new user
 pub async fn create_user(
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<CreateUserRequest>,
 ) -> Result<(StatusCode, Json<UserResponse>)> {
+    validate_app_user_id(&request.app_user_id)?;
+    
     // Check if a user with this app_user_id already exists
     if let Some(_) = User::find_by_app_user_id(&request.app_user_id, &pool).await? {
         return Err(AppError::BadRequest(format!(
@@ -125,21 +273,150 @@ pub async fn create_user(
     
     // Create the user
     let user = User::new(request.app_user_id, request.email);
-    user.create(&pool).await?;
+    let mut tx = pool.begin().await?;
+
+    user.create(&mut *tx).await?;
+
+    if user.email.is_some() {
+        set_email_attribute(&user, &mut *tx).await?;
+    }
+
+    let entry = AuditLogEntry::new(AuditAction::UserCreated, "user", &user.id)
+        .for_user(&user.id)
+        .after(&user);
+    audit.record(entry, &mut *tx).await?;
+
+    tx.commit().await?;
     
     Ok((
         StatusCode::CREATED,
-        Json(UserResponse {
-            id: user.id,
-            app_user_id: user.app_user_id,
-            email: user.email,
+        Json(UserResponse::load(user, &pool).await?),
+    ))
+}
+
+// Log in as `new_app_user_id` from the ID the app has been using. An
+// anonymous user logging in takes the new ID if nobody has it yet, or is
+// merged into the user who does, bringing their purchases along. Switching
+// from one known user to another moves nothing.
+pub async fn login(
+    State(pool): State<SqlitePool>,
+    Json(request): Json<LoginRequest>,
+) -> Result<(StatusCode, Json<LoginResponse>)> {
+    validate_app_user_id(&request.app_user_id)?;
+
+    if User::is_anonymous_id(&request.new_app_user_id) {
+        return Err(AppError::ValidationError(
+            "Can't log in with an anonymous app user ID".to_string(),
+        ));
+    }
+
+    let current = User::find_by_app_user_id(&request.app_user_id, &pool)
+        .await?
+        .filter(|current| current.is_anonymous());
+    let existing = User::find_by_app_user_id(&request.new_app_user_id, &pool).await?;
+
+    let (user, created) = match (current, existing) {
+        // Already logged in, e.g. the anonymous ID is an alias of the user
+        (Some(current), Some(existing)) if current.id == existing.id => (existing, false),
+        (Some(current), Some(mut existing)) => {
+            merge_anonymous_user(&current, &mut existing, &pool).await?;
+            (existing, false)
+        }
+        // Nobody has the new ID, so the anonymous user becomes that user
+        (Some(mut current), None) => {
+            let anonymous_id = std::mem::replace(&mut current.app_user_id, request.new_app_user_id.clone());
+            current.update(&pool).await?;
+            UserAlias::new(current.id.clone(), anonymous_id).create(&pool).await?;
+            (current, true)
+        }
+        (None, Some(existing)) => (existing, false),
+        (None, None) => {
+            let user = User::new(request.new_app_user_id.clone(), None);
+            user.create(&pool).await?;
+            (user, true)
+        }
+    };
+
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+
+    Ok((
+        status,
+        Json(LoginResponse {
+            user: UserResponse::load(user, &pool).await?,
+            created,
         }),
     ))
 }
 
+// Get the other app user IDs a user is known by
+pub async fn get_user_aliases(
+    Path(user_id): Path<String>,
+    Query(page): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<AliasesResponse>> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    let limit = page_limit(page.limit)?;
+    check_cursor("user_aliases", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra alias to tell whether there's another page
+    let aliases = UserAlias::list_by_user(&user.id, page.starting_after.as_deref(), limit + 1, &pool).await?;
+    let (aliases, has_more) = split_page(aliases, limit);
+
+    Ok(Json(AliasesResponse {
+        aliases: aliases.into_iter().map(AliasResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Make another app user ID resolve to this user. If an anonymous user has
+// the ID, they're merged into this user. Known users can't be aliased.
+pub async fn create_user_alias(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<CreateAliasRequest>,
+) -> Result<(StatusCode, Json<AliasResponse>)> {
+    validate_app_user_id(&request.alias)?;
+
+    let mut user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    match User::find_by_app_user_id(&request.alias, &pool).await? {
+        Some(other) if other.id == user.id => {
+            return Err(AppError::ValidationError(format!(
+                "{} is already an ID of this user",
+                request.alias
+            )));
+        }
+        Some(other) if other.is_anonymous() && other.app_user_id == request.alias => {
+            merge_anonymous_user(&other, &mut user, &pool).await?;
+        }
+        Some(_) => {
+            return Err(AppError::ValidationError(format!(
+                "{} belongs to another user",
+                request.alias
+            )));
+        }
+        None => {
+            UserAlias::new(user.id.clone(), request.alias.clone()).create(&pool).await?;
+        }
+    }
+
+    let alias = UserAlias::find_by_alias(&request.alias, &pool)
+        .await?
+        .filter(|alias| alias.user_id == user.id)
+        .ok_or_else(|| AppError::InternalServerError("Alias wasn't created".to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(alias.into())))
+}
+
 // Update a user
 pub async fn update_user(
     Path(user_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
     Json(request): Json<UpdateUserRequest>,
 ) -> Result<Json<UserResponse>> {
@@ -147,30 +424,62 @@ pub async fn update_user(
         .await?
         .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
     
+    let entry = AuditLogEntry::new(AuditAction::UserUpdated, "user", &user.id)
+        .for_user(&user.id)
+        .before(&user);
+    
     // Update fields if provided
+    let email_changed = request.email.is_some();
     if let Some(email) = request.email {
         user.email = Some(email);
     }
+
+    if let Some(consumption_consent) = request.consumption_consent {
+        user.consumption_consent = consumption_consent;
+    }
+
+    if let Some(play_time_minutes) = request.play_time_minutes {
+        if play_time_minutes < 0 {
+            return Err(AppError::ValidationError("Play time must not be negative".to_string()));
+        }
+        user.play_time_minutes = Some(play_time_minutes);
+    }
     
-    user.update(&pool).await?;
+    let mut tx = pool.begin().await?;
+
+    user.update(&mut *tx).await?;
+
+    if email_changed {
+        set_email_attribute(&user, &mut *tx).await?;
+    }
+
+    audit.record(entry.after(&user), &mut *tx).await?;
+
+    tx.commit().await?;
     
-    Ok(Json(UserResponse {
-        id: user.id,
-        app_user_id: user.app_user_id,
-        email: user.email,
-    }))
+    Ok(Json(UserResponse::load(user, &pool).await?))
 }
 
 // Delete a user
 pub async fn delete_user(
     Path(user_id): Path<String>,
+    audit: AuditContext,
     State(pool): State<SqlitePool>,
 ) -> Result<StatusCode> {
     let user = User::find_by_id(&user_id, &pool)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
     
-    user.delete(&pool).await?;
+    let entry = AuditLogEntry::new(AuditAction::UserDeleted, "user", &user.id)
+        .for_user(&user.id)
+        .before(&user);
+
+    let mut tx = pool.begin().await?;
+
+    user.delete(&mut *tx).await?;
+    audit.record(entry, &mut *tx).await?;
+
+    tx.commit().await?;
     
     Ok(StatusCode::NO_CONTENT)
 }
@@ -178,6 +487,7 @@ pub async fn delete_user(
 // Get all subscriptions for a user
 pub async fn get_user_subscriptions(
     Path(user_id): Path<String>,
+    Query(page): Query<PageQuery>,
     State(pool): State<SqlitePool>,
 ) -> Result<Json<UserSubscriptionsResponse>> {
     // Check if the user exists
@@ -186,7 +496,18 @@ pub async fn get_user_subscriptions(
         .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
     
     // Get all subscriptions for the user
-    let subscriptions = Subscription::list_by_user(&user_id, &pool).await?;
+    let limit = page_limit(page.limit)?;
+    check_cursor("subscriptions", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra subscription to tell whether there's another page
+    let subscriptions = Subscription::list_page_by_user(
+        &user_id,
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (subscriptions, has_more) = split_page(subscriptions, limit);
     
     let subscription_responses = subscriptions
         .into_iter()
@@ -198,17 +519,20 @@ pub async fn get_user_subscriptions(
             expires_date: subscription.expires_date,
             status: subscription.status,
             auto_renew_status: subscription.auto_renew_status,
+            ownership_type: subscription.ownership_type,
         })
         .collect();
     
     Ok(Json(UserSubscriptionsResponse {
         subscriptions: subscription_responses,
+        has_more,
     }))
 }
 
 // Get active subscriptions for a user
 pub async fn get_user_active_subscriptions(
     Path(user_id): Path<String>,
+    Query(page): Query<PageQuery>,
     State(pool): State<SqlitePool>,
 ) -> Result<Json<UserSubscriptionsResponse>> {
     // Check if the user exists
@@ -217,7 +541,18 @@ pub async fn get_user_active_subscriptions(
         .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
     
     // Get active subscriptions for the user
-    let subscriptions = Subscription::list_active_by_user(&user_id, &pool).await?;
+    let limit = page_limit(page.limit)?;
+    check_cursor("subscriptions", page.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra subscription to tell whether there's another page
+    let subscriptions = Subscription::list_active_by_user(
+        &user_id,
+        page.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (subscriptions, has_more) = split_page(subscriptions, limit);
     
     let subscription_responses = subscriptions
         .into_iter()
@@ -229,10 +564,12 @@ pub async fn get_user_active_subscriptions(
             expires_date: subscription.expires_date,
             status: subscription.status,
             auto_renew_status: subscription.auto_renew_status,
+            ownership_type: subscription.ownership_type,
         })
         .collect();
     
     Ok(Json(UserSubscriptionsResponse {
         subscriptions: subscription_responses,
+        has_more,
     }))
 }
@@ -0,0 +1,246 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::api::pagination::{check_cursor, page_limit, split_page};
+use crate::db::models::{User, WalletBalance, WalletEntry, WalletEntryType};
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Serialize)]
+pub struct WalletEntryResponse {
+    pub id: String,
+    pub currency: String,
+    pub amount: i64,
+    pub type_: String,
+    pub subscription_id: Option<String>,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletResponse {
+    pub user_id: String,
+    pub balances: Vec<WalletBalance>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletEntriesResponse {
+    pub entries: Vec<WalletEntryResponse>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletTransactionResponse {
+    pub entry: WalletEntryResponse,
+    pub balance: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WalletEntriesQuery {
+    pub currency: Option<String>,
+    pub limit: Option<i64>,
+    pub starting_after: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpendRequest {
+    pub currency: String,
+    pub amount: i64,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdjustRequest {
+    pub currency: String,
+    pub amount: i64,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+}
+
+impl From<WalletEntry> for WalletEntryResponse {
+    fn from(entry: WalletEntry) -> Self {
+        Self {
+            id: entry.id,
+            currency: entry.currency,
+            amount: entry.amount,
+            type_: entry.type_,
+            subscription_id: entry.subscription_id,
+            reference: entry.reference,
+            description: entry.description,
+            created_at: entry.created_at,
+        }
+    }
+}
+
+// Get a user's balance in each currency
+pub async fn get_wallet(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<WalletResponse>> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    let balances = WalletEntry::balances_for_user(&user.id, &pool).await?;
+
+    Ok(Json(WalletResponse {
+        user_id: user.id,
+        balances,
+    }))
+}
+
+// Get the ledger entries behind a user's balances, newest first
+pub async fn get_wallet_entries(
+    Path(user_id): Path<String>,
+    Query(query): Query<WalletEntriesQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<WalletEntriesResponse>> {
+    let user = User::find_by_id(&user_id, &pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))?;
+
+    let limit = page_limit(query.limit)?;
+    check_cursor("wallet_entries", query.starting_after.as_deref(), &pool).await?;
+
+    // Fetch one extra entry to tell whether there's another page
+    let entries = WalletEntry::list_by_user(
+        &user.id,
+        query.currency.as_deref(),
+        query.starting_after.as_deref(),
+        limit + 1,
+        &pool,
+    )
+    .await?;
+    let (entries, has_more) = split_page(entries, limit);
+
+    Ok(Json(WalletEntriesResponse {
+        entries: entries.into_iter().map(WalletEntryResponse::from).collect(),
+        has_more,
+    }))
+}
+
+// Spend virtual currency. Sending the same reference again returns the
+// original entry instead of spending twice.
+pub async fn spend(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<SpendRequest>,
+) -> Result<(StatusCode, Json<WalletTransactionResponse>)> {
+    if request.amount <= 0 {
+        return Err(AppError::ValidationError("Amount must be positive".to_string()));
+    }
+
+    let mut entry = WalletEntry::new(
+        user_id,
+        request.currency,
+        -request.amount,
+        WalletEntryType::Spend,
+        request.description,
+    );
+    entry.reference = request.reference;
+
+    record_entry(entry, &pool).await
+}
+
+// Manually credit or debit a wallet, e.g. to compensate a user. Unlike
+// spending, an adjustment may take the balance below zero.
+pub async fn adjust(
+    Path(user_id): Path<String>,
+    State(pool): State<SqlitePool>,
+    Json(request): Json<AdjustRequest>,
+) -> Result<(StatusCode, Json<WalletTransactionResponse>)> {
+    if request.amount == 0 {
+        return Err(AppError::ValidationError("Amount must not be zero".to_string()));
+    }
+
+    let mut entry = WalletEntry::new(
+        user_id,
+        request.currency,
+        request.amount,
+        WalletEntryType::Adjustment,
+        request.description,
+    );
+    entry.reference = request.reference;
+
+    record_entry(entry, &pool).await
+}
+
+async fn record_entry(
+    entry: WalletEntry,
+    pool: &SqlitePool,
+) -> Result<(StatusCode, Json<WalletTransactionResponse>)> {
+    if entry.currency.trim().is_empty() {
+        return Err(AppError::ValidationError("Currency is required".to_string()));
+    }
+
+    let user = User::find_by_id(&entry.user_id, pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", entry.user_id)))?;
+
+    if let Some(existing) = find_existing(&entry, pool).await? {
+        return Ok((StatusCode::OK, Json(transaction_response(existing, pool).await?)));
+    }
+
+    let result = if entry.type_ == WalletEntryType::Spend.to_string() {
+        entry.create_debit(pool).await
+    } else {
+        entry.create(pool).await
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => {
+            let balance = WalletEntry::balance(&user.id, &entry.currency, pool).await?;
+            return Err(AppError::InsufficientFunds(format!(
+                "Balance of {} {} is less than {}",
+                balance, entry.currency, -entry.amount
+            )));
+        }
+        // Another request with the same reference got in first
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            if let Some(existing) = find_existing(&entry, pool).await? {
+                return Ok((StatusCode::OK, Json(transaction_response(existing, pool).await?)));
+            }
+            return Err(AppError::DatabaseError(sqlx::Error::Database(e)));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok((StatusCode::CREATED, Json(transaction_response(entry, pool).await?)))
+}
+
+async fn find_existing(entry: &WalletEntry, pool: &SqlitePool) -> Result<Option<WalletEntry>> {
+    match &entry.reference {
+        Some(reference) => {
+            let existing =
+                WalletEntry::find_by_reference(&entry.user_id, &entry.currency, reference, pool).await?;
+
+            match existing {
+                Some(existing) if existing.type_ != entry.type_ || existing.amount != entry.amount => {
+                    Err(AppError::BadRequest(format!(
+                        "Reference already used for a different entry: {}",
+                        reference
+                    )))
+                }
+                existing => Ok(existing),
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+async fn transaction_response(entry: WalletEntry, pool: &SqlitePool) -> Result<WalletTransactionResponse> {
+    let balance = WalletEntry::balance(&entry.user_id, &entry.currency, pool).await?;
+
+    Ok(WalletTransactionResponse {
+        entry: WalletEntryResponse::from(entry),
+        balance,
+    })
+}
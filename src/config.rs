@@ -14,6 +14,7 @@ pub struct Config {
     pub apple_shared_secret: Option<String>,
     pub google_service_account_json: Option<String>,
     pub webhook_signature_secret: String,
+    pub exchange_rates_file: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone, PartialEq)]
@@ -52,6 +53,7 @@ impl Config {
         let google_service_account_json = env::var("GOOGLE_SERVICE_ACCOUNT_JSON").ok();
         let webhook_signature_secret = env::var("WEBHOOK_SIGNATURE_SECRET")
             .unwrap_or_else(|_| "your-webhook-signature-secret".to_string());
+        let exchange_rates_file = env::var("EXCHANGE_RATES_FILE").ok();
 
         Config {
             database_url,
@@ -64,6 +66,7 @@ impl Config {
             apple_shared_secret,
             google_service_account_json,
             webhook_signature_secret,
+            exchange_rates_file,
         }
     }
 
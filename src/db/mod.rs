@@ -33,15 +33,29 @@ pub async fn initialize_db(database_url: &str) -> Result<SqlitePool> {
 }
 
 pub async fn run_migrations(pool: &SqlitePool) -> Result<()> {
-    // Create tables if they don't exist
-    let migration_query = include_str!("../../migrations/00001_initial_schema.sql");
-    sqlx::query(migration_query)
-        .execute(pool)
+    // Apply any migrations in ./migrations that haven't been run yet
+    sqlx::migrate!("./migrations")
+        .run(pool)
         .await?;
 
     Ok(())
 }
 
+// Load exchange rates from a JSON or CSV file, then convert any ledger
+// amounts that were waiting on a rate
+pub async fn load_exchange_rates(pool: &SqlitePool, path: &str) -> Result<usize> {
+    let contents = std::fs::read_to_string(path)?;
+    let rates = models::ExchangeRate::parse_rates(&contents).map_err(anyhow::Error::msg)?;
+
+    for rate in &rates {
+        rate.upsert(pool).await?;
+    }
+
+    models::Transaction::backfill_usd_amounts(pool).await?;
+
+    Ok(rates.len())
+}
+
 pub async fn check_db_connection(pool: &SqlitePool) -> Result<bool> {
     let result = sqlx::query_scalar::<_, i64>("SELECT 1")
         .fetch_one(pool)
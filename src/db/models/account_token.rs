@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use uuid::Uuid;
+
+// An account identifier the app attaches to store purchases: Apple's
+// appAccountToken, or Google's obfuscated account or profile ID
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AccountToken {
+    pub id: String,
+    pub user_id: String,
+    pub store: String,  // 'apple' or 'google'
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Google limits obfuscated account and profile IDs to 64 characters
+const MAX_GOOGLE_TOKEN_LENGTH: usize = 64;
+
+impl AccountToken {
+    // Canonical form of a token, or None if it isn't valid for the store.
+    // Apple's appAccountToken is a UUID, compared in lowercase.
+    pub fn normalize(store: &str, token: &str) -> Option<String> {
+        match store {
+            "apple" => Uuid::parse_str(token.trim()).ok().map(|uuid| uuid.to_string()),
+            "google" => {
+                let token = token.trim();
+                (!token.is_empty() && token.len() <= MAX_GOOGLE_TOKEN_LENGTH).then(|| token.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn new(user_id: String, store: String, token: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            store,
+            token,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO account_tokens (id, user_id, store, token, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.user_id)
+        .bind(&self.store)
+        .bind(&self.token)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let account_token = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM account_tokens WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(account_token)
+    }
+
+    pub async fn find_by_token(store: &str, token: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
+        let account_token = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM account_tokens WHERE store = ? AND token = ?
+            "#,
+        )
+        .bind(store)
+        .bind(token)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(account_token)
+    }
+
+    // The user's account tokens in the order they were registered, starting
+    // after the token with ID `starting_after`
+    pub async fn list_by_user(
+        user_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let account_tokens = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM account_tokens
+            WHERE user_id = ?
+              AND (? IS NULL OR (created_at, rowid) > (SELECT created_at, rowid FROM account_tokens WHERE id = ?))
+            ORDER BY created_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(account_tokens)
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM account_tokens WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct App {
+    pub id: String,
+    pub name: String,
+    pub apple_bundle_id: Option<String>,
+    pub google_package_name: Option<String>,
+    pub apple_offer_key_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub apple_offer_private_key: Option<String>,
+    pub apple_issuer_id: Option<String>,
+    pub apple_consumption_opt_in: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl App {
+    pub fn new(
+        name: String,
+        apple_bundle_id: Option<String>,
+        google_package_name: Option<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            apple_bundle_id,
+            google_package_name,
+            apple_offer_key_id: None,
+            apple_offer_private_key: None,
+            apple_issuer_id: None,
+            apple_consumption_opt_in: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO apps (
+                id, name, apple_bundle_id, google_package_name,
+                apple_offer_key_id, apple_offer_private_key, apple_issuer_id,
+                apple_consumption_opt_in, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.name)
+        .bind(&self.apple_bundle_id)
+        .bind(&self.google_package_name)
+        .bind(&self.apple_offer_key_id)
+        .bind(&self.apple_offer_private_key)
+        .bind(&self.apple_issuer_id)
+        .bind(self.apple_consumption_opt_in)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let app = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM apps WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(app)
+    }
+
+    pub async fn find_by_apple_bundle_id(bundle_id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let app = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM apps WHERE apple_bundle_id = ?
+            "#,
+        )
+        .bind(bundle_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(app)
+    }
+
+    // Apps by name, starting after the app with ID `starting_after`
+    pub async fn list(
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let apps = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM apps
+            WHERE (? IS NULL OR (name, rowid) > (SELECT name, rowid FROM apps WHERE id = ?))
+            ORDER BY name ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(apps)
+    }
+
+    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE apps
+            SET name = ?, apple_bundle_id = ?, google_package_name = ?,
+                apple_offer_key_id = ?, apple_offer_private_key = ?, apple_issuer_id = ?,
+                apple_consumption_opt_in = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&self.name)
+        .bind(&self.apple_bundle_id)
+        .bind(&self.google_package_name)
+        .bind(&self.apple_offer_key_id)
+        .bind(&self.apple_offer_private_key)
+        .bind(&self.apple_issuer_id)
+        .bind(self.apple_consumption_opt_in)
+        .bind(Utc::now())
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM apps WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
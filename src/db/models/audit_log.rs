@@ -0,0 +1,178 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use sqlx::types::Json;
+use std::fmt;
+use uuid::Uuid;
+
+// A manual change made through the API
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuditLogEntry {
+    pub id: String,
+    pub actor: Option<String>,  // API key ID of the caller, if known
+    pub action: String,
+    pub target_type: String,
+    pub target_id: String,
+    pub user_id: Option<String>,  // The user affected, if any
+    #[sqlx(rename = "before_json")]
+    pub before: Option<Json<serde_json::Value>>,
+    #[sqlx(rename = "after_json")]
+    pub after: Option<Json<serde_json::Value>>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    EntitlementGranted,
+    EntitlementExtended,
+    EntitlementRevoked,
+    SubscriptionCanceled,
+    SubscriptionRefunded,
+    ProductCreated,
+    ProductUpdated,
+    ProductDeleted,
+    ProductEntitlementAdded,
+    ProductEntitlementRemoved,
+    UserCreated,
+    UserUpdated,
+    UserDeleted,
+    PromoCodeBatchCreated,
+    PromoCodesGenerated,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditAction::EntitlementGranted => write!(f, "entitlement.granted"),
+            AuditAction::EntitlementExtended => write!(f, "entitlement.extended"),
+            AuditAction::EntitlementRevoked => write!(f, "entitlement.revoked"),
+            AuditAction::SubscriptionCanceled => write!(f, "subscription.canceled"),
+            AuditAction::SubscriptionRefunded => write!(f, "subscription.refunded"),
+            AuditAction::ProductCreated => write!(f, "product.created"),
+            AuditAction::ProductUpdated => write!(f, "product.updated"),
+            AuditAction::ProductDeleted => write!(f, "product.deleted"),
+            AuditAction::ProductEntitlementAdded => write!(f, "product.entitlement_added"),
+            AuditAction::ProductEntitlementRemoved => write!(f, "product.entitlement_removed"),
+            AuditAction::UserCreated => write!(f, "user.created"),
+            AuditAction::UserUpdated => write!(f, "user.updated"),
+            AuditAction::UserDeleted => write!(f, "user.deleted"),
+            AuditAction::PromoCodeBatchCreated => write!(f, "promo_code_batch.created"),
+            AuditAction::PromoCodesGenerated => write!(f, "promo_code_batch.codes_generated"),
+        }
+    }
+}
+
+// Filters for listing the log. Every filter is optional.
+#[derive(Debug, Default)]
+pub struct AuditLogFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub user_id: Option<String>,
+}
+
+fn snapshot<T: Serialize>(value: &T) -> Option<Json<serde_json::Value>> {
+    serde_json::to_value(value).ok().map(Json)
+}
+
+impl AuditLogEntry {
+    pub fn new(action: AuditAction, target_type: &str, target_id: &str) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            actor: None,
+            action: action.to_string(),
+            target_type: target_type.to_string(),
+            target_id: target_id.to_string(),
+            user_id: None,
+            before: None,
+            after: None,
+            reason: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn for_user(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    // The target as it was before the change
+    pub fn before<T: Serialize>(mut self, value: &T) -> Self {
+        self.before = snapshot(value);
+        self
+    }
+
+    // The target as it was after the change
+    pub fn after<T: Serialize>(mut self, value: &T) -> Self {
+        self.after = snapshot(value);
+        self
+    }
+
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log (
+                id, actor, action, target_type, target_id, user_id,
+                before_json, after_json, reason, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.actor)
+        .bind(&self.action)
+        .bind(&self.target_type)
+        .bind(&self.target_id)
+        .bind(&self.user_id)
+        .bind(&self.before)
+        .bind(&self.after)
+        .bind(&self.reason)
+        .bind(self.created_at)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    // Entries matching the filter, newest first, starting after the entry
+    // with ID `starting_after`
+    pub async fn list(
+        filter: &AuditLogFilter,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let entries = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM audit_log
+            WHERE (? IS NULL OR actor = ?)
+              AND (? IS NULL OR action = ?)
+              AND (? IS NULL OR target_type = ?)
+              AND (? IS NULL OR target_id = ?)
+              AND (? IS NULL OR user_id = ?)
+              AND (? IS NULL OR (created_at, rowid) < (SELECT created_at, rowid FROM audit_log WHERE id = ?))
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(&filter.actor)
+        .bind(&filter.actor)
+        .bind(&filter.action)
+        .bind(&filter.action)
+        .bind(&filter.target_type)
+        .bind(&filter.target_type)
+        .bind(&filter.target_id)
+        .bind(&filter.target_id)
+        .bind(&filter.user_id)
+        .bind(&filter.user_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
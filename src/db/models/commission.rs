@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CommissionRule {
+    pub store: String,
+    pub standard_rate: f64,
+    pub reduced_rate: f64,
+    pub small_business: bool,
+    pub reduced_after_days: Option<i64>,
+    pub reduced_for_subscriptions: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TaxRate {
+    pub country_code: String,
+    pub rate: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+// What's left of a gross amount once tax included in the price
+// and the store's commission on the rest are taken out
+pub fn proceeds(amount: f64, commission_rate: f64, tax_rate: f64) -> f64 {
+    amount / (1.0 + tax_rate) * (1.0 - commission_rate)
+}
+
+impl CommissionRule {
+    // The standard 30% rate, used for stores without a configured rule
+    pub fn standard(store: &str) -> Self {
+        Self {
+            store: store.to_string(),
+            standard_rate: 0.30,
+            reduced_rate: 0.15,
+            small_business: false,
+            reduced_after_days: None,
+            reduced_for_subscriptions: false,
+            updated_at: Utc::now(),
+        }
+    }
+
+    // Commission for a sale. Small business members get the reduced rate on
+    // everything, and subscriptions can get it outright (Google) or once the
+    // subscriber has enough paid days (Apple's rate after a year).
+    pub fn commission_rate(&self, is_subscription: bool, paid_days: i64) -> f64 {
+        let is_reduced = self.small_business
+            || (is_subscription && self.reduced_for_subscriptions)
+            || (is_subscription && self.reduced_after_days.is_some_and(|days| paid_days >= days));
+
+        if is_reduced {
+            self.reduced_rate
+        } else {
+            self.standard_rate
+        }
+    }
+
+    pub async fn find_by_store(store: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
+        let rule = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM commission_rules WHERE store = ?
+            "#,
+        )
+        .bind(store)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(rule)
+    }
+
+    // Rules by store, starting after the store `starting_after`.
+    // The cursor is the sort key itself, so it works even if that rule is gone.
+    pub async fn list(starting_after: Option<&str>, limit: i64, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let rules = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM commission_rules
+            WHERE (? IS NULL OR store > ?)
+            ORDER BY store
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rules)
+    }
+
+    // Insert the rule, replacing any existing rule for the store
+    pub async fn upsert(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO commission_rules (
+                store, standard_rate, reduced_rate, small_business,
+                reduced_after_days, reduced_for_subscriptions, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(store) DO UPDATE SET
+                standard_rate = excluded.standard_rate,
+                reduced_rate = excluded.reduced_rate,
+                small_business = excluded.small_business,
+                reduced_after_days = excluded.reduced_after_days,
+                reduced_for_subscriptions = excluded.reduced_for_subscriptions,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&self.store)
+        .bind(self.standard_rate)
+        .bind(self.reduced_rate)
+        .bind(self.small_business)
+        .bind(self.reduced_after_days)
+        .bind(self.reduced_for_subscriptions)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl TaxRate {
+    pub fn new(country_code: &str, rate: f64) -> Self {
+        Self {
+            country_code: country_code.trim().to_uppercase(),
+            rate,
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn find_by_country(country_code: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
+        let rate = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM tax_rates WHERE country_code = ?
+            "#,
+        )
+        .bind(country_code.trim().to_uppercase())
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(rate)
+    }
+
+    // Rates by country, starting after the country code `starting_after`.
+    // The cursor is the sort key itself, so it works even if that rate is gone.
+    pub async fn list(starting_after: Option<&str>, limit: i64, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let rates = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM tax_rates
+            WHERE (? IS NULL OR country_code > ?)
+            ORDER BY country_code
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rates)
+    }
+
+    // Insert the rate, replacing any existing rate for the country
+    pub async fn upsert(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO tax_rates (country_code, rate, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(country_code) DO UPDATE SET rate = excluded.rate, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&self.country_code)
+        .bind(self.rate)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM tax_rates WHERE country_code = ?
+            "#,
+        )
+        .bind(&self.country_code)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ConsumptionRequest {
+    pub id: String,
+    pub app_id: String,
+    pub notification_uuid: String,
+    pub environment: String,  // 'Production' or 'Sandbox'
+    pub transaction_id: String,
+    pub user_id: Option<String>,
+    pub subscription_id: Option<String>,
+    pub status: String,  // 'pending', 'sent', 'skipped' or 'failed'
+    pub request_json: Option<String>,  // Body sent to Apple
+    pub error: Option<String>,  // Why the request was skipped or failed
+    pub deadline: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ConsumptionRequestStatus {
+    Pending,
+    Sent,
+    Skipped,
+    Failed,
+}
+
+impl fmt::Display for ConsumptionRequestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumptionRequestStatus::Pending => write!(f, "pending"),
+            ConsumptionRequestStatus::Sent => write!(f, "sent"),
+            ConsumptionRequestStatus::Skipped => write!(f, "skipped"),
+            ConsumptionRequestStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+impl ConsumptionRequest {
+    pub fn new(
+        app_id: String,
+        notification_uuid: String,
+        environment: String,
+        transaction_id: String,
+        deadline: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            app_id,
+            notification_uuid,
+            environment,
+            transaction_id,
+            user_id: None,
+            subscription_id: None,
+            status: ConsumptionRequestStatus::Pending.to_string(),
+            request_json: None,
+            error: None,
+            deadline,
+            sent_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    // Record the request. Returns false if Apple already sent this
+    // notification and it was recorded before.
+    pub async fn create(&self, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO consumption_requests (
+                id, app_id, notification_uuid, environment, transaction_id, user_id,
+                subscription_id, status, request_json, error, deadline, sent_at, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.app_id)
+        .bind(&self.notification_uuid)
+        .bind(&self.environment)
+        .bind(&self.transaction_id)
+        .bind(&self.user_id)
+        .bind(&self.subscription_id)
+        .bind(&self.status)
+        .bind(&self.request_json)
+        .bind(&self.error)
+        .bind(self.deadline)
+        .bind(self.sent_at)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let request = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM consumption_requests WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(request)
+    }
+
+    // The app's consumption requests, newest first, starting after the request
+    // with ID `starting_after`
+    pub async fn list_by_app(
+        app_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let requests = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM consumption_requests
+            WHERE app_id = ?
+              AND (? IS NULL OR (created_at, rowid) < (SELECT created_at, rowid FROM consumption_requests WHERE id = ?))
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(app_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(requests)
+    }
+
+    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE consumption_requests
+            SET user_id = ?, subscription_id = ?, status = ?, request_json = ?, error = ?,
+                sent_at = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&self.user_id)
+        .bind(&self.subscription_id)
+        .bind(&self.status)
+        .bind(&self.request_json)
+        .bind(&self.error)
+        .bind(self.sent_at)
+        .bind(Utc::now())
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub fn skip(&mut self, reason: String) {
+        self.status = ConsumptionRequestStatus::Skipped.to_string();
+        self.error = Some(reason);
+    }
+
+    pub fn fail(&mut self, error: String) {
+        self.status = ConsumptionRequestStatus::Failed.to_string();
+        self.error = Some(error);
+    }
+
+    pub fn mark_sent(&mut self, sent_at: DateTime<Utc>) {
+        self.status = ConsumptionRequestStatus::Sent.to_string();
+        self.error = None;
+        self.sent_at = Some(sent_at);
+    }
+}
@@ -1,6 +1,7 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Months, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use std::fmt;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -20,10 +21,58 @@ pub struct UserEntitlement {
     pub subscription_id: Option<String>,
     pub starts_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    pub reason: Option<String>,  // Why it was granted, for grants outside of a purchase
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+// How long a promotional grant lasts
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GrantDuration {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Lifetime,
+}
+
+impl GrantDuration {
+    // When a grant of this length starting at `start` ends, None for lifetime
+    pub fn end(&self, start: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            GrantDuration::Daily => Some(start + Duration::days(1)),
+            GrantDuration::Weekly => Some(start + Duration::weeks(1)),
+            GrantDuration::Monthly => start.checked_add_months(Months::new(1)),
+            GrantDuration::Yearly => start.checked_add_months(Months::new(12)),
+            GrantDuration::Lifetime => None,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(GrantDuration::Daily),
+            "weekly" => Some(GrantDuration::Weekly),
+            "monthly" => Some(GrantDuration::Monthly),
+            "yearly" => Some(GrantDuration::Yearly),
+            "lifetime" => Some(GrantDuration::Lifetime),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GrantDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrantDuration::Daily => write!(f, "daily"),
+            GrantDuration::Weekly => write!(f, "weekly"),
+            GrantDuration::Monthly => write!(f, "monthly"),
+            GrantDuration::Yearly => write!(f, "yearly"),
+            GrantDuration::Lifetime => write!(f, "lifetime"),
+        }
+    }
+}
+
 impl Entitlement {
     pub fn new(name: String, description: Option<String>) -> Self {
         Self {
@@ -140,19 +189,20 @@ impl UserEntitlement {
             subscription_id,
             starts_at,
             expires_at,
+            reason: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
 
-    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             INSERT INTO user_entitlements (
                 id, user_id, entitlement_id, subscription_id, 
-                starts_at, expires_at, created_at, updated_at
+                starts_at, expires_at, reason, created_at, updated_at
             )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&self.id)
@@ -161,9 +211,10 @@ impl UserEntitlement {
         .bind(&self.subscription_id)
         .bind(&self.starts_at)
         .bind(&self.expires_at)
+        .bind(&self.reason)
         .bind(&self.created_at)
         .bind(&self.updated_at)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
@@ -210,7 +261,7 @@ impl UserEntitlement {
     pub async fn list_active_for_user(
         user_id: &str,
         now: DateTime<Utc>,
-        pool: &SqlitePool,
+        executor: impl SqliteExecutor<'_>,
     ) -> Result<Vec<Self>, sqlx::Error> {
         let user_entitlements = sqlx::query_as::<_, Self>(
             r#"
@@ -223,13 +274,43 @@ impl UserEntitlement {
         .bind(user_id)
         .bind(now)
         .bind(now)
-        .fetch_all(pool)
+        .fetch_all(executor)
         .await?;
 
         Ok(user_entitlements)
     }
 
-    pub async fn update_expiry(&mut self, expires_at: Option<DateTime<Utc>>, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // The user's promotional grant of the entitlement that hasn't ended yet
+    // and has started by `starts_by`. If there are several, the one that
+    // ends last.
+    pub async fn find_current_promotional(
+        user_id: &str,
+        entitlement_id: &str,
+        starts_by: DateTime<Utc>,
+        now: DateTime<Utc>,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let user_entitlement = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM user_entitlements
+            WHERE user_id = ? AND entitlement_id = ? AND subscription_id IS NULL
+              AND julianday(starts_at) <= julianday(?)
+              AND (expires_at IS NULL OR expires_at > ?)
+            ORDER BY expires_at IS NULL DESC, expires_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(entitlement_id)
+        .bind(starts_by)
+        .bind(now)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(user_entitlement)
+    }
+
+    pub async fn update_expiry(&mut self, expires_at: Option<DateTime<Utc>>, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         self.expires_at = expires_at;
         self.updated_at = Utc::now();
         
@@ -243,13 +324,36 @@ impl UserEntitlement {
         .bind(&self.expires_at)
         .bind(&self.updated_at)
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    // Set the expiry of every entitlement granted by a subscription, including
+    // ones that were suspended while it was paused or on hold
+    pub async fn update_expiry_for_subscription(
+        subscription_id: &str,
+        expires_at: Option<DateTime<Utc>>,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE user_entitlements
+            SET expires_at = ?, updated_at = ?
+            WHERE subscription_id = ?
+            "#,
+        )
+        .bind(expires_at)
+        .bind(Utc::now())
+        .bind(subscription_id)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn revoke(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn revoke(&mut self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         let now = Utc::now();
         self.expires_at = Some(now);
         self.updated_at = now;
@@ -264,7 +368,7 @@ impl UserEntitlement {
         .bind(&self.expires_at)
         .bind(&self.updated_at)
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use std::collections::HashMap;
+
+pub const BASE_CURRENCY: &str = "USD";
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub usd_rate: f64,  // USD value of one unit of the currency
+    pub updated_at: DateTime<Utc>,
+}
+
+// All known rates, for converting many amounts without a query each
+#[derive(Debug, Default, Clone)]
+pub struct ExchangeRates {
+    rates: HashMap<String, f64>,
+}
+
+impl ExchangeRates {
+    // Convert an amount to USD. Amounts without a currency are assumed to be USD,
+    // and None is returned when there's no rate for the currency.
+    pub fn to_usd(&self, amount: f64, currency: Option<&str>) -> Option<f64> {
+        match currency.map(|currency| currency.trim().to_uppercase()) {
+            None => Some(amount),
+            Some(currency) if currency == BASE_CURRENCY => Some(amount),
+            Some(currency) => self.rates.get(&currency).map(|rate| amount * rate),
+        }
+    }
+}
+
+impl ExchangeRate {
+    // Currencies are ISO 4217 codes and rates must be positive
+    pub fn validate(currency: &str, usd_rate: f64) -> Result<(), String> {
+        let currency = currency.trim();
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("Invalid currency code: {}", currency));
+        }
+
+        if !usd_rate.is_finite() || usd_rate <= 0.0 {
+            return Err(format!("Rate for {} must be a positive number", currency));
+        }
+
+        Ok(())
+    }
+
+    // Parse a rates file, either a JSON object of currency to USD rate
+    // ({"EUR": 1.08}) or CSV lines of `currency,usd_rate` with an optional header
+    pub fn parse_rates(contents: &str) -> Result<Vec<Self>, String> {
+        let mut rates = Vec::new();
+
+        if contents.trim_start().starts_with('{') {
+            let map: HashMap<String, f64> = serde_json::from_str(contents)
+                .map_err(|e| format!("Invalid exchange rates JSON: {}", e))?;
+
+            for (currency, usd_rate) in map {
+                Self::validate(&currency, usd_rate)?;
+                rates.push(Self::new(&currency, usd_rate));
+            }
+        } else {
+            for (index, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let (currency, usd_rate) = line
+                    .split_once(',')
+                    .ok_or_else(|| format!("Line {}: expected currency,usd_rate", index + 1))?;
+
+                let usd_rate = match usd_rate.trim().parse::<f64>() {
+                    Ok(usd_rate) => usd_rate,
+                    // Skip a header line
+                    Err(_) if index == 0 => continue,
+                    Err(_) => return Err(format!("Line {}: invalid rate {}", index + 1, usd_rate.trim())),
+                };
+
+                Self::validate(currency, usd_rate)?;
+                rates.push(Self::new(currency, usd_rate));
+            }
+        }
+
+        Ok(rates)
+    }
+
+    pub fn new(currency: &str, usd_rate: f64) -> Self {
+        Self {
+            currency: currency.trim().to_uppercase(),
+            usd_rate,
+            updated_at: Utc::now(),
+        }
+    }
+
+    // Insert the rate, replacing any existing rate for the currency
+    pub async fn upsert(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO exchange_rates (currency, usd_rate, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(currency) DO UPDATE SET usd_rate = excluded.usd_rate, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&self.currency)
+        .bind(self.usd_rate)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_currency(currency: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
+        let rate = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM exchange_rates WHERE currency = ?
+            "#,
+        )
+        .bind(currency.trim().to_uppercase())
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(rate)
+    }
+
+    pub async fn list_all(pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let rates = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM exchange_rates ORDER BY currency
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rates)
+    }
+
+    // Rates by currency, starting after the currency `starting_after`.
+    // The cursor is the sort key itself, so it works even if that rate is gone.
+    pub async fn list(starting_after: Option<&str>, limit: i64, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let rates = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM exchange_rates
+            WHERE (? IS NULL OR currency > ?)
+            ORDER BY currency
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rates)
+    }
+
+    pub async fn load_all(pool: &SqlitePool) -> Result<ExchangeRates, sqlx::Error> {
+        let rates = Self::list_all(pool)
+            .await?
+            .into_iter()
+            .map(|rate| (rate.currency, rate.usd_rate))
+            .collect();
+
+        Ok(ExchangeRates { rates })
+    }
+
+    // Convert a single amount to USD using the stored rate
+    pub async fn convert_to_usd(
+        amount: f64,
+        currency: Option<&str>,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<Option<f64>, sqlx::Error> {
+        let currency = match currency {
+            Some(currency) if !currency.trim().eq_ignore_ascii_case(BASE_CURRENCY) => currency,
+            _ => return Ok(Some(amount)),
+        };
+
+        let rate = Self::find_by_currency(currency, executor).await?;
+
+        Ok(rate.map(|rate| amount * rate.usd_rate))
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM exchange_rates WHERE currency = ?
+            "#,
+        )
+        .bind(&self.currency)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::db::models::User;
+use crate::utils::hashing::weighted_bucket;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Experiment {
+    pub id: String,
+    pub app_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub status: String,  // 'draft', 'running' or 'stopped'
+    pub started_at: Option<DateTime<Utc>>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ExperimentVariant {
+    pub id: String,
+    pub experiment_id: String,
+    pub identifier: String,
+    pub offering_id: String,
+    pub weight: i32,
+    pub position: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ExperimentAssignment {
+    pub id: String,
+    pub experiment_id: String,
+    pub variant_id: String,
+    pub user_id: String,
+    pub assigned_at: DateTime<Utc>,
+}
+
+// Raw per-variant counts used to build experiment results
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ExperimentVariantStats {
+    pub users: i64,
+    pub trial_starts: i64,
+    pub conversions: i64,
+    pub revenue: f64,
+    pub proceeds: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ExperimentStatus {
+    Draft,
+    Running,
+    Stopped,
+}
+
+impl fmt::Display for ExperimentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExperimentStatus::Draft => write!(f, "draft"),
+            ExperimentStatus::Running => write!(f, "running"),
+            ExperimentStatus::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+impl Experiment {
+    pub fn new(app_id: String, name: String, description: Option<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            app_id,
+            name,
+            description,
+            status: ExperimentStatus::Draft.to_string(),
+            started_at: None,
+            stopped_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO experiments (
+                id, app_id, name, description, status, started_at, stopped_at,
+                created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.app_id)
+        .bind(&self.name)
+        .bind(&self.description)
+        .bind(&self.status)
+        .bind(self.started_at)
+        .bind(self.stopped_at)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let experiment = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM experiments WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(experiment)
+    }
+
+    pub async fn find_running_for_app(app_id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let experiment = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM experiments WHERE app_id = ? AND status = 'running'
+            LIMIT 1
+            "#,
+        )
+        .bind(app_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(experiment)
+    }
+
+    // The app's experiments, newest first, starting after the experiment with
+    // ID `starting_after`
+    pub async fn list_by_app(
+        app_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let experiments = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM experiments
+            WHERE app_id = ?
+              AND (? IS NULL OR (created_at, rowid) < (SELECT created_at, rowid FROM experiments WHERE id = ?))
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(app_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(experiments)
+    }
+
+    pub async fn update_status(&mut self, status: ExperimentStatus, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+
+        match status {
+            ExperimentStatus::Running => self.started_at = Some(now),
+            ExperimentStatus::Stopped => self.stopped_at = Some(now),
+            ExperimentStatus::Draft => {}
+        }
+
+        self.status = status.to_string();
+        self.updated_at = now;
+
+        sqlx::query(
+            r#"
+            UPDATE experiments
+            SET status = ?, started_at = ?, stopped_at = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&self.status)
+        .bind(self.started_at)
+        .bind(self.stopped_at)
+        .bind(self.updated_at)
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM experiments WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Put the user in a variant, reusing their earlier assignment if they have one.
+    // New users are bucketed by hashing their app_user_id, so the split is stable.
+    pub async fn assign_variant(
+        &self,
+        user: &User,
+        pool: &SqlitePool,
+    ) -> Result<Option<ExperimentVariant>, sqlx::Error> {
+        let variants = self.get_variants(pool).await?;
+
+        if ExperimentAssignment::find_for_user(&self.id, &user.id, pool).await?.is_none() {
+            let Some(index) = self.bucket(&user.app_user_id, &variants) else {
+                return Ok(None);
+            };
+
+            let assignment = ExperimentAssignment::new(
+                self.id.clone(),
+                variants[index].id.clone(),
+                user.id.clone(),
+            );
+            assignment.create(pool).await?;
+        }
+
+        // Read the assignment back in case a concurrent request stored it first
+        let assignment = ExperimentAssignment::find_for_user(&self.id, &user.id, pool).await?;
+
+        Ok(assignment.and_then(|assignment| {
+            variants.into_iter().find(|variant| variant.id == assignment.variant_id)
+        }))
+    }
+
+    // Work out which variant an app user ID falls into without storing anything.
+    // Used for users we haven't seen yet; they land in the same variant once
+    // they exist and are assigned for real.
+    pub async fn preview_variant(
+        &self,
+        app_user_id: &str,
+        pool: &SqlitePool,
+    ) -> Result<Option<ExperimentVariant>, sqlx::Error> {
+        let mut variants = self.get_variants(pool).await?;
+
+        Ok(self
+            .bucket(app_user_id, &variants)
+            .map(|index| variants.swap_remove(index)))
+    }
+
+    fn bucket(&self, app_user_id: &str, variants: &[ExperimentVariant]) -> Option<usize> {
+        let weights: Vec<u32> = variants.iter().map(|variant| variant.weight.max(0) as u32).collect();
+        let key = format!("{}:{}", self.id, app_user_id);
+
+        weighted_bucket(&key, &weights)
+    }
+
+    // Get all variants for this experiment, control first
+    pub async fn get_variants(&self, pool: &SqlitePool) -> Result<Vec<ExperimentVariant>, sqlx::Error> {
+        let variants = sqlx::query_as::<_, ExperimentVariant>(
+            r#"
+            SELECT * FROM experiment_variants WHERE experiment_id = ?
+            ORDER BY position
+            "#,
+        )
+        .bind(&self.id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(variants)
+    }
+}
+
+impl ExperimentVariant {
+    pub fn new(
+        experiment_id: String,
+        identifier: String,
+        offering_id: String,
+        weight: i32,
+        position: i32,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            experiment_id,
+            identifier,
+            offering_id,
+            weight,
+            position,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO experiment_variants (
+                id, experiment_id, identifier, offering_id, weight, position, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.experiment_id)
+        .bind(&self.identifier)
+        .bind(&self.offering_id)
+        .bind(self.weight)
+        .bind(self.position)
+        .bind(self.created_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Count assigned users and the purchases they made between being assigned
+    // and the experiment stopping. Only products offered by one of the
+    // experiment's variants count, so other purchases don't skew the results.
+    // A user converts with a paid purchase, or when a trial they started
+    // renews into its first paid period on the same subscription.
+    // Revenue is the net USD amount from the ledger, so refunds are subtracted.
+    pub async fn get_stats(&self, pool: &SqlitePool) -> Result<ExperimentVariantStats, sqlx::Error> {
+        let stats = sqlx::query_as::<_, ExperimentVariantStats>(
+            r#"
+            WITH experiment AS (
+                SELECT e.id, COALESCE(e.stopped_at, ?2) AS ended_at
+                FROM experiments e
+                JOIN experiment_variants v ON v.experiment_id = e.id
+                WHERE v.id = ?1
+            ),
+            tested_products AS (
+                SELECT p.product_id
+                FROM packages p
+                JOIN experiment_variants v ON v.offering_id = p.offering_id
+                WHERE v.experiment_id = (SELECT id FROM experiment)
+            ),
+            purchases AS (
+                SELECT a.user_id, a.assigned_at, s.id, s.is_trial, s.status
+                FROM experiment_assignments a
+                JOIN subscriptions s ON s.user_id = a.user_id
+                    AND s.purchase_date >= a.assigned_at
+                    AND s.purchase_date <= (SELECT ended_at FROM experiment)
+                WHERE a.variant_id = ?1 AND s.product_id IN (SELECT product_id FROM tested_products)
+            ),
+            ledger AS (
+                SELECT t.subscription_id, t.type, t.transaction_date, t.amount_usd, t.proceeds_usd
+                FROM experiment_assignments a
+                JOIN transactions t ON t.user_id = a.user_id
+                    AND t.transaction_date >= a.assigned_at
+                    AND t.transaction_date <= (SELECT ended_at FROM experiment)
+                JOIN subscriptions s ON s.id = t.subscription_id
+                WHERE a.variant_id = ?1 AND s.product_id IN (SELECT product_id FROM tested_products)
+            )
+            SELECT
+                (SELECT COUNT(*) FROM experiment_assignments WHERE variant_id = ?1) AS users,
+                (SELECT COUNT(DISTINCT user_id) FROM purchases WHERE is_trial = TRUE) AS trial_starts,
+                (
+                    SELECT COUNT(DISTINCT p.user_id)
+                    FROM purchases p
+                    WHERE (p.is_trial = FALSE AND p.status != 'refunded')
+                        OR EXISTS (
+                            SELECT 1 FROM ledger t
+                            WHERE t.subscription_id = p.id
+                                AND t.type = 'renewal'
+                                AND t.transaction_date >= p.assigned_at
+                        )
+                ) AS conversions,
+                (SELECT CAST(COALESCE(SUM(amount_usd), 0) AS REAL) FROM ledger) AS revenue,
+                (SELECT CAST(COALESCE(SUM(proceeds_usd), 0) AS REAL) FROM ledger) AS proceeds
+            "#,
+        )
+        .bind(&self.id)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await?;
+
+        Ok(stats)
+    }
+}
+
+impl ExperimentAssignment {
+    pub fn new(experiment_id: String, variant_id: String, user_id: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            experiment_id,
+            variant_id,
+            user_id,
+            assigned_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        // A user keeps their first assignment, so concurrent requests can't move them
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO experiment_assignments (
+                id, experiment_id, variant_id, user_id, assigned_at
+            )
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.experiment_id)
+        .bind(&self.variant_id)
+        .bind(&self.user_id)
+        .bind(self.assigned_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_for_user(
+        experiment_id: &str,
+        user_id: &str,
+        pool: &SqlitePool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let assignment = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM experiment_assignments
+            WHERE experiment_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(experiment_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(assignment)
+    }
+}
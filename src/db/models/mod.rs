@@ -2,8 +2,40 @@ pub mod user;
 pub mod product;
 pub mod subscription;
 pub mod entitlement;
+pub mod app;
+pub mod offering;
+pub mod experiment;
+pub mod transaction;
+pub mod exchange_rate;
+pub mod commission;
+pub mod store_report;
+pub mod wallet;
+pub mod subscription_event;
+pub mod consumption_request;
+pub mod account_token;
+pub mod unattributed_purchase;
+pub mod user_alias;
+pub mod user_attribute;
+pub mod audit_log;
+pub mod promo_code;
 
 pub use user::*;
 pub use product::*;
 pub use subscription::*;
 pub use entitlement::*;
+pub use app::*;
+pub use offering::*;
+pub use experiment::*;
+pub use transaction::*;
+pub use exchange_rate::*;
+pub use commission::*;
+pub use store_report::*;
+pub use wallet::*;
+pub use subscription_event::*;
+pub use consumption_request::*;
+pub use account_token::*;
+pub use unattributed_purchase::*;
+pub use user_alias::*;
+pub use user_attribute::*;
+pub use audit_log::*;
+pub use promo_code::*;
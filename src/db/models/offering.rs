@@ -0,0 +1,293 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use sqlx::types::Json;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Offering {
+    pub id: String,
+    pub app_id: String,
+    pub identifier: String,
+    pub description: Option<String>,
+    pub metadata: Json<serde_json::Value>,
+    pub is_current: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Package {
+    pub id: String,
+    pub offering_id: String,
+    pub identifier: String,  // e.g. '$rc_monthly', '$rc_annual', '$rc_lifetime'
+    pub product_id: String,
+    pub position: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Offering {
+    pub fn new(
+        app_id: String,
+        identifier: String,
+        description: Option<String>,
+        metadata: serde_json::Value,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            app_id,
+            identifier,
+            description,
+            metadata: Json(metadata),
+            is_current: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO offerings (
+                id, app_id, identifier, description, metadata, is_current, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.app_id)
+        .bind(&self.identifier)
+        .bind(&self.description)
+        .bind(&self.metadata)
+        .bind(self.is_current)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let offering = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM offerings WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(offering)
+    }
+
+    pub async fn find_by_identifier(
+        app_id: &str,
+        identifier: &str,
+        pool: &SqlitePool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let offering = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM offerings WHERE app_id = ? AND identifier = ?
+            "#,
+        )
+        .bind(app_id)
+        .bind(identifier)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(offering)
+    }
+
+    pub async fn find_current_for_app(app_id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let offering = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM offerings WHERE app_id = ? AND is_current = TRUE
+            LIMIT 1
+            "#,
+        )
+        .bind(app_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(offering)
+    }
+
+    // The app's offerings by identifier, starting after the offering with ID
+    // `starting_after`
+    pub async fn list_by_app(
+        app_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let offerings = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM offerings
+            WHERE app_id = ?
+              AND (? IS NULL OR (identifier, rowid) > (SELECT identifier, rowid FROM offerings WHERE id = ?))
+            ORDER BY identifier ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(app_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(offerings)
+    }
+
+    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE offerings
+            SET identifier = ?, description = ?, metadata = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&self.identifier)
+        .bind(&self.description)
+        .bind(&self.metadata)
+        .bind(Utc::now())
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Make this the offering served to the app, replacing the previous one
+    pub async fn make_current(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE offerings
+            SET is_current = FALSE, updated_at = ?
+            WHERE app_id = ? AND is_current = TRUE
+            "#,
+        )
+        .bind(now)
+        .bind(&self.app_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE offerings
+            SET is_current = TRUE, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(now)
+        .bind(&self.id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        self.is_current = true;
+        self.updated_at = now;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM offerings WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Get all packages in this offering, in display order
+    pub async fn get_packages(&self, pool: &SqlitePool) -> Result<Vec<Package>, sqlx::Error> {
+        Package::list_by_offering(&self.id, pool).await
+    }
+}
+
+impl Package {
+    pub fn new(offering_id: String, identifier: String, product_id: String, position: i32) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            offering_id,
+            identifier,
+            product_id,
+            position,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO packages (
+                id, offering_id, identifier, product_id, position, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.offering_id)
+        .bind(&self.identifier)
+        .bind(&self.product_id)
+        .bind(self.position)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let package = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM packages WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(package)
+    }
+
+    pub async fn list_by_offering(offering_id: &str, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let packages = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM packages WHERE offering_id = ?
+            ORDER BY position, identifier
+            "#,
+        )
+        .bind(offering_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(packages)
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM packages WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
 use uuid::Uuid;
 
+use crate::db::models::ProductChangeType;
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Product {
     pub id: String,
@@ -10,9 +12,14 @@ pub struct Product {
     pub description: Option<String>,
     pub apple_product_id: Option<String>,
     pub google_product_id: Option<String>,
-    pub type_: String,  // 'subscription' or 'one_time'
+    #[sqlx(rename = "type")]
+    pub type_: String,  // 'subscription', 'non_renewing', 'one_time' or 'consumable'
     pub price_usd: Option<f64>,
     pub duration_days: Option<i32>,
+    pub virtual_currency: Option<String>,
+    pub virtual_currency_amount: Option<i64>,
+    pub subscription_group: Option<String>,
+    pub group_level: Option<i32>,  // 1 is the highest tier in the group
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -20,14 +27,18 @@ pub struct Product {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ProductType {
     Subscription,
+    NonRenewing,
     OneTime,
+    Consumable,
 }
 
 impl ToString for ProductType {
     fn to_string(&self) -> String {
         match self {
             ProductType::Subscription => "subscription".to_string(),
+            ProductType::NonRenewing => "non_renewing".to_string(),
             ProductType::OneTime => "one_time".to_string(),
+            ProductType::Consumable => "consumable".to_string(),
         }
     }
 }
@@ -51,19 +62,24 @@ impl Product {
             type_: type_.to_string(),
             price_usd,
             duration_days,
+            virtual_currency: None,
+            virtual_currency_amount: None,
+            subscription_group: None,
+            group_level: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
 
-    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             INSERT INTO products (
                 id, name, description, apple_product_id, google_product_id, 
-                type, price_usd, duration_days, created_at, updated_at
+                type, price_usd, duration_days, virtual_currency, virtual_currency_amount,
+                subscription_group, group_level, created_at, updated_at
             )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&self.id)
@@ -74,22 +90,26 @@ impl Product {
         .bind(&self.type_)
         .bind(&self.price_usd)
         .bind(&self.duration_days)
+        .bind(&self.virtual_currency)
+        .bind(self.virtual_currency_amount)
+        .bind(&self.subscription_group)
+        .bind(self.group_level)
         .bind(&self.created_at)
         .bind(&self.updated_at)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_id(id: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
         let product = sqlx::query_as::<_, Self>(
             r#"
             SELECT * FROM products WHERE id = ?
             "#,
         )
         .bind(id)
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(product)
@@ -98,7 +118,7 @@ impl Product {
     pub async fn find_by_store_product_id(
         store: &str,
         store_product_id: &str,
-        pool: &SqlitePool,
+        executor: impl SqliteExecutor<'_>,
     ) -> Result<Option<Self>, sqlx::Error> {
         let query = match store {
             "apple" => "SELECT * FROM products WHERE apple_product_id = ?",
@@ -108,7 +128,7 @@ impl Product {
 
         let product = sqlx::query_as::<_, Self>(query)
             .bind(store_product_id)
-            .fetch_optional(pool)
+            .fetch_optional(executor)
             .await?;
 
         Ok(product)
@@ -126,12 +146,33 @@ impl Product {
         Ok(products)
     }
 
-    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Products by name, starting after the product with ID `starting_after`
+    pub async fn list(starting_after: Option<&str>, limit: i64, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let products = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM products
+            WHERE (? IS NULL OR (name, rowid) > (SELECT name, rowid FROM products WHERE id = ?))
+            ORDER BY name ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(products)
+    }
+
+    pub async fn update(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             UPDATE products
             SET name = ?, description = ?, apple_product_id = ?, google_product_id = ?,
-                type = ?, price_usd = ?, duration_days = ?, updated_at = ?
+                type = ?, price_usd = ?, duration_days = ?, virtual_currency = ?,
+                virtual_currency_amount = ?, subscription_group = ?, group_level = ?,
+                updated_at = ?
             WHERE id = ?
             "#,
         )
@@ -142,29 +183,50 @@ impl Product {
         .bind(&self.type_)
         .bind(&self.price_usd)
         .bind(&self.duration_days)
+        .bind(&self.virtual_currency)
+        .bind(self.virtual_currency_amount)
+        .bind(&self.subscription_group)
+        .bind(self.group_level)
         .bind(Utc::now())
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn delete(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             DELETE FROM products WHERE id = ?
             "#,
         )
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
+    // How moving from this product to another one in the same subscription
+    // group changes the tier. None if they aren't in the same group.
+    pub fn change_type(&self, to: &Product) -> Option<ProductChangeType> {
+        if self.subscription_group.is_none() || self.subscription_group != to.subscription_group {
+            return None;
+        }
+
+        let from_level = self.group_level.unwrap_or(i32::MAX);
+        let to_level = to.group_level.unwrap_or(i32::MAX);
+
+        Some(match to_level.cmp(&from_level) {
+            std::cmp::Ordering::Less => ProductChangeType::Upgrade,
+            std::cmp::Ordering::Greater => ProductChangeType::Downgrade,
+            std::cmp::Ordering::Equal => ProductChangeType::Crossgrade,
+        })
+    }
+
     // Add or update entitlement mapping
-    pub async fn add_entitlement(&self, entitlement_id: &str, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn add_entitlement(&self, entitlement_id: &str, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             INSERT OR IGNORE INTO product_entitlements (product_id, entitlement_id, created_at)
@@ -174,14 +236,14 @@ impl Product {
         .bind(&self.id)
         .bind(entitlement_id)
         .bind(Utc::now())
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
     // Remove entitlement mapping
-    pub async fn remove_entitlement(&self, entitlement_id: &str, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn remove_entitlement(&self, entitlement_id: &str, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             DELETE FROM product_entitlements 
@@ -190,14 +252,14 @@ impl Product {
         )
         .bind(&self.id)
         .bind(entitlement_id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
     // Get all entitlements for this product
-    pub async fn get_entitlements(&self, pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    pub async fn get_entitlements(&self, executor: impl SqliteExecutor<'_>) -> Result<Vec<String>, sqlx::Error> {
         let entitlements = sqlx::query_scalar::<_, String>(
             r#"
             SELECT entitlement_id FROM product_entitlements 
@@ -205,7 +267,7 @@ impl Product {
             "#,
         )
         .bind(&self.id)
-        .fetch_all(pool)
+        .fetch_all(executor)
         .await?;
 
         Ok(entitlements)
@@ -0,0 +1,372 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnection, SqliteExecutor, SqlitePool};
+use uuid::Uuid;
+
+use crate::db::models::GrantDuration;
+
+// A set of promo codes for one campaign
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PromoCodeBatch {
+    pub id: String,
+    pub name: String,
+    pub entitlement_id: String,
+    pub duration: String,  // A GrantDuration
+    pub max_redemptions: i64,  // How many users can redeem each code
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PromoCode {
+    pub id: String,
+    pub batch_id: String,
+    pub code: String,
+    pub redemption_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PromoCodeRedemption {
+    pub id: String,
+    pub batch_id: String,
+    pub code_id: String,
+    pub user_id: String,
+    pub user_entitlement_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PromoCodeBatchStats {
+    pub code_count: i64,
+    pub redeemed_codes: i64,  // Codes redeemed at least once
+    pub fully_redeemed_codes: i64,
+    pub redemption_count: i64,
+    pub remaining_redemptions: i64,
+    pub first_redeemed_at: Option<DateTime<Utc>>,
+    pub last_redeemed_at: Option<DateTime<Utc>>,
+}
+
+// Codes leave out 0, 1, I, L and O so they can't be misread
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 12;
+
+impl PromoCodeBatch {
+    pub fn new(
+        name: String,
+        entitlement_id: String,
+        duration: GrantDuration,
+        max_redemptions: i64,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            entitlement_id,
+            duration: duration.to_string(),
+            max_redemptions,
+            expires_at,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub fn grant_duration(&self) -> Option<GrantDuration> {
+        GrantDuration::parse(&self.duration)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO promo_code_batches (
+                id, name, entitlement_id, duration, max_redemptions,
+                expires_at, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.name)
+        .bind(&self.entitlement_id)
+        .bind(&self.duration)
+        .bind(self.max_redemptions)
+        .bind(self.expires_at)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let batch = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM promo_code_batches WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(batch)
+    }
+
+    // Batches, newest first, starting after the batch with ID `starting_after`
+    pub async fn list(
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let batches = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM promo_code_batches
+            WHERE (? IS NULL OR (created_at, rowid) < (SELECT created_at, rowid FROM promo_code_batches WHERE id = ?))
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(batches)
+    }
+
+    // Add `count` new random codes to the batch, regenerating any that
+    // happen to already exist
+    pub async fn generate_codes(&self, count: usize, conn: &mut SqliteConnection) -> Result<Vec<PromoCode>, sqlx::Error> {
+        let mut codes = Vec::with_capacity(count);
+
+        while codes.len() < count {
+            let code = PromoCode::new(self.id.clone(), random_code());
+
+            let result = sqlx::query(
+                r#"
+                INSERT INTO promo_codes (id, batch_id, code, redemption_count, created_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT(code) DO NOTHING
+                "#,
+            )
+            .bind(&code.id)
+            .bind(&code.batch_id)
+            .bind(&code.code)
+            .bind(code.redemption_count)
+            .bind(code.created_at)
+            .execute(&mut *conn)
+            .await?;
+
+            if result.rows_affected() > 0 {
+                codes.push(code);
+            }
+        }
+
+        Ok(codes)
+    }
+
+    pub async fn stats(&self, pool: &SqlitePool) -> Result<PromoCodeBatchStats, sqlx::Error> {
+        let stats = sqlx::query_as::<_, PromoCodeBatchStats>(
+            r#"
+            SELECT
+                COUNT(*) AS code_count,
+                COALESCE(SUM(redemption_count > 0), 0) AS redeemed_codes,
+                COALESCE(SUM(redemption_count >= ?), 0) AS fully_redeemed_codes,
+                COALESCE(SUM(redemption_count), 0) AS redemption_count,
+                COALESCE(SUM(MAX(? - redemption_count, 0)), 0) AS remaining_redemptions,
+                (SELECT MIN(created_at) FROM promo_code_redemptions WHERE batch_id = ?) AS first_redeemed_at,
+                (SELECT MAX(created_at) FROM promo_code_redemptions WHERE batch_id = ?) AS last_redeemed_at
+            FROM promo_codes
+            WHERE batch_id = ?
+            "#,
+        )
+        .bind(self.max_redemptions)
+        .bind(self.max_redemptions)
+        .bind(&self.id)
+        .bind(&self.id)
+        .bind(&self.id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(stats)
+    }
+}
+
+fn random_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CODE_LENGTH)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+impl PromoCode {
+    pub fn new(batch_id: String, code: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            batch_id,
+            code,
+            redemption_count: 0,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Codes are matched ignoring case, spaces and dashes, so they can be
+    // typed in however they were printed
+    pub fn normalize(code: &str) -> String {
+        code.chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect::<String>()
+            .to_uppercase()
+    }
+
+    pub async fn find_by_code(code: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let promo_code = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM promo_codes WHERE code = ?
+            "#,
+        )
+        .bind(Self::normalize(code))
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(promo_code)
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let promo_code = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM promo_codes WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(promo_code)
+    }
+
+    // The batch's codes in the order they were generated, starting after the
+    // code with ID `starting_after`
+    pub async fn list_by_batch(
+        batch_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let codes = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM promo_codes
+            WHERE batch_id = ?
+              AND (? IS NULL OR (created_at, rowid) > (SELECT created_at, rowid FROM promo_codes WHERE id = ?))
+            ORDER BY created_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(batch_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(codes)
+    }
+
+    // Count a redemption of the code by the user, unless the code has already
+    // been redeemed `max_redemptions` times. Returns None if it has.
+    pub async fn redeem(
+        &self,
+        user_id: &str,
+        max_redemptions: i64,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ) -> Result<Option<PromoCodeRedemption>, sqlx::Error> {
+        let claimed = sqlx::query(
+            r#"
+            UPDATE promo_codes SET redemption_count = redemption_count + 1
+            WHERE id = ? AND redemption_count < ?
+            "#,
+        )
+        .bind(&self.id)
+        .bind(max_redemptions)
+        .execute(&mut **tx)
+        .await?;
+
+        if claimed.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let redemption = PromoCodeRedemption {
+            id: Uuid::new_v4().to_string(),
+            batch_id: self.batch_id.clone(),
+            code_id: self.id.clone(),
+            user_id: user_id.to_string(),
+            user_entitlement_id: None,
+            created_at: Utc::now(),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO promo_code_redemptions (id, batch_id, code_id, user_id, user_entitlement_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&redemption.id)
+        .bind(&redemption.batch_id)
+        .bind(&redemption.code_id)
+        .bind(&redemption.user_id)
+        .bind(&redemption.user_entitlement_id)
+        .bind(redemption.created_at)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(Some(redemption))
+    }
+}
+
+impl PromoCodeRedemption {
+    pub async fn find_by_batch_and_user(
+        batch_id: &str,
+        user_id: &str,
+        pool: &SqlitePool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let redemption = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM promo_code_redemptions WHERE batch_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(batch_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(redemption)
+    }
+
+    // Link the redemption to the grant it created or extended
+    pub async fn set_user_entitlement(
+        &mut self,
+        user_entitlement_id: &str,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::Error> {
+        self.user_entitlement_id = Some(user_entitlement_id.to_string());
+
+        sqlx::query(
+            r#"
+            UPDATE promo_code_redemptions SET user_entitlement_id = ? WHERE id = ?
+            "#,
+        )
+        .bind(&self.user_entitlement_id)
+        .bind(&self.id)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct StoreReport {
+    pub id: String,
+    pub store: String,  // 'apple' or 'google'
+    pub report_type: String,
+    pub filename: Option<String>,
+    pub content_hash: String,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub row_count: i64,
+    pub skipped_count: i64,
+    pub imported_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct StoreReportRow {
+    pub id: String,
+    pub report_id: String,
+    pub event_date: NaiveDate,
+    pub kind: String,  // 'sale' or 'refund'
+    pub order_id: Option<String>,
+    pub store_product_id: Option<String>,
+    pub quantity: i64,
+    pub amount: Option<f64>,
+    pub currency: Option<String>,
+    pub proceeds: Option<f64>,
+    pub proceeds_currency: Option<String>,
+    pub country_code: Option<String>,
+    pub matched_quantity: i64,
+    pub matched_transaction_id: Option<String>,
+    pub raw_data: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum StoreReportType {
+    AppleSales,
+    AppleSubscriptionEvents,
+    GoogleEarnings,
+    GoogleSales,
+}
+
+impl StoreReportType {
+    pub fn store(&self) -> &'static str {
+        match self {
+            StoreReportType::AppleSales | StoreReportType::AppleSubscriptionEvents => "apple",
+            StoreReportType::GoogleEarnings | StoreReportType::GoogleSales => "google",
+        }
+    }
+}
+
+impl fmt::Display for StoreReportType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreReportType::AppleSales => write!(f, "apple_sales"),
+            StoreReportType::AppleSubscriptionEvents => write!(f, "apple_subscription_events"),
+            StoreReportType::GoogleEarnings => write!(f, "google_earnings"),
+            StoreReportType::GoogleSales => write!(f, "google_sales"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ReportEventKind {
+    Sale,
+    Refund,
+}
+
+impl fmt::Display for ReportEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportEventKind::Sale => write!(f, "sale"),
+            ReportEventKind::Refund => write!(f, "refund"),
+        }
+    }
+}
+
+impl StoreReport {
+    pub fn new(report_type: StoreReportType, filename: Option<String>, content_hash: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            store: report_type.store().to_string(),
+            report_type: report_type.to_string(),
+            filename,
+            content_hash,
+            start_date: None,
+            end_date: None,
+            row_count: 0,
+            skipped_count: 0,
+            imported_at: Utc::now(),
+        }
+    }
+
+    // Save the report and its rows together so a failed import leaves nothing behind
+    pub async fn create_with_rows(&self, rows: &[StoreReportRow], pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO store_reports (
+                id, store, report_type, filename, content_hash, start_date, end_date,
+                row_count, skipped_count, imported_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.store)
+        .bind(&self.report_type)
+        .bind(&self.filename)
+        .bind(&self.content_hash)
+        .bind(self.start_date)
+        .bind(self.end_date)
+        .bind(self.row_count)
+        .bind(self.skipped_count)
+        .bind(self.imported_at)
+        .execute(&mut *tx)
+        .await?;
+
+        for row in rows {
+            sqlx::query(
+                r#"
+                INSERT INTO store_report_rows (
+                    id, report_id, event_date, kind, order_id, store_product_id, quantity,
+                    amount, currency, proceeds, proceeds_currency, country_code,
+                    matched_quantity, matched_transaction_id, raw_data
+                )
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&row.id)
+            .bind(&self.id)
+            .bind(row.event_date)
+            .bind(&row.kind)
+            .bind(&row.order_id)
+            .bind(&row.store_product_id)
+            .bind(row.quantity)
+            .bind(row.amount)
+            .bind(&row.currency)
+            .bind(row.proceeds)
+            .bind(&row.proceeds_currency)
+            .bind(&row.country_code)
+            .bind(row.matched_quantity)
+            .bind(&row.matched_transaction_id)
+            .bind(&row.raw_data)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let report = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM store_reports WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(report)
+    }
+
+    pub async fn find_by_content_hash(
+        store: &str,
+        content_hash: &str,
+        pool: &SqlitePool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let report = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM store_reports WHERE store = ? AND content_hash = ?
+            "#,
+        )
+        .bind(store)
+        .bind(content_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(report)
+    }
+
+    // Imported reports, newest first, starting after the report with ID
+    // `starting_after`
+    pub async fn list(
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let reports = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM store_reports
+            WHERE (? IS NULL OR (imported_at, rowid) < (SELECT imported_at, rowid FROM store_reports WHERE id = ?))
+            ORDER BY imported_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(reports)
+    }
+
+    pub async fn get_rows(&self, pool: &SqlitePool) -> Result<Vec<StoreReportRow>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, StoreReportRow>(
+            r#"
+            SELECT * FROM store_report_rows WHERE report_id = ? ORDER BY event_date, rowid
+            "#,
+        )
+        .bind(&self.id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Store the results of matching the rows against the ledger
+    pub async fn update_matches(&self, rows: &[StoreReportRow], pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        for row in rows {
+            sqlx::query(
+                r#"
+                UPDATE store_report_rows
+                SET matched_quantity = ?, matched_transaction_id = ?
+                WHERE id = ? AND report_id = ?
+                "#,
+            )
+            .bind(row.matched_quantity)
+            .bind(&row.matched_transaction_id)
+            .bind(&row.id)
+            .bind(&self.id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM store_reports WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl StoreReportRow {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        event_date: NaiveDate,
+        kind: ReportEventKind,
+        order_id: Option<String>,
+        store_product_id: Option<String>,
+        quantity: i64,
+        amount: Option<f64>,
+        currency: Option<String>,
+        country_code: Option<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            report_id: String::new(),
+            event_date,
+            kind: kind.to_string(),
+            order_id,
+            store_product_id,
+            quantity,
+            amount,
+            currency,
+            proceeds: None,
+            proceeds_currency: None,
+            country_code,
+            matched_quantity: 0,
+            matched_transaction_id: None,
+            raw_data: None,
+        }
+    }
+}
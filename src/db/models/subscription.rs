@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteConnection, SqliteExecutor, SqlitePool};
+use sqlx::Connection;
+use std::fmt;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -15,12 +17,21 @@ pub struct Subscription {
     pub expires_date: Option<DateTime<Utc>>,
     pub cancellation_date: Option<DateTime<Utc>>,
     pub renewal_grace_period_expires_date: Option<DateTime<Utc>>,
-    pub status: String,  // 'active', 'expired', 'cancelled', 'grace_period', etc.
+    pub status: String,  // 'active', 'expired', 'cancelled', 'grace_period', 'billing_retry', etc.
     pub auto_renew_status: Option<bool>,
     pub price_paid: Option<f64>,
     pub currency: Option<String>,
     pub is_trial: bool,
     pub is_intro_offer: bool,
+    pub country_code: Option<String>,
+    pub period_start_date: Option<DateTime<Utc>>,  // Only set for non-renewing purchases
+    pub pending_product_id: Option<String>,  // Product change that takes effect at renewal
+    pub previous_subscription_id: Option<String>,  // Subscription this replaced after a product change
+    pub auto_resume_date: Option<DateTime<Utc>>,  // When a paused subscription resumes
+    pub billing_issue_detected_at: Option<DateTime<Utc>>,  // When a failed renewal was first seen
+    pub expiration_reason: Option<String>,  // Why the subscription stopped renewing
+    pub ownership_type: String,  // 'purchased' or 'family_shared'
+    pub family_owner_subscription_id: Option<String>,  // Purchaser's subscription a family share follows
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -31,6 +42,8 @@ pub enum SubscriptionStatus {
     Expired,
     Cancelled,
     GracePeriod,
+    BillingRetry,
+    OnHold,
     Refunded,
     Paused,
 }
@@ -42,12 +55,82 @@ impl ToString for SubscriptionStatus {
             SubscriptionStatus::Expired => "expired".to_string(),
             SubscriptionStatus::Cancelled => "cancelled".to_string(),
             SubscriptionStatus::GracePeriod => "grace_period".to_string(),
+            SubscriptionStatus::BillingRetry => "billing_retry".to_string(),
+            SubscriptionStatus::OnHold => "on_hold".to_string(),
             SubscriptionStatus::Refunded => "refunded".to_string(),
             SubscriptionStatus::Paused => "paused".to_string(),
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum OwnershipType {
+    Purchased,
+    FamilyShared,
+}
+
+impl OwnershipType {
+    // Parse Apple's inAppOwnershipType
+    pub fn from_apple(value: &str) -> Self {
+        match value {
+            "FAMILY_SHARED" => OwnershipType::FamilyShared,
+            _ => OwnershipType::Purchased,
+        }
+    }
+}
+
+impl fmt::Display for OwnershipType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OwnershipType::Purchased => write!(f, "purchased"),
+            OwnershipType::FamilyShared => write!(f, "family_shared"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ExpirationReason {
+    Voluntary,
+    BillingError,
+    PriceIncrease,
+    ProductNotForSale,
+    Other,
+}
+
+impl ExpirationReason {
+    // Churn the customer chose, as opposed to a payment that failed
+    pub fn is_voluntary(&self) -> bool {
+        matches!(self, ExpirationReason::Voluntary | ExpirationReason::PriceIncrease)
+    }
+
+    pub fn is_involuntary(&self) -> bool {
+        matches!(self, ExpirationReason::BillingError)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "voluntary" => Some(ExpirationReason::Voluntary),
+            "billing_error" => Some(ExpirationReason::BillingError),
+            "price_increase" => Some(ExpirationReason::PriceIncrease),
+            "product_not_for_sale" => Some(ExpirationReason::ProductNotForSale),
+            "other" => Some(ExpirationReason::Other),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExpirationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpirationReason::Voluntary => write!(f, "voluntary"),
+            ExpirationReason::BillingError => write!(f, "billing_error"),
+            ExpirationReason::PriceIncrease => write!(f, "price_increase"),
+            ExpirationReason::ProductNotForSale => write!(f, "product_not_for_sale"),
+            ExpirationReason::Other => write!(f, "other"),
+        }
+    }
+}
+
 impl Subscription {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -82,22 +165,33 @@ impl Subscription {
             currency,
             is_trial,
             is_intro_offer,
+            country_code: None,
+            period_start_date: None,
+            pending_product_id: None,
+            previous_subscription_id: None,
+            auto_resume_date: None,
+            billing_issue_detected_at: None,
+            expiration_reason: None,
+            ownership_type: OwnershipType::Purchased.to_string(),
+            family_owner_subscription_id: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
 
-    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             INSERT INTO subscriptions (
                 id, user_id, product_id, original_transaction_id, store_transaction_id,
                 store, purchase_date, expires_date, cancellation_date, 
                 renewal_grace_period_expires_date, status, auto_renew_status,
-                price_paid, currency, is_trial, is_intro_offer,
-                created_at, updated_at
+                price_paid, currency, is_trial, is_intro_offer, country_code,
+                period_start_date, pending_product_id, previous_subscription_id,
+                auto_resume_date, billing_issue_detected_at, expiration_reason, ownership_type,
+                family_owner_subscription_id, created_at, updated_at
             )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&self.id)
@@ -116,68 +210,132 @@ impl Subscription {
         .bind(&self.currency)
         .bind(&self.is_trial)
         .bind(&self.is_intro_offer)
+        .bind(&self.country_code)
+        .bind(self.period_start_date)
+        .bind(&self.pending_product_id)
+        .bind(&self.previous_subscription_id)
+        .bind(self.auto_resume_date)
+        .bind(self.billing_issue_detected_at)
+        .bind(&self.expiration_reason)
+        .bind(&self.ownership_type)
+        .bind(&self.family_owner_subscription_id)
         .bind(&self.created_at)
         .bind(&self.updated_at)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_id(id: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
         let subscription = sqlx::query_as::<_, Self>(
             r#"
             SELECT * FROM subscriptions WHERE id = ?
             "#,
         )
         .bind(id)
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(subscription)
     }
 
+    // Product changes can leave several subscriptions with the same original
+    // transaction, so this returns the newest one. Family shares of the
+    // transaction are left out, so this is always the purchaser's subscription.
     pub async fn find_by_store_transaction(
         store: &str,
         transaction_id: &str,
-        pool: &SqlitePool,
+        executor: impl SqliteExecutor<'_>,
     ) -> Result<Option<Self>, sqlx::Error> {
         let subscription = sqlx::query_as::<_, Self>(
             r#"
             SELECT * FROM subscriptions 
             WHERE store = ? AND (store_transaction_id = ? OR original_transaction_id = ?)
+              AND ownership_type = 'purchased'
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT 1
             "#,
         )
         .bind(store)
         .bind(transaction_id)
         .bind(transaction_id)
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(subscription)
     }
 
-    pub async fn find_active_by_user_and_product(
-        user_id: &str,
-        product_id: &str,
-        pool: &SqlitePool,
+    // A family member's subscription, by the transaction Apple gave them
+    pub async fn find_family_share(
+        store: &str,
+        transaction_id: &str,
+        executor: impl SqliteExecutor<'_>,
     ) -> Result<Option<Self>, sqlx::Error> {
         let subscription = sqlx::query_as::<_, Self>(
             r#"
-            SELECT * FROM subscriptions 
-            WHERE user_id = ? AND product_id = ? AND status = 'active'
-            ORDER BY expires_date DESC 
-            LIMIT 1
+            SELECT * FROM subscriptions
+            WHERE store = ? AND store_transaction_id = ? AND ownership_type = 'family_shared'
             "#,
         )
-        .bind(user_id)
-        .bind(product_id)
-        .fetch_optional(pool)
+        .bind(store)
+        .bind(transaction_id)
+        .fetch_optional(executor)
         .await?;
 
         Ok(subscription)
     }
 
+    // Family members keep access as long as the purchaser does. Copy the
+    // purchaser's status and expiry to the shares still following this
+    // subscription, and line their entitlements up with the purchaser's.
+    // Shares Apple revoked have a cancellation date and are left alone.
+    pub async fn sync_family_shares(&self, conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = conn.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE subscriptions
+            SET status = ?, expires_date = ?, renewal_grace_period_expires_date = ?, updated_at = ?
+            WHERE family_owner_subscription_id = ? AND cancellation_date IS NULL
+            "#,
+        )
+        .bind(&self.status)
+        .bind(self.expires_date)
+        .bind(self.renewal_grace_period_expires_date)
+        .bind(now)
+        .bind(&self.id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE user_entitlements
+            SET expires_at = (
+                    SELECT MAX(owner.expires_at) FROM user_entitlements owner
+                    WHERE owner.subscription_id = ?
+                ),
+                updated_at = ?
+            WHERE subscription_id IN (
+                SELECT id FROM subscriptions
+                WHERE family_owner_subscription_id = ? AND cancellation_date IS NULL
+            )
+            AND EXISTS (SELECT 1 FROM user_entitlements owner WHERE owner.subscription_id = ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(now)
+        .bind(&self.id)
+        .bind(&self.id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     pub async fn list_by_user(user_id: &str, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
         let subscriptions = sqlx::query_as::<_, Self>(
             r#"
@@ -193,22 +351,105 @@ impl Subscription {
         Ok(subscriptions)
     }
 
-    pub async fn list_active_by_user(user_id: &str, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+    // The user's subscriptions, latest purchase first, starting after the
+    // subscription with ID `starting_after`
+    pub async fn list_page_by_user(
+        user_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
         let subscriptions = sqlx::query_as::<_, Self>(
             r#"
-            SELECT * FROM subscriptions 
+            SELECT * FROM subscriptions
+            WHERE user_id = ?
+              AND (? IS NULL OR (purchase_date, rowid) < (SELECT purchase_date, rowid FROM subscriptions WHERE id = ?))
+            ORDER BY purchase_date DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    // The user's active subscriptions, latest expiry first, with ones that
+    // never expire at the top. Starts after the subscription with ID
+    // `starting_after`.
+    pub async fn list_active_by_user(
+        user_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let subscriptions = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM subscriptions
             WHERE user_id = ? AND status = 'active'
-            ORDER BY expires_date DESC
+              AND (? IS NULL OR (COALESCE(expires_date, '9999-12-31'), rowid) < (
+                  SELECT COALESCE(expires_date, '9999-12-31'), rowid FROM subscriptions WHERE id = ?
+              ))
+            ORDER BY COALESCE(expires_date, '9999-12-31') DESC, rowid DESC
+            LIMIT ?
             "#,
         )
         .bind(user_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
         .fetch_all(pool)
         .await?;
 
         Ok(subscriptions)
     }
 
-    pub async fn update_status(&mut self, status: SubscriptionStatus, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Subscriptions with a failing renewal payment, longest failing first.
+    // Starts after the subscription with ID `starting_after`.
+    pub async fn list_with_billing_issues(
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let subscriptions = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM subscriptions
+            WHERE billing_issue_detected_at IS NOT NULL
+              AND status IN ('grace_period', 'billing_retry', 'on_hold')
+              AND (? IS NULL OR (billing_issue_detected_at, rowid) > (
+                  SELECT billing_issue_detected_at, rowid FROM subscriptions WHERE id = ?
+              ))
+            ORDER BY billing_issue_detected_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    // Keep the time the problem was first seen through retries and grace periods
+    pub fn detect_billing_issue(&mut self, detected_at: DateTime<Utc>) {
+        if self.billing_issue_detected_at.is_none() {
+            self.billing_issue_detected_at = Some(detected_at);
+        }
+    }
+
+    // A renewal went through, so any payment problem is resolved
+    pub fn clear_billing_issue(&mut self) {
+        self.billing_issue_detected_at = None;
+        self.renewal_grace_period_expires_date = None;
+    }
+
+    pub async fn update_status(&mut self, status: SubscriptionStatus, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         self.status = status.to_string();
         self.updated_at = Utc::now();
         
@@ -222,13 +463,13 @@ impl Subscription {
         .bind(&self.status)
         .bind(&self.updated_at)
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn cancel(&mut self, cancellation_date: DateTime<Utc>, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn cancel(&mut self, cancellation_date: DateTime<Utc>, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         self.cancellation_date = Some(cancellation_date);
         self.status = SubscriptionStatus::Cancelled.to_string();
         self.auto_renew_status = Some(false);
@@ -246,13 +487,13 @@ impl Subscription {
         .bind(&self.auto_renew_status)
         .bind(&self.updated_at)
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn update_expiry(&mut self, expires_date: DateTime<Utc>, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn update_expiry(&mut self, expires_date: DateTime<Utc>, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         self.expires_date = Some(expires_date);
         self.updated_at = Utc::now();
         
@@ -266,13 +507,13 @@ impl Subscription {
         .bind(&self.expires_date)
         .bind(&self.updated_at)
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn update_auto_renew_status(&mut self, auto_renew: bool, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn update_auto_renew_status(&mut self, auto_renew: bool, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         self.auto_renew_status = Some(auto_renew);
         self.updated_at = Utc::now();
         
@@ -286,13 +527,13 @@ impl Subscription {
         .bind(&self.auto_renew_status)
         .bind(&self.updated_at)
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn update(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             UPDATE subscriptions
@@ -301,7 +542,10 @@ impl Subscription {
                 expires_date = ?, cancellation_date = ?, 
                 renewal_grace_period_expires_date = ?, status = ?,
                 auto_renew_status = ?, price_paid = ?, currency = ?,
-                is_trial = ?, is_intro_offer = ?, updated_at = ?
+                is_trial = ?, is_intro_offer = ?, country_code = ?, period_start_date = ?,
+                pending_product_id = ?, previous_subscription_id = ?, auto_resume_date = ?,
+                billing_issue_detected_at = ?, expiration_reason = ?, ownership_type = ?,
+                family_owner_subscription_id = ?, updated_at = ?
             WHERE id = ?
             "#,
         )
@@ -320,11 +564,170 @@ impl Subscription {
         .bind(&self.currency)
         .bind(&self.is_trial)
         .bind(&self.is_intro_offer)
+        .bind(&self.country_code)
+        .bind(self.period_start_date)
+        .bind(&self.pending_product_id)
+        .bind(&self.previous_subscription_id)
+        .bind(self.auto_resume_date)
+        .bind(self.billing_issue_detected_at)
+        .bind(&self.expiration_reason)
+        .bind(&self.ownership_type)
+        .bind(&self.family_owner_subscription_id)
         .bind(Utc::now())
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    // When a new non-renewing purchase of the product should start. Access
+    // stacks, so this is the end of the user's latest unexpired period, or now.
+    pub async fn next_period_start(
+        user_id: &str,
+        product_id: &str,
+        now: DateTime<Utc>,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<DateTime<Utc>, sqlx::Error> {
+        let periods = Self::list_periods(user_id, product_id, executor).await?;
+
+        Ok(periods
+            .iter()
+            .filter_map(|subscription| subscription.expires_date)
+            .fold(now, |start, expires_date| start.max(expires_date)))
+    }
+
+    // Non-renewing purchases of a product that haven't been refunded
+    async fn list_periods(user_id: &str, product_id: &str, executor: impl SqliteExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let subscriptions = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM subscriptions
+            WHERE user_id = ? AND product_id = ? AND status = 'active' AND period_start_date IS NOT NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(product_id)
+        .fetch_all(executor)
         .await?;
 
+        Ok(subscriptions)
+    }
+
+    // Remove the unused part of a refunded non-renewing period. Access ends
+    // now (or never starts, if the period hasn't begun), and periods stacked
+    // after this one move earlier by the time that was taken away.
+    pub async fn roll_back_period(
+        &mut self,
+        now: DateTime<Utc>,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let (start, end) = match (self.period_start_date, self.expires_date) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(()),
+        };
+
+        let access_end = start.max(now);
+        if access_end >= end {
+            return Ok(());
+        }
+        let removed = end - access_end;
+
+        let later_periods: Vec<Self> = Self::list_periods(&self.user_id, &self.product_id, &mut *conn)
+            .await?
+            .into_iter()
+            .filter(|subscription| subscription.id != self.id)
+            .filter(|subscription| subscription.period_start_date.is_some_and(|later_start| later_start >= end))
+            .collect();
+
+        let mut tx = conn.begin().await?;
+
+        self.expires_date = Some(access_end);
+        self.updated_at = Utc::now();
+        set_period(&self.id, start, access_end, &mut tx).await?;
+
+        for subscription in later_periods {
+            if let (Some(later_start), Some(later_end)) = (subscription.period_start_date, subscription.expires_date) {
+                set_period(&subscription.id, later_start - removed, later_end - removed, &mut tx).await?;
+            }
+        }
+
+        tx.commit().await?;
+
         Ok(())
     }
+
+    // Give back the time a refund took from a non-renewing period, after the
+    // store reversed the refund. The remaining time is stacked after the
+    // user's current periods, like a new purchase.
+    pub async fn restore_period(
+        &mut self,
+        duration: chrono::Duration,
+        now: DateTime<Utc>,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let (start, end) = match (self.period_start_date, self.expires_date) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(()),
+        };
+
+        let remaining = duration - (end - start);
+        if remaining <= chrono::Duration::zero() {
+            return Ok(());
+        }
+
+        let new_start = Self::next_period_start(&self.user_id, &self.product_id, now, &mut *conn)
+            .await?
+            .max(end);
+        let new_end = new_start + remaining;
+
+        let mut tx = conn.begin().await?;
+        set_period(&self.id, new_start, new_end, &mut tx).await?;
+        tx.commit().await?;
+
+        self.period_start_date = Some(new_start);
+        self.expires_date = Some(new_end);
+        self.updated_at = Utc::now();
+
+        Ok(())
+    }
+}
+
+// Move a non-renewing period and the entitlements it grants
+async fn set_period(
+    subscription_id: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+
+    sqlx::query(
+        r#"
+        UPDATE subscriptions
+        SET period_start_date = ?, expires_date = ?, updated_at = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(start)
+    .bind(end)
+    .bind(now)
+    .bind(subscription_id)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE user_entitlements
+        SET starts_at = ?, expires_at = ?, updated_at = ?
+        WHERE subscription_id = ?
+        "#,
+    )
+    .bind(start)
+    .bind(end)
+    .bind(now)
+    .bind(subscription_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
 }
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::db::models::Subscription;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SubscriptionEvent {
+    pub id: String,
+    pub subscription_id: String,
+    pub user_id: String,
+    #[sqlx(rename = "type")]
+    pub type_: String,
+    pub from_product_id: Option<String>,
+    pub to_product_id: Option<String>,
+    pub change_type: Option<String>,  // 'upgrade', 'downgrade' or 'crossgrade'
+    pub price: Option<f64>,  // New price for price changes
+    pub currency: Option<String>,
+    pub effective_date: DateTime<Utc>,
+    pub subscriber_attributes: Option<String>,  // JSON object of the user's attributes at the time
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SubscriptionEventType {
+    ProductChangeScheduled,
+    ProductChangeCanceled,
+    ProductChange,
+    PriceChangeConfirmed,
+    PriceChangeUpdated,
+    RenewalDeferred,
+    Paused,
+    PauseScheduleChanged,
+    Resubscribed,
+    BillingRecovered,
+    PriceChangePending,
+    AutoRenewEnabled,
+    AutoRenewDisabled,
+}
+
+impl fmt::Display for SubscriptionEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionEventType::ProductChangeScheduled => write!(f, "product_change_scheduled"),
+            SubscriptionEventType::ProductChangeCanceled => write!(f, "product_change_canceled"),
+            SubscriptionEventType::ProductChange => write!(f, "product_change"),
+            SubscriptionEventType::PriceChangeConfirmed => write!(f, "price_change_confirmed"),
+            SubscriptionEventType::PriceChangeUpdated => write!(f, "price_change_updated"),
+            SubscriptionEventType::RenewalDeferred => write!(f, "renewal_deferred"),
+            SubscriptionEventType::Paused => write!(f, "paused"),
+            SubscriptionEventType::PauseScheduleChanged => write!(f, "pause_schedule_changed"),
+            SubscriptionEventType::Resubscribed => write!(f, "resubscribed"),
+            SubscriptionEventType::BillingRecovered => write!(f, "billing_recovered"),
+            SubscriptionEventType::PriceChangePending => write!(f, "price_change_pending"),
+            SubscriptionEventType::AutoRenewEnabled => write!(f, "auto_renew_enabled"),
+            SubscriptionEventType::AutoRenewDisabled => write!(f, "auto_renew_disabled"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ProductChangeType {
+    Upgrade,
+    Downgrade,
+    Crossgrade,
+}
+
+impl fmt::Display for ProductChangeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProductChangeType::Upgrade => write!(f, "upgrade"),
+            ProductChangeType::Downgrade => write!(f, "downgrade"),
+            ProductChangeType::Crossgrade => write!(f, "crossgrade"),
+        }
+    }
+}
+
+impl SubscriptionEvent {
+    pub fn new(
+        subscription: &Subscription,
+        type_: SubscriptionEventType,
+        effective_date: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            subscription_id: subscription.id.clone(),
+            user_id: subscription.user_id.clone(),
+            type_: type_.to_string(),
+            from_product_id: None,
+            to_product_id: None,
+            change_type: None,
+            price: None,
+            currency: None,
+            effective_date,
+            subscriber_attributes: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn product_change(
+        subscription: &Subscription,
+        type_: SubscriptionEventType,
+        from_product_id: &str,
+        to_product_id: &str,
+        change_type: Option<ProductChangeType>,
+        effective_date: DateTime<Utc>,
+    ) -> Self {
+        let mut event = Self::new(subscription, type_, effective_date);
+        event.from_product_id = Some(from_product_id.to_string());
+        event.to_product_id = Some(to_product_id.to_string());
+        event.change_type = change_type.map(|change_type| change_type.to_string());
+        event
+    }
+
+    // The user's attributes are recorded with the event as they are now
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO subscription_events (
+                id, subscription_id, user_id, type, from_product_id, to_product_id,
+                change_type, price, currency, effective_date, subscriber_attributes, created_at
+            )
+            VALUES (
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+                (SELECT json_group_object(key, value) FROM user_attributes WHERE user_id = ? AND value IS NOT NULL),
+                ?
+            )
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.subscription_id)
+        .bind(&self.user_id)
+        .bind(&self.type_)
+        .bind(&self.from_product_id)
+        .bind(&self.to_product_id)
+        .bind(&self.change_type)
+        .bind(self.price)
+        .bind(&self.currency)
+        .bind(self.effective_date)
+        .bind(&self.user_id)
+        .bind(self.created_at)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    // The subscription's events in the order they happened, starting after the
+    // event with ID `starting_after`
+    pub async fn list_by_subscription(
+        subscription_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let events = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM subscription_events
+            WHERE subscription_id = ?
+              AND (? IS NULL OR (created_at, rowid) > (SELECT created_at, rowid FROM subscription_events WHERE id = ?))
+            ORDER BY created_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(subscription_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(events)
+    }
+
+    // Events across the user's subscriptions in the order they happened,
+    // starting after the event with ID `starting_after`
+    pub async fn list_by_user(
+        user_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let events = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM subscription_events
+            WHERE user_id = ?
+              AND (? IS NULL OR (created_at, rowid) > (SELECT created_at, rowid FROM subscription_events WHERE id = ?))
+            ORDER BY created_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(events)
+    }
+}
@@ -0,0 +1,478 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnection, SqliteExecutor, SqlitePool};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::db::models::{
+    proceeds, CommissionRule, ExchangeRate, Product, ProductType, Subscription, TaxRate,
+};
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Transaction {
+    pub id: String,
+    pub user_id: String,
+    pub subscription_id: String,
+    pub store_transaction_id: String,
+    pub store: String,  // 'apple' or 'google'
+    #[sqlx(rename = "type")]
+    pub type_: String,  // 'initial_purchase', 'renewal', 'refund', etc.
+    pub amount: Option<f64>,
+    pub currency: Option<String>,
+    pub amount_usd: Option<f64>,  // None until a rate for the currency is known
+    pub commission_rate: Option<f64>,
+    pub tax_rate: Option<f64>,
+    pub proceeds: Option<f64>,  // Estimated amount paid out by the store, in `currency`
+    pub proceeds_usd: Option<f64>,
+    pub transaction_date: DateTime<Utc>,
+    pub raw_data: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TransactionType {
+    InitialPurchase,
+    Renewal,
+    Refund,
+    RefundReversal,
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionType::InitialPurchase => write!(f, "initial_purchase"),
+            TransactionType::Renewal => write!(f, "renewal"),
+            TransactionType::Refund => write!(f, "refund"),
+            TransactionType::RefundReversal => write!(f, "refund_reversal"),
+        }
+    }
+}
+
+impl Transaction {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_id: String,
+        subscription_id: String,
+        store_transaction_id: String,
+        store: String,
+        type_: TransactionType,
+        amount: Option<f64>,
+        currency: Option<String>,
+        transaction_date: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            subscription_id,
+            store_transaction_id,
+            store,
+            type_: type_.to_string(),
+            amount,
+            currency,
+            amount_usd: None,
+            commission_rate: None,
+            tax_rate: None,
+            proceeds: None,
+            proceeds_usd: None,
+            transaction_date,
+            raw_data: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Build a ledger entry for the subscription's current store transaction.
+    // Refunds are recorded as negative amounts so revenue can be summed directly.
+    pub fn for_subscription(
+        subscription: &Subscription,
+        type_: TransactionType,
+        transaction_date: DateTime<Utc>,
+    ) -> Self {
+        let store_transaction_id = subscription
+            .store_transaction_id
+            .clone()
+            .or_else(|| subscription.original_transaction_id.clone())
+            .unwrap_or_else(|| subscription.id.clone());
+
+        let amount = match type_ {
+            TransactionType::Refund => subscription.price_paid.map(|amount| -amount),
+            _ => subscription.price_paid,
+        };
+
+        Self::new(
+            subscription.user_id.clone(),
+            subscription.id.clone(),
+            store_transaction_id,
+            subscription.store.clone(),
+            type_,
+            amount,
+            subscription.currency.clone(),
+            transaction_date,
+        )
+    }
+
+    // Record the transaction with its estimated proceeds, converting the
+    // amounts to USD at the current rate. Stores redeliver notifications, so
+    // an entry already recorded for the store transaction is left as it is.
+    pub async fn create(&mut self, conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        if self.proceeds.is_none() {
+            self.calculate_proceeds(&mut *conn).await?;
+        }
+
+        if self.amount_usd.is_none() {
+            if let Some(amount) = self.amount {
+                self.amount_usd = ExchangeRate::convert_to_usd(amount, self.currency.as_deref(), &mut *conn).await?;
+            }
+        }
+
+        if self.proceeds_usd.is_none() {
+            if let Some(proceeds) = self.proceeds {
+                self.proceeds_usd = ExchangeRate::convert_to_usd(proceeds, self.currency.as_deref(), &mut *conn).await?;
+            }
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO transactions (
+                id, user_id, subscription_id, store_transaction_id, store, type,
+                amount, currency, amount_usd, commission_rate, tax_rate, proceeds, proceeds_usd,
+                transaction_date, raw_data, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (store, store_transaction_id, type) DO NOTHING
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.user_id)
+        .bind(&self.subscription_id)
+        .bind(&self.store_transaction_id)
+        .bind(&self.store)
+        .bind(&self.type_)
+        .bind(self.amount)
+        .bind(&self.currency)
+        .bind(self.amount_usd)
+        .bind(self.commission_rate)
+        .bind(self.tax_rate)
+        .bind(self.proceeds)
+        .bind(self.proceeds_usd)
+        .bind(self.transaction_date)
+        .bind(&self.raw_data)
+        .bind(self.created_at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    // Estimate the store's payout from the commission rules and the tax rate
+    // of the subscription's country. Refunds and their reversals undo or
+    // restore the sale they refer to, so they reuse that sale's rates.
+    pub async fn calculate_proceeds(&mut self, conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let amount = match self.amount {
+            Some(amount) => amount,
+            None => return Ok(()),
+        };
+
+        let refunded_sale = if self.type_ == TransactionType::Refund.to_string()
+            || self.type_ == TransactionType::RefundReversal.to_string()
+        {
+            Self::find_latest_sale(&self.subscription_id, self.transaction_date, &mut *conn).await?
+        } else {
+            None
+        };
+
+        let (commission_rate, tax_rate) = match refunded_sale {
+            Some(Transaction {
+                commission_rate: Some(commission_rate),
+                tax_rate,
+                ..
+            }) => (commission_rate, tax_rate.unwrap_or(0.0)),
+            _ => self.current_rates(&mut *conn).await?,
+        };
+
+        self.commission_rate = Some(commission_rate);
+        self.tax_rate = Some(tax_rate);
+        self.proceeds = Some(proceeds(amount, commission_rate, tax_rate));
+
+        Ok(())
+    }
+
+    // Commission and tax rates that apply to a sale made now
+    async fn current_rates(&self, conn: &mut SqliteConnection) -> Result<(f64, f64), sqlx::Error> {
+        let subscription = Subscription::find_by_id(&self.subscription_id, &mut *conn).await?;
+
+        let product = match &subscription {
+            Some(subscription) => Product::find_by_id(&subscription.product_id, &mut *conn).await?,
+            None => None,
+        };
+        let is_subscription = product
+            .is_some_and(|product| product.type_ == ProductType::Subscription.to_string());
+
+        // Plan changes start a new subscription row, so paid days carry over
+        // from the subscriptions it replaced
+        let sales = Self::list_paid_periods(&self.subscription_id, self.transaction_date, &mut *conn).await?;
+        let paid_days = paid_days(&sales, self.transaction_date);
+
+        let rule = CommissionRule::find_by_store(&self.store, &mut *conn)
+            .await?
+            .unwrap_or_else(|| CommissionRule::standard(&self.store));
+
+        let tax_rate = match subscription.and_then(|subscription| subscription.country_code) {
+            Some(country_code) => TaxRate::find_by_country(&country_code, &mut *conn)
+                .await?
+                .map(|tax_rate| tax_rate.rate)
+                .unwrap_or(0.0),
+            None => 0.0,
+        };
+
+        Ok((rule.commission_rate(is_subscription, paid_days), tax_rate))
+    }
+
+    // Paid sales before `before` across the subscription, the ones it replaced
+    // and any that share its original store transaction, with the number of
+    // days each paid for
+    async fn list_paid_periods(
+        subscription_id: &str,
+        before: DateTime<Utc>,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<Vec<(DateTime<Utc>, Option<i32>)>, sqlx::Error> {
+        let sales = sqlx::query_as::<_, (DateTime<Utc>, Option<i32>)>(
+            r#"
+            WITH RECURSIVE chain(id) AS (
+                SELECT ?
+                UNION
+                SELECT s.previous_subscription_id
+                FROM subscriptions s
+                JOIN chain ON s.id = chain.id
+                WHERE s.previous_subscription_id IS NOT NULL
+            ),
+            related(id) AS (
+                SELECT id FROM chain
+                UNION
+                SELECT s.id
+                FROM subscriptions s
+                JOIN subscriptions c ON c.store = s.store AND c.original_transaction_id = s.original_transaction_id
+                WHERE c.id IN (SELECT id FROM chain)
+            )
+            SELECT t.transaction_date, p.duration_days
+            FROM transactions t
+            JOIN subscriptions s ON s.id = t.subscription_id
+            LEFT JOIN products p ON p.id = s.product_id
+            WHERE t.subscription_id IN (SELECT id FROM related)
+              AND t.type IN ('initial_purchase', 'renewal') AND t.amount > 0
+              AND t.transaction_date < ?
+            ORDER BY t.transaction_date
+            "#,
+        )
+        .bind(subscription_id)
+        .bind(before)
+        .fetch_all(executor)
+        .await?;
+
+        Ok(sales)
+    }
+
+    async fn find_latest_sale(
+        subscription_id: &str,
+        before: DateTime<Utc>,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let transaction = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM transactions
+            WHERE subscription_id = ? AND type != 'refund' AND transaction_date <= ?
+            ORDER BY transaction_date DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(subscription_id)
+        .bind(before)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(transaction)
+    }
+
+    // Total USD a user has paid and had refunded across both stores, returned
+    // as positive amounts
+    pub async fn lifetime_usd_for_user(user_id: &str, pool: &SqlitePool) -> Result<(f64, f64), sqlx::Error> {
+        let totals = sqlx::query_as::<_, (f64, f64)>(
+            r#"
+            SELECT
+                TOTAL(CASE WHEN amount_usd > 0 THEN amount_usd END),
+                TOTAL(CASE WHEN amount_usd < 0 THEN -amount_usd END)
+            FROM transactions
+            WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(totals)
+    }
+
+    // Estimate proceeds for transactions recorded before proceeds were tracked
+    pub async fn backfill_proceeds(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+        let transactions = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM transactions
+            WHERE proceeds IS NULL AND amount IS NOT NULL
+            ORDER BY transaction_date
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let count = transactions.len() as u64;
+
+        for mut transaction in transactions {
+            transaction.calculate_proceeds(&mut *pool.acquire().await?).await?;
+
+            sqlx::query(
+                r#"
+                UPDATE transactions SET commission_rate = ?, tax_rate = ?, proceeds = ? WHERE id = ?
+                "#,
+            )
+            .bind(transaction.commission_rate)
+            .bind(transaction.tax_rate)
+            .bind(transaction.proceeds)
+            .bind(&transaction.id)
+            .execute(pool)
+            .await?;
+        }
+
+        Self::backfill_usd_amounts(pool).await?;
+
+        Ok(count)
+    }
+
+    // Fill in USD amounts for transactions recorded before their currency had a rate.
+    // Amounts that were already converted keep the rate from when they were recorded.
+    pub async fn backfill_usd_amounts(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE transactions
+            SET amount_usd = CASE
+                WHEN currency IS NULL OR UPPER(currency) = 'USD' THEN amount
+                ELSE amount * (SELECT usd_rate FROM exchange_rates WHERE currency = UPPER(transactions.currency))
+            END
+            WHERE amount_usd IS NULL AND amount IS NOT NULL
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE transactions
+            SET proceeds_usd = CASE
+                WHEN currency IS NULL OR UPPER(currency) = 'USD' THEN proceeds
+                ELSE proceeds * (SELECT usd_rate FROM exchange_rates WHERE currency = UPPER(transactions.currency))
+            END
+            WHERE proceeds_usd IS NULL AND proceeds IS NOT NULL
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+// A lapse longer than this starts the paid day count over
+const PAID_DAYS_MAX_GAP: i64 = 60;
+
+// Used for sales whose product has no duration set
+const DEFAULT_PERIOD_DAYS: i64 = 30;
+
+// Days of paid service up to `at` from sales ordered by date, each with the
+// number of days it paid for. Overlapping periods count once, time spent
+// lapsed doesn't count, and a lapse of more than 60 days, including one
+// running up to `at`, starts the count over.
+fn paid_days(sales: &[(DateTime<Utc>, Option<i32>)], at: DateTime<Utc>) -> i64 {
+    let mut total = Duration::zero();
+    let mut paid_until: Option<DateTime<Utc>> = None;
+
+    for &(start, duration_days) in sales {
+        let end = start + Duration::days(duration_days.map_or(DEFAULT_PERIOD_DAYS, i64::from));
+
+        let counted_from = match paid_until {
+            Some(paid_until) if (start - paid_until).num_days() > PAID_DAYS_MAX_GAP => {
+                total = Duration::zero();
+                start
+            }
+            Some(paid_until) => start.max(paid_until),
+            None => start,
+        };
+
+        if end.min(at) > counted_from {
+            total += end.min(at) - counted_from;
+        }
+        paid_until = Some(paid_until.map_or(end, |paid_until| paid_until.max(end)));
+    }
+
+    match paid_until {
+        Some(paid_until) if (at - paid_until).num_days() <= PAID_DAYS_MAX_GAP => total.num_days(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day(n: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + Duration::days(n)
+    }
+
+    // Monthly sales starting on `from`, one every 30 days
+    fn monthly(from: i64, count: i64) -> Vec<(DateTime<Utc>, Option<i32>)> {
+        (0..count).map(|i| (day(from + i * 30), Some(30))).collect()
+    }
+
+    #[test]
+    fn paid_days_counts_continuous_service() {
+        assert_eq!(paid_days(&monthly(0, 13), day(390)), 390);
+        assert_eq!(paid_days(&[], day(10)), 0);
+    }
+
+    #[test]
+    fn paid_days_stops_at_the_sale_being_priced() {
+        // The last period runs past `at`, so only the days up to it count
+        assert_eq!(paid_days(&monthly(0, 2), day(45)), 45);
+    }
+
+    #[test]
+    fn paid_days_skips_short_lapses() {
+        // Six months, a 30 day lapse, then six more
+        let mut sales = monthly(0, 6);
+        sales.extend(monthly(210, 6));
+
+        assert_eq!(paid_days(&sales, day(390)), 360);
+    }
+
+    #[test]
+    fn paid_days_restarts_after_long_lapses() {
+        // A year, a 90 day lapse, then one month
+        let mut sales = monthly(0, 12);
+        sales.extend(monthly(450, 1));
+
+        assert_eq!(paid_days(&sales, day(480)), 30);
+        // Coming back after the lapse, before any new sale
+        assert_eq!(paid_days(&monthly(0, 12), day(450)), 0);
+    }
+
+    #[test]
+    fn paid_days_counts_overlapping_periods_once() {
+        // An upgrade bought halfway through a yearly period
+        let sales = vec![(day(0), Some(365)), (day(180), Some(365))];
+
+        assert_eq!(paid_days(&sales, day(400)), 400);
+    }
+
+    #[test]
+    fn paid_days_defaults_unknown_durations() {
+        assert_eq!(paid_days(&[(day(0), None)], day(30)), 30);
+    }
+}
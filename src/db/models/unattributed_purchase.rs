@@ -0,0 +1,191 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use std::fmt;
+use uuid::Uuid;
+
+// A store purchase we couldn't match to a user. The notification is kept so
+// the purchase can be processed once the user is known.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UnattributedPurchase {
+    pub id: String,
+    pub store: String,  // 'apple' or 'google'
+    pub account_token: Option<String>,  // Token on the purchase, if any
+    pub store_product_id: String,
+    pub transaction_id: String,
+    pub original_transaction_id: Option<String>,
+    pub notification_json: String,
+    pub status: String,  // 'pending' or 'attributed'
+    pub user_id: Option<String>,  // Set when the purchase is attributed
+    pub attributed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum UnattributedPurchaseStatus {
+    Pending,
+    Attributed,
+}
+
+impl fmt::Display for UnattributedPurchaseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnattributedPurchaseStatus::Pending => write!(f, "pending"),
+            UnattributedPurchaseStatus::Attributed => write!(f, "attributed"),
+        }
+    }
+}
+
+impl UnattributedPurchase {
+    pub fn new(
+        store: String,
+        account_token: Option<String>,
+        store_product_id: String,
+        transaction_id: String,
+        original_transaction_id: Option<String>,
+        notification_json: String,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            store,
+            account_token,
+            store_product_id,
+            transaction_id,
+            original_transaction_id,
+            notification_json,
+            status: UnattributedPurchaseStatus::Pending.to_string(),
+            user_id: None,
+            attributed_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    // Park the purchase. Stores retry notifications, so a purchase that was
+    // already parked is left as it is.
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO unattributed_purchases (
+                id, store, account_token, store_product_id, transaction_id, original_transaction_id,
+                notification_json, status, user_id, attributed_at, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.store)
+        .bind(&self.account_token)
+        .bind(&self.store_product_id)
+        .bind(&self.transaction_id)
+        .bind(&self.original_transaction_id)
+        .bind(&self.notification_json)
+        .bind(&self.status)
+        .bind(&self.user_id)
+        .bind(self.attributed_at)
+        .bind(self.created_at)
+        .bind(self.updated_at)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let purchase = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM unattributed_purchases WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(purchase)
+    }
+
+    pub async fn find_by_transaction(
+        store: &str,
+        transaction_id: &str,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let purchase = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM unattributed_purchases WHERE store = ? AND transaction_id = ?
+            "#,
+        )
+        .bind(store)
+        .bind(transaction_id)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(purchase)
+    }
+
+    // Parked purchases, oldest first, optionally with one status. Starts after
+    // the purchase with ID `starting_after`.
+    pub async fn list(
+        status: Option<&str>,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let purchases = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM unattributed_purchases
+            WHERE (?1 IS NULL OR status = ?1)
+              AND (? IS NULL OR (created_at, rowid) > (SELECT created_at, rowid FROM unattributed_purchases WHERE id = ?))
+            ORDER BY created_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(status)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(purchases)
+    }
+
+    pub async fn list_pending_by_token(store: &str, token: &str, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let purchases = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM unattributed_purchases
+            WHERE store = ? AND account_token = ? AND status = 'pending'
+            ORDER BY created_at, rowid
+            "#,
+        )
+        .bind(store)
+        .bind(token)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(purchases)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.status == UnattributedPurchaseStatus::Pending.to_string()
+    }
+
+    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE unattributed_purchases
+            SET status = ?, user_id = ?, attributed_at = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&self.status)
+        .bind(&self.user_id)
+        .bind(self.attributed_at)
+        .bind(Utc::now())
+        .bind(&self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,13 +1,35 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
 use uuid::Uuid;
 
+use crate::db::models::UserAlias;
+
+// Apps identify users who haven't signed in yet as `$anon:<uuid>`
+pub const ANONYMOUS_ID_PREFIX: &str = "$anon:";
+
+// Tables whose rows belong to a user and move with them when users merge
+const USER_OWNED_TABLES: [&str; 11] = [
+    "subscriptions",
+    "user_entitlements",
+    "transactions",
+    "subscription_events",
+    "wallet_entries",
+    "consumption_requests",
+    "account_tokens",
+    "unattributed_purchases",
+    "user_aliases",
+    "user_attributes",
+    "promo_code_redemptions",
+];
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
     pub id: String,
     pub app_user_id: String,
     pub email: Option<String>,
+    pub consumption_consent: bool,  // Agreed to share consumption data with Apple
+    pub play_time_minutes: Option<i64>,  // Reported by the app
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -18,24 +40,30 @@ impl User {
             id: Uuid::new_v4().to_string(),
             app_user_id,
             email,
+            consumption_consent: false,
+            play_time_minutes: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
 
-    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
-            INSERT INTO users (id, app_user_id, email, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?)
+            INSERT INTO users (
+                id, app_user_id, email, consumption_consent, play_time_minutes, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&self.id)
         .bind(&self.app_user_id)
         .bind(&self.email)
+        .bind(self.consumption_consent)
+        .bind(self.play_time_minutes)
         .bind(&self.created_at)
         .bind(&self.updated_at)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
@@ -54,45 +82,275 @@ impl User {
         Ok(user)
     }
 
-    pub async fn find_by_app_user_id(app_user_id: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+    // Find a user by their app user ID or one of their aliases
+    pub async fn find_by_app_user_id(app_user_id: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
         let user = sqlx::query_as::<_, Self>(
             r#"
-            SELECT * FROM users WHERE app_user_id = ?
+            SELECT * FROM users
+            WHERE app_user_id = ?
+               OR id IN (SELECT user_id FROM user_aliases WHERE alias = ?)
+            LIMIT 1
             "#,
         )
         .bind(app_user_id)
-        .fetch_optional(pool)
+        .bind(app_user_id)
+        .fetch_optional(executor)
         .await?;
 
         Ok(user)
     }
 
-    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub fn is_anonymous_id(app_user_id: &str) -> bool {
+        app_user_id
+            .strip_prefix(ANONYMOUS_ID_PREFIX)
+            .is_some_and(|id| Uuid::parse_str(id).is_ok())
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        Self::is_anonymous_id(&self.app_user_id)
+    }
+
+    // Whether both users have a record of the same store purchase, which
+    // can't be owned twice
+    pub async fn shares_purchases_with(&self, other: &User, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM subscriptions mine
+            JOIN subscriptions theirs
+              ON theirs.product_id = mine.product_id
+             AND theirs.store_transaction_id = mine.store_transaction_id
+            WHERE mine.user_id = ? AND theirs.user_id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&other.id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    // Move everything this user owns to `target` and delete this user. Both
+    // users' purchases are kept. Where both have a value, the target's wins:
+    // its experiment assignments and profile fields are kept, and this user's
+    // only fill in what the target is missing. This user's app user ID
+    // becomes an alias of the target.
+    pub async fn merge_into(&self, target: &mut User, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // A user is only in each experiment once
+        sqlx::query(
+            r#"
+            UPDATE experiment_assignments SET user_id = ?
+            WHERE user_id = ?
+              AND experiment_id NOT IN (SELECT experiment_id FROM experiment_assignments WHERE user_id = ?)
+            "#,
+        )
+        .bind(&target.id)
+        .bind(&self.id)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Spend references are only unique per user, so two different spends
+        // can share one. Drop ours so both spends are kept.
+        sqlx::query(
+            r#"
+            UPDATE wallet_entries SET reference = NULL
+            WHERE user_id = ? AND reference IS NOT NULL
+              AND EXISTS (
+                  SELECT 1 FROM wallet_entries theirs
+                  WHERE theirs.user_id = ?
+                    AND theirs.currency = wallet_entries.currency
+                    AND theirs.reference = wallet_entries.reference
+              )
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Both users may have set the same attribute. Keep whichever was
+        // set last, as if both writes had come from the same user.
+        sqlx::query(
+            r#"
+            DELETE FROM user_attributes
+            WHERE user_id = ?
+              AND EXISTS (
+                  SELECT 1 FROM user_attributes theirs
+                  WHERE theirs.user_id = ?
+                    AND theirs.key = user_attributes.key
+                    AND julianday(theirs.updated_at) >= julianday(user_attributes.updated_at)
+              )
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM user_attributes
+            WHERE user_id = ?
+              AND key IN (SELECT key FROM user_attributes WHERE user_id = ?)
+            "#,
+        )
+        .bind(&target.id)
+        .bind(&self.id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Each user can redeem one code per batch. If both did, only the
+        // logged-in user's redemption is kept, and the grant ours created is
+        // ended so the batch isn't granted twice. A grant ours only extended,
+        // or that another of our redemptions also extended, is left alone.
+        sqlx::query(
+            r#"
+            UPDATE user_entitlements SET expires_at = ?1, updated_at = ?1
+            WHERE user_id = ?2
+              AND (expires_at IS NULL OR julianday(expires_at) > julianday(?1))
+              AND EXISTS (
+                  SELECT 1 FROM promo_code_redemptions ours
+                  WHERE ours.user_id = ?2
+                    AND ours.user_entitlement_id = user_entitlements.id
+                    AND julianday(user_entitlements.created_at) >= julianday(ours.created_at)
+                    AND ours.batch_id IN (SELECT batch_id FROM promo_code_redemptions WHERE user_id = ?3)
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM promo_code_redemptions ours
+                  WHERE ours.user_id = ?2
+                    AND ours.user_entitlement_id = user_entitlements.id
+                    AND ours.batch_id NOT IN (SELECT batch_id FROM promo_code_redemptions WHERE user_id = ?3)
+              )
+            "#,
+        )
+        .bind(Utc::now())
+        .bind(&self.id)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Give the code back the redemption that's being dropped
+        sqlx::query(
+            r#"
+            UPDATE promo_codes
+            SET redemption_count = redemption_count - (
+                SELECT COUNT(*) FROM promo_code_redemptions ours
+                WHERE ours.code_id = promo_codes.id
+                  AND ours.user_id = ?1
+                  AND ours.batch_id IN (SELECT batch_id FROM promo_code_redemptions WHERE user_id = ?2)
+            )
+            WHERE id IN (
+                SELECT code_id FROM promo_code_redemptions
+                WHERE user_id = ?1
+                  AND batch_id IN (SELECT batch_id FROM promo_code_redemptions WHERE user_id = ?2)
+            )
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM promo_code_redemptions
+            WHERE user_id = ?
+              AND batch_id IN (SELECT batch_id FROM promo_code_redemptions WHERE user_id = ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        for table in USER_OWNED_TABLES {
+            sqlx::query(&format!("UPDATE {} SET user_id = ? WHERE user_id = ?", table))
+                .bind(&target.id)
+                .bind(&self.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        if target.email.is_none() {
+            target.email = self.email.clone();
+        }
+        if target.play_time_minutes.is_none() {
+            target.play_time_minutes = self.play_time_minutes;
+        }
+        target.updated_at = Utc::now();
+
+        sqlx::query(
+            r#"
+            UPDATE users SET email = ?, play_time_minutes = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&target.email)
+        .bind(target.play_time_minutes)
+        .bind(target.updated_at)
+        .bind(&target.id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM users WHERE id = ?
+            "#,
+        )
+        .bind(&self.id)
+        .execute(&mut *tx)
+        .await?;
+
+        let alias = UserAlias::new(target.id.clone(), self.app_user_id.clone());
+        sqlx::query(
+            r#"
+            INSERT INTO user_aliases (id, user_id, alias, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&alias.id)
+        .bind(&alias.user_id)
+        .bind(&alias.alias)
+        .bind(alias.created_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn update(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             UPDATE users
-            SET app_user_id = ?, email = ?, updated_at = ?
+            SET app_user_id = ?, email = ?, consumption_consent = ?, play_time_minutes = ?, updated_at = ?
             WHERE id = ?
             "#,
         )
         .bind(&self.app_user_id)
         .bind(&self.email)
+        .bind(self.consumption_consent)
+        .bind(self.play_time_minutes)
         .bind(Utc::now())
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
-    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    pub async fn delete(&self, executor: impl SqliteExecutor<'_>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             DELETE FROM users WHERE id = ?
             "#,
         )
         .bind(&self.id)
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
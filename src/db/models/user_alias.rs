@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use uuid::Uuid;
+
+// Another app user ID a user is known by, e.g. the anonymous ID they had
+// before logging in
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserAlias {
+    pub id: String,
+    pub user_id: String,
+    pub alias: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserAlias {
+    pub fn new(user_id: String, alias: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            alias,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_aliases (id, user_id, alias, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.user_id)
+        .bind(&self.alias)
+        .bind(self.created_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_alias(alias: &str, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let alias = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM user_aliases WHERE alias = ?
+            "#,
+        )
+        .bind(alias)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(alias)
+    }
+
+    // The user's aliases in the order they were added, starting after the alias
+    // with ID `starting_after`
+    pub async fn list_by_user(
+        user_id: &str,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let aliases = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM user_aliases
+            WHERE user_id = ?
+              AND (? IS NULL OR (created_at, rowid) > (SELECT created_at, rowid FROM user_aliases WHERE id = ?))
+            ORDER BY created_at ASC, rowid ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(aliases)
+    }
+}
@@ -0,0 +1,102 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteExecutor, SqlitePool};
+use uuid::Uuid;
+
+// A key/value attribute the app has set on a user
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserAttribute {
+    pub id: String,
+    pub user_id: String,
+    pub key: String,
+    pub value: Option<String>,  // None once the attribute has been unset
+    pub updated_at: DateTime<Utc>,  // When the client set the value
+    pub created_at: DateTime<Utc>,
+}
+
+// Keys starting with `$` are reserved for these attributes
+pub const RESERVED_ATTRIBUTES: [&str; 12] = [
+    "$email",
+    "$displayName",
+    "$phoneNumber",
+    "$locale",
+    "$apnsTokens",
+    "$fcmTokens",
+    "$mediaSource",
+    "$campaign",
+    "$adGroup",
+    "$ad",
+    "$keyword",
+    "$creative",
+];
+
+pub const EMAIL_ATTRIBUTE: &str = "$email";
+
+const MAX_KEY_LENGTH: usize = 100;
+const MAX_VALUE_LENGTH: usize = 500;
+
+impl UserAttribute {
+    pub fn new(user_id: String, key: String, value: Option<String>, updated_at: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            key,
+            value,
+            updated_at,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn is_valid_key(key: &str) -> bool {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return false;
+        }
+
+        !key.starts_with('$') || RESERVED_ATTRIBUTES.contains(&key)
+    }
+
+    pub fn is_valid_value(value: &str) -> bool {
+        value.len() <= MAX_VALUE_LENGTH
+    }
+
+    // Save the value unless a newer one has already been saved, so writes
+    // arriving out of order from several devices settle on the latest.
+    // Returns whether the value was saved.
+    pub async fn set(&self, executor: impl SqliteExecutor<'_>) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO user_attributes (id, user_id, key, value, updated_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id, key) DO UPDATE
+            SET value = excluded.value, updated_at = excluded.updated_at
+            WHERE julianday(excluded.updated_at) >= julianday(user_attributes.updated_at)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.user_id)
+        .bind(&self.key)
+        .bind(&self.value)
+        .bind(self.updated_at)
+        .bind(self.created_at)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Attributes that are currently set, by key
+    pub async fn list_by_user(user_id: &str, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let attributes = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM user_attributes
+            WHERE user_id = ? AND value IS NOT NULL
+            ORDER BY key
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(attributes)
+    }
+}
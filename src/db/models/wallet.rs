@@ -0,0 +1,302 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnection, SqliteExecutor, SqlitePool};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::db::models::{Product, Subscription};
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WalletEntry {
+    pub id: String,
+    pub user_id: String,
+    pub currency: String,
+    pub amount: i64,  // Positive for credits, negative for debits
+    #[sqlx(rename = "type")]
+    pub type_: String,  // 'purchase', 'spend', 'refund', 'refund_reversal', 'adjustment'
+    pub subscription_id: Option<String>,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WalletBalance {
+    pub currency: String,
+    pub balance: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum WalletEntryType {
+    Purchase,
+    Spend,
+    Refund,
+    RefundReversal,
+    Adjustment,
+}
+
+impl fmt::Display for WalletEntryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletEntryType::Purchase => write!(f, "purchase"),
+            WalletEntryType::Spend => write!(f, "spend"),
+            WalletEntryType::Refund => write!(f, "refund"),
+            WalletEntryType::RefundReversal => write!(f, "refund_reversal"),
+            WalletEntryType::Adjustment => write!(f, "adjustment"),
+        }
+    }
+}
+
+impl WalletEntry {
+    pub fn new(
+        user_id: String,
+        currency: String,
+        amount: i64,
+        type_: WalletEntryType,
+        description: Option<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            currency,
+            amount,
+            type_: type_.to_string(),
+            subscription_id: None,
+            reference: None,
+            description,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Record the entry. Returns false if it was ignored because the purchase
+    // it belongs to already has an entry of this type.
+    pub async fn create(&self, executor: impl SqliteExecutor<'_>) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO wallet_entries (
+                id, user_id, currency, amount, type, subscription_id, reference, description, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.user_id)
+        .bind(&self.currency)
+        .bind(self.amount)
+        .bind(&self.type_)
+        .bind(&self.subscription_id)
+        .bind(&self.reference)
+        .bind(&self.description)
+        .bind(self.created_at)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Record a debit only if the balance covers it. The check and the insert
+    // are one statement, so concurrent spends can't overdraw the wallet.
+    // Returns false when the balance is too low.
+    pub async fn create_debit(&self, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO wallet_entries (
+                id, user_id, currency, amount, type, subscription_id, reference, description, created_at
+            )
+            SELECT ?, ?, ?, ?, ?, ?, ?, ?, ?
+            WHERE (
+                SELECT COALESCE(SUM(amount), 0) FROM wallet_entries WHERE user_id = ? AND currency = ?
+            ) >= ?
+            "#,
+        )
+        .bind(&self.id)
+        .bind(&self.user_id)
+        .bind(&self.currency)
+        .bind(self.amount)
+        .bind(&self.type_)
+        .bind(&self.subscription_id)
+        .bind(&self.reference)
+        .bind(&self.description)
+        .bind(self.created_at)
+        .bind(&self.user_id)
+        .bind(&self.currency)
+        .bind(-self.amount)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find_by_reference(
+        user_id: &str,
+        currency: &str,
+        reference: &str,
+        pool: &SqlitePool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let entry = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM wallet_entries WHERE user_id = ? AND currency = ? AND reference = ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(currency)
+        .bind(reference)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(entry)
+    }
+
+    // The user's ledger entries, newest first, optionally for one currency.
+    // Starts after the entry with ID `starting_after`.
+    pub async fn list_by_user(
+        user_id: &str,
+        currency: Option<&str>,
+        starting_after: Option<&str>,
+        limit: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let entries = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM wallet_entries
+            WHERE user_id = ?1 AND (?2 IS NULL OR currency = ?2)
+              AND (? IS NULL OR (created_at, rowid) < (SELECT created_at, rowid FROM wallet_entries WHERE id = ?))
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(currency)
+        .bind(starting_after)
+        .bind(starting_after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    pub async fn balances_for_user(user_id: &str, pool: &SqlitePool) -> Result<Vec<WalletBalance>, sqlx::Error> {
+        let balances = sqlx::query_as::<_, WalletBalance>(
+            r#"
+            SELECT currency, SUM(amount) AS balance
+            FROM wallet_entries
+            WHERE user_id = ?
+            GROUP BY currency
+            ORDER BY currency
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(balances)
+    }
+
+    pub async fn balance(user_id: &str, currency: &str, pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+        let balance = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COALESCE(SUM(amount), 0) FROM wallet_entries WHERE user_id = ? AND currency = ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(currency)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(balance)
+    }
+
+    // The credit a consumable purchase made, if it granted any currency
+    pub async fn find_purchase(subscription_id: &str, executor: impl SqliteExecutor<'_>) -> Result<Option<Self>, sqlx::Error> {
+        let entry = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM wallet_entries WHERE subscription_id = ? AND type = ?
+            "#,
+        )
+        .bind(subscription_id)
+        .bind(WalletEntryType::Purchase.to_string())
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(entry)
+    }
+
+    // Credit the virtual currency a consumable purchase grants.
+    // Does nothing for products that don't grant currency.
+    pub async fn credit_purchase(
+        subscription: &Subscription,
+        product: &Product,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::Error> {
+        let (currency, amount) = match (&product.virtual_currency, product.virtual_currency_amount) {
+            (Some(currency), Some(amount)) if amount > 0 => (currency.clone(), amount),
+            _ => return Ok(()),
+        };
+
+        let mut entry = Self::new(
+            subscription.user_id.clone(),
+            currency,
+            amount,
+            WalletEntryType::Purchase,
+            Some(format!("Purchased {}", product.name)),
+        );
+        entry.subscription_id = Some(subscription.id.clone());
+        entry.create(executor).await?;
+
+        Ok(())
+    }
+
+    // Take back the currency a refunded purchase credited. This can leave
+    // the balance negative if the currency was already spent.
+    pub async fn reverse_purchase(
+        subscription: &Subscription,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let purchase = Self::find_purchase(&subscription.id, &mut *conn).await?;
+
+        if let Some(purchase) = purchase {
+            let mut entry = Self::new(
+                purchase.user_id,
+                purchase.currency,
+                -purchase.amount,
+                WalletEntryType::Refund,
+                Some("Purchase refunded".to_string()),
+            );
+            entry.subscription_id = Some(subscription.id.clone());
+            entry.create(&mut *conn).await?;
+        }
+
+        Ok(())
+    }
+
+    // Credit back the currency taken by a refund the store later reversed
+    pub async fn restore_purchase(
+        subscription: &Subscription,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let refund = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM wallet_entries WHERE subscription_id = ? AND type = ?
+            "#,
+        )
+        .bind(&subscription.id)
+        .bind(WalletEntryType::Refund.to_string())
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(refund) = refund {
+            let mut entry = Self::new(
+                refund.user_id,
+                refund.currency,
+                -refund.amount,
+                WalletEntryType::RefundReversal,
+                Some("Refund reversed".to_string()),
+            );
+            entry.subscription_id = Some(subscription.id.clone());
+            entry.create(&mut *conn).await?;
+        }
+
+        Ok(())
+    }
+}
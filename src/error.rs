@@ -23,6 +23,9 @@ pub enum AppError {
     #[error("Validation error: {0}")]
     ValidationError(String),
 
+    #[error("Insufficient funds: {0}")]
+    InsufficientFunds(String),
+
     #[error("Store API error: {0}")]
     StoreApiError(String),
 
@@ -41,6 +44,7 @@ impl IntoResponse for AppError {
             AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message.clone()),
             AppError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message.clone()),
             AppError::ValidationError(message) => (StatusCode::BAD_REQUEST, message.clone()),
+            AppError::InsufficientFunds(message) => (StatusCode::CONFLICT, message.clone()),
             AppError::StoreApiError(message) => (StatusCode::BAD_GATEWAY, message.clone()),
             AppError::InternalServerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message.clone()),
             AppError::Other(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
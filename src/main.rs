@@ -1,9 +1,11 @@
+mod analytics;
 mod api;
 mod config;
 mod db;
 mod error;
 mod webhooks;
 mod providers;
+mod reports;
 mod utils;
 
 use axum::{
@@ -43,6 +45,38 @@ async fn main() -> anyhow::Result<()> {
         return Err(anyhow::anyhow!("Failed to connect to the database"));
     }
     
+    // Load exchange rates for normalizing revenue to USD
+    if let Some(path) = &config.exchange_rates_file {
+        let count = db::load_exchange_rates(&pool, path).await?;
+        tracing::info!("Loaded {} exchange rates from {}", count, path);
+    }
+    
+    // Estimate proceeds for ledger entries recorded before they were tracked.
+    // Runs after the rates are loaded so their USD amounts can be filled in.
+    let backfilled = db::models::Transaction::backfill_proceeds(&pool).await?;
+    if backfilled > 0 {
+        tracing::info!("Estimated proceeds for {} transactions", backfilled);
+    }
+    
+    // `nuxie-payments import-report <file>` imports a store report,
+    // prints how it matches the ledger and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("import-report") {
+        let path = args
+            .get(2)
+            .ok_or_else(|| anyhow::anyhow!("Usage: nuxie-payments import-report <file>"))?;
+        let contents = std::fs::read_to_string(path)?;
+        let filename = std::path::Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+
+        let (report, reconciliation) = reports::import_report(&contents, filename, &pool).await?;
+        tracing::info!("Imported {} report {} with {} rows", report.report_type, report.id, report.row_count);
+        println!("{}", serde_json::to_string_pretty(&reconciliation)?);
+
+        return Ok(());
+    }
+    
     // Create the API routes
     let api_routes = api::routes(pool.clone());
     
@@ -1 +1,196 @@
-// Placeholder for Apple App Store API integration
+// App Store Server API integration
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::Serialize;
+
+use crate::db::models::{App, ConsumptionRequest};
+
+const PRODUCTION_URL: &str = "https://api.storekit.itunes.apple.com";
+const SANDBOX_URL: &str = "https://api.storekit-sandbox.itunes.apple.com";
+
+// Tokens may be valid for up to an hour, but each request gets a fresh one
+const TOKEN_LIFETIME_SECONDS: i64 = 5 * 60;
+
+#[derive(Debug, Serialize)]
+struct ApiClaims<'a> {
+    iss: &'a str,
+    iat: i64,
+    exp: i64,
+    aud: &'a str,
+    bid: &'a str,
+}
+
+// Body of the Send Consumption Information request. Most fields are
+// buckets defined by Apple, where 0 means undeclared.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumptionInformation {
+    pub account_tenure: i32,
+    pub app_account_token: String,
+    pub consumption_status: i32,
+    pub customer_consented: bool,
+    pub delivery_status: i32,
+    pub lifetime_dollars_purchased: i32,
+    pub lifetime_dollars_refunded: i32,
+    pub platform: i32,
+    pub play_time: i32,
+    pub sample_content_provided: bool,
+    pub user_status: i32,
+}
+
+// Consumption status values
+pub const CONSUMPTION_UNDECLARED: i32 = 0;
+pub const NOT_CONSUMED: i32 = 1;
+pub const PARTIALLY_CONSUMED: i32 = 2;
+pub const FULLY_CONSUMED: i32 = 3;
+
+// Delivery status, platform and user status values
+pub const DELIVERED: i32 = 0;
+pub const PLATFORM_APPLE: i32 = 1;
+pub const USER_ACTIVE: i32 = 1;
+
+// Bucket for how long ago the account was created
+pub fn account_tenure(created_at: DateTime<Utc>, now: DateTime<Utc>) -> i32 {
+    let days = (now - created_at).num_hours() as f64 / 24.0;
+
+    match days {
+        d if d < 3.0 => 1,
+        d if d < 10.0 => 2,
+        d if d < 30.0 => 3,
+        d if d < 90.0 => 4,
+        d if d < 180.0 => 5,
+        d if d < 365.0 => 6,
+        _ => 7,
+    }
+}
+
+// Bucket for how long the customer has used the app
+pub fn play_time(minutes: Option<i64>) -> i32 {
+    match minutes {
+        None => 0,
+        Some(m) if m < 5 => 1,
+        Some(m) if m < 60 => 2,
+        Some(m) if m < 6 * 60 => 3,
+        Some(m) if m < 24 * 60 => 4,
+        Some(m) if m < 4 * 24 * 60 => 5,
+        Some(m) if m < 16 * 24 * 60 => 6,
+        Some(_) => 7,
+    }
+}
+
+// Bucket for a lifetime amount purchased or refunded, in USD
+pub fn lifetime_dollars(amount_usd: f64) -> i32 {
+    match amount_usd {
+        a if a <= 0.0 => 1,
+        a if a < 50.0 => 2,
+        a if a < 100.0 => 3,
+        a if a < 500.0 => 4,
+        a if a < 1000.0 => 5,
+        a if a < 2000.0 => 6,
+        _ => 7,
+    }
+}
+
+pub struct AppStoreServerApi {
+    base_url: &'static str,
+    issuer_id: String,
+    key_id: String,
+    private_key: String,
+    bundle_id: String,
+}
+
+impl AppStoreServerApi {
+    // Build a client from the app's In-App Purchase key. `environment` is the
+    // value Apple puts in notifications ('Production' or 'Sandbox').
+    pub fn for_app(app: &App, environment: &str) -> Result<Self, String> {
+        match (
+            &app.apple_issuer_id,
+            &app.apple_offer_key_id,
+            &app.apple_offer_private_key,
+            &app.apple_bundle_id,
+        ) {
+            (Some(issuer_id), Some(key_id), Some(private_key), Some(bundle_id)) => Ok(Self {
+                base_url: if environment == "Production" { PRODUCTION_URL } else { SANDBOX_URL },
+                issuer_id: issuer_id.clone(),
+                key_id: key_id.clone(),
+                private_key: private_key.clone(),
+                bundle_id: bundle_id.clone(),
+            }),
+            _ => Err(format!(
+                "App {} needs an Apple bundle ID, issuer ID and In-App Purchase key to call the App Store Server API",
+                app.id
+            )),
+        }
+    }
+
+    fn token(&self) -> Result<String, String> {
+        let now = Utc::now().timestamp();
+        let claims = ApiClaims {
+            iss: &self.issuer_id,
+            iat: now,
+            exp: now + TOKEN_LIFETIME_SECONDS,
+            aud: "appstoreconnect-v1",
+            bid: &self.bundle_id,
+        };
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.key_id.clone());
+
+        let key = EncodingKey::from_ec_pem(self.private_key.as_bytes())
+            .map_err(|e| format!("Invalid In-App Purchase key: {}", e))?;
+
+        jsonwebtoken::encode(&header, &claims, &key).map_err(|e| format!("Failed to sign API token: {}", e))
+    }
+
+    // Answer a CONSUMPTION_REQUEST notification for a transaction
+    pub async fn send_consumption_information(&self, transaction_id: &str, body: &str) -> Result<(), String> {
+        let url = format!(
+            "{}/inApps/v1/transactions/consumption/{}",
+            self.base_url, transaction_id
+        );
+
+        let response = reqwest::Client::new()
+            .put(url)
+            .bearer_auth(self.token()?)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| format!("App Store Server API request failed: {}", e))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            Err(format!("App Store Server API returned {}: {}", status, text))
+        }
+    }
+}
+
+// Send the body recorded on a consumption request and mark it sent or
+// failed. Apple ignores answers after the deadline, so those are skipped.
+pub async fn send_consumption_request(request: &mut ConsumptionRequest, app: &App) {
+    let now = Utc::now();
+
+    if now > request.deadline {
+        request.skip("The 12 hour window to respond has passed".to_string());
+        return;
+    }
+
+    let Some(body) = request.request_json.clone() else {
+        request.fail("No consumption information to send".to_string());
+        return;
+    };
+
+    let result = match AppStoreServerApi::for_app(app, &request.environment) {
+        Ok(api) => api.send_consumption_information(&request.transaction_id, &body).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => request.mark_sent(now),
+        Err(e) => request.fail(e),
+    }
+}
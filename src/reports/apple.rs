@@ -0,0 +1,205 @@
+// App Store Connect reports. Neither report has transaction IDs, so their
+// rows are matched against the ledger by date and product.
+
+use crate::db::models::{ReportEventKind, StoreReportRow, StoreReportType};
+
+use super::{parse_amount, parse_date, ParsedReport, Table};
+
+// Subscription events that correspond to a purchase in the ledger
+const SALE_EVENTS: &[&str] = &[
+    "subscribe",
+    "start introductory offer",
+    "start promotional offer",
+    "paid subscription from",
+    "renew",
+    "reactivate",
+    "crossgrade",
+    "upgrade",
+    "downgrade",
+];
+
+// Sales & Trends sales report (tab separated). Rows are aggregated per day,
+// product and price, with negative units for refunds. Only in-app purchase
+// rows are kept; app downloads and updates are skipped.
+pub fn parse_sales(table: &Table) -> Result<ParsedReport, String> {
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+
+    for (index, values) in table.rows.iter().enumerate() {
+        let product_type = table.get(values, &["Product Type Identifier"]).unwrap_or_default();
+        let units = table
+            .get(values, &["Units"])
+            .and_then(parse_amount)
+            .unwrap_or(0.0) as i64;
+
+        let is_in_app = product_type.starts_with("IA") || product_type.starts_with("FI");
+        if !is_in_app || units == 0 {
+            skipped += 1;
+            continue;
+        }
+
+        let event_date = table
+            .get(values, &["Begin Date"])
+            .and_then(parse_date)
+            .ok_or_else(|| format!("Row {}: invalid Begin Date", index + 2))?;
+
+        let kind = if units < 0 {
+            ReportEventKind::Refund
+        } else {
+            ReportEventKind::Sale
+        };
+
+        // Prices are per unit, and already negative for refunds
+        let mut row = StoreReportRow::new(
+            event_date,
+            kind,
+            None,
+            table.get(values, &["SKU"]).map(str::to_string),
+            units.abs(),
+            table.get(values, &["Customer Price"]).and_then(parse_amount),
+            table.get(values, &["Customer Currency"]).map(str::to_string),
+            table.get(values, &["Country Code"]).map(str::to_string),
+        );
+        row.proceeds = table.get(values, &["Developer Proceeds"]).and_then(parse_amount);
+        row.proceeds_currency = table.get(values, &["Currency of Proceeds"]).map(str::to_string);
+        row.raw_data = table.raw_data(values);
+
+        rows.push(row);
+    }
+
+    Ok(ParsedReport {
+        report_type: StoreReportType::AppleSales,
+        rows,
+        skipped,
+    })
+}
+
+// Subscription Event report (tab separated). Has no prices or product IDs,
+// so rows only match on date. Events that aren't purchases or refunds, like
+// cancellations and billing retries, are skipped.
+pub fn parse_subscription_events(table: &Table) -> Result<ParsedReport, String> {
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+
+    for (index, values) in table.rows.iter().enumerate() {
+        let event = table.get(values, &["Event"]).unwrap_or_default().to_lowercase();
+
+        let kind = if event.contains("refund") {
+            ReportEventKind::Refund
+        } else if SALE_EVENTS.iter().any(|sale_event| event.starts_with(sale_event)) {
+            ReportEventKind::Sale
+        } else {
+            skipped += 1;
+            continue;
+        };
+
+        let event_date = table
+            .get(values, &["Event Date"])
+            .and_then(parse_date)
+            .ok_or_else(|| format!("Row {}: invalid Event Date", index + 2))?;
+
+        let quantity = table
+            .get(values, &["Quantity"])
+            .and_then(parse_amount)
+            .map(|quantity| quantity as i64)
+            .unwrap_or(1);
+
+        let mut row = StoreReportRow::new(
+            event_date,
+            kind,
+            None,
+            None,
+            quantity,
+            None,
+            None,
+            table.get(values, &["Country"]).map(str::to_string),
+        );
+        row.raw_data = table.raw_data(values);
+
+        rows.push(row);
+    }
+
+    Ok(ParsedReport {
+        report_type: StoreReportType::AppleSubscriptionEvents,
+        rows,
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::reports::Table;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_sales_report() {
+        let contents = "\
+Provider\tProvider Country\tSKU\tDeveloper\tTitle\tVersion\tProduct Type Identifier\tUnits\tDeveloper Proceeds\tBegin Date\tEnd Date\tCustomer Currency\tCountry Code\tCurrency of Proceeds\tApple Identifier\tCustomer Price\tPromo Code\tParent Identifier\tSubscription\tPeriod
+APPLE\tUS\tcom.example.app\tExample\tExample App\t1.0\t1F\t12\t0\t01/15/2024\t01/15/2024\tUSD\tUS\tUSD\t100\t0\t\t\t\t
+APPLE\tUS\tpremium_monthly\tExample\tPremium\t\tIAY\t3\t6.99\t01/15/2024\t01/15/2024\tUSD\tUS\tUSD\t101\t9.99\t\tcom.example.app\tRenewal\t1 Month
+APPLE\tUS\tpremium_monthly\tExample\tPremium\t\tIAY\t-1\t-6.99\t01/16/2024\t01/16/2024\tEUR\tDE\tEUR\t101\t-9.99\t\tcom.example.app\tRenewal\t1 Month
+";
+        let table = Table::parse(contents).unwrap();
+        let parsed = parse_sales(&table).unwrap();
+
+        assert_eq!(parsed.report_type, StoreReportType::AppleSales);
+        // The app download isn't an in-app purchase
+        assert_eq!(parsed.skipped, 1);
+        assert_eq!(parsed.rows.len(), 2);
+
+        let sale = &parsed.rows[0];
+        assert_eq!(sale.kind, "sale");
+        assert_eq!(sale.event_date, date(2024, 1, 15));
+        assert_eq!(sale.store_product_id.as_deref(), Some("premium_monthly"));
+        assert_eq!(sale.quantity, 3);
+        assert_eq!(sale.amount, Some(9.99));
+        assert_eq!(sale.proceeds, Some(6.99));
+        assert_eq!(sale.order_id, None);
+
+        let refund = &parsed.rows[1];
+        assert_eq!(refund.kind, "refund");
+        assert_eq!(refund.quantity, 1);
+        assert_eq!(refund.amount, Some(-9.99));
+        assert_eq!(refund.currency.as_deref(), Some("EUR"));
+        assert_eq!(refund.country_code.as_deref(), Some("DE"));
+    }
+
+    #[test]
+    fn parses_subscription_event_report() {
+        let contents = "\
+Event Date\tEvent\tApp Name\tApp Apple ID\tSubscription Name\tSubscription Apple ID\tSubscription Group ID\tStandard Subscription Duration\tCountry\tQuantity
+2024-01-15\tSubscribe\tExample\t100\tPremium\t101\t200\t1 Month\tUS\t2
+2024-01-15\tRenew\tExample\t100\tPremium\t101\t200\t1 Month\tUS\t1
+2024-01-15\tCancel\tExample\t100\tPremium\t101\t200\t1 Month\tUS\t1
+2024-01-16\tBilling Retry from Paid Subscription\tExample\t100\tPremium\t101\t200\t1 Month\tGB\t1
+2024-01-16\tRefund\tExample\t100\tPremium\t101\t200\t1 Month\tGB\t
+";
+        let table = Table::parse(contents).unwrap();
+        let parsed = parse_subscription_events(&table).unwrap();
+
+        assert_eq!(parsed.report_type, StoreReportType::AppleSubscriptionEvents);
+        assert_eq!(parsed.skipped, 2);
+
+        let kinds: Vec<(&str, i64)> = parsed.rows.iter().map(|row| (row.kind.as_str(), row.quantity)).collect();
+        assert_eq!(kinds, vec![("sale", 2), ("sale", 1), ("refund", 1)]);
+        assert_eq!(parsed.rows[2].event_date, date(2024, 1, 16));
+        assert!(parsed.rows.iter().all(|row| row.store_product_id.is_none() && row.amount.is_none()));
+    }
+
+    #[test]
+    fn rejects_rows_with_invalid_dates() {
+        let contents = "\
+Event Date\tEvent\tQuantity
+not a date\tSubscribe\t1
+";
+        let table = Table::parse(contents).unwrap();
+
+        assert!(parse_subscription_events(&table).is_err());
+    }
+}
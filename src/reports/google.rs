@@ -0,0 +1,214 @@
+// Play Console reports. Both include the order ID, so rows are matched
+// against the ledger by order.
+
+use std::collections::HashMap;
+
+use crate::db::models::{ReportEventKind, StoreReportRow, StoreReportType};
+
+use super::{parse_amount, parse_date, ParsedReport, Table};
+
+// Earnings report (CSV). Each order has a line per charge, Google fee and
+// tax, plus matching refund lines. The lines are combined into one sale and
+// one refund per order, with the proceeds being what's left after fees and tax.
+pub fn parse_earnings(table: &Table) -> Result<ParsedReport, String> {
+    let mut rows: Vec<StoreReportRow> = Vec::new();
+    let mut row_indexes: HashMap<(String, bool), usize> = HashMap::new();
+    let mut skipped = 0;
+
+    for (index, values) in table.rows.iter().enumerate() {
+        let order_id = match table.get(values, &["Description"]) {
+            Some(order_id) => order_id.to_string(),
+            None => {
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let transaction_type = table
+            .get(values, &["Transaction Type"])
+            .unwrap_or_default()
+            .to_lowercase();
+        let is_refund = transaction_type.contains("refund");
+        let is_charge = transaction_type.starts_with("charge");
+
+        let event_date = table
+            .get(values, &["Transaction Date"])
+            .and_then(parse_date)
+            .ok_or_else(|| format!("Row {}: invalid Transaction Date", index + 2))?;
+
+        let merchant_amount = table
+            .get(values, &["Amount (Merchant Currency)"])
+            .and_then(parse_amount)
+            .unwrap_or(0.0);
+
+        let key = (order_id.clone(), is_refund);
+        let row_index = match row_indexes.get(&key) {
+            Some(row_index) => *row_index,
+            None => {
+                let kind = if is_refund {
+                    ReportEventKind::Refund
+                } else {
+                    ReportEventKind::Sale
+                };
+
+                let mut row = StoreReportRow::new(
+                    event_date,
+                    kind,
+                    Some(order_id),
+                    table.get(values, &["Sku Id", "SKU ID"]).map(str::to_string),
+                    1,
+                    None,
+                    table.get(values, &["Buyer Currency"]).map(str::to_string),
+                    table.get(values, &["Buyer Country"]).map(str::to_string),
+                );
+                row.proceeds = Some(0.0);
+                row.proceeds_currency = table.get(values, &["Merchant Currency"]).map(str::to_string);
+
+                rows.push(row);
+                row_indexes.insert(key, rows.len() - 1);
+                rows.len() - 1
+            }
+        };
+
+        let row = &mut rows[row_index];
+        row.proceeds = Some(row.proceeds.unwrap_or(0.0) + merchant_amount);
+
+        if is_charge {
+            row.amount = table.get(values, &["Amount (Buyer Currency)"]).and_then(parse_amount);
+            row.raw_data = table.raw_data(values);
+        }
+    }
+
+    Ok(ParsedReport {
+        report_type: StoreReportType::GoogleEarnings,
+        rows,
+        skipped,
+    })
+}
+
+// Sales report (CSV), one line per order. Orders that weren't charged or
+// refunded, like cancelled payments, are skipped.
+pub fn parse_sales(table: &Table) -> Result<ParsedReport, String> {
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+
+    for (index, values) in table.rows.iter().enumerate() {
+        let status = table
+            .get(values, &["Financial Status"])
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let kind = if status.contains("refund") {
+            ReportEventKind::Refund
+        } else if status == "charged" {
+            ReportEventKind::Sale
+        } else {
+            skipped += 1;
+            continue;
+        };
+
+        let event_date = table
+            .get(values, &["Order Charged Date"])
+            .and_then(parse_date)
+            .ok_or_else(|| format!("Row {}: invalid Order Charged Date", index + 2))?;
+
+        // Refunds are listed with the original charge, so flip the sign
+        let amount = table.get(values, &["Charged Amount"]).and_then(parse_amount);
+        let amount = match kind {
+            ReportEventKind::Refund => amount.map(|amount| -amount.abs()),
+            ReportEventKind::Sale => amount,
+        };
+
+        let mut row = StoreReportRow::new(
+            event_date,
+            kind,
+            table.get(values, &["Order Number"]).map(str::to_string),
+            table.get(values, &["SKU ID", "Sku Id"]).map(str::to_string),
+            1,
+            amount,
+            table.get(values, &["Currency of Sale"]).map(str::to_string),
+            table.get(values, &["Country of Buyer"]).map(str::to_string),
+        );
+        row.raw_data = table.raw_data(values);
+
+        rows.push(row);
+    }
+
+    Ok(ParsedReport {
+        report_type: StoreReportType::GoogleSales,
+        rows,
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::reports::Table;
+
+    #[test]
+    fn combines_earnings_lines_per_order() {
+        let contents = "\
+Description,Transaction Date,Transaction Time,Tax Type,Transaction Type,Refund Type,Product Title,Product id,Product Type,Sku Id,Hardware,Buyer Country,Buyer State,Buyer Postal Code,Buyer Currency,Amount (Buyer Currency),Currency Conversion Rate,Merchant Currency,Amount (Merchant Currency)
+GPA.1234-5678-9012-34567,\"Jan 15, 2024\",10:00:00 AM PST,,Charge,,Premium (Example),com.example.app,subscription,premium_monthly,phone,US,CA,94103,USD,9.99,1,USD,9.99
+GPA.1234-5678-9012-34567,\"Jan 15, 2024\",10:00:00 AM PST,,Google fee,,Premium (Example),com.example.app,subscription,premium_monthly,phone,US,CA,94103,USD,-1.50,1,USD,-1.50
+GPA.1234-5678-9012-34567..1,\"Feb 15, 2024\",10:00:00 AM PST,,Charge,,Premium (Example),com.example.app,subscription,premium_monthly,phone,US,CA,94103,USD,9.99,1,USD,9.99
+GPA.1234-5678-9012-34567..1,\"Feb 15, 2024\",10:00:00 AM PST,,Google fee,,Premium (Example),com.example.app,subscription,premium_monthly,phone,US,CA,94103,USD,-1.50,1,USD,-1.50
+GPA.1234-5678-9012-34567..1,\"Feb 20, 2024\",10:00:00 AM PST,,Charge refund,Full,Premium (Example),com.example.app,subscription,premium_monthly,phone,US,CA,94103,USD,-9.99,1,USD,-9.99
+GPA.1234-5678-9012-34567..1,\"Feb 20, 2024\",10:00:00 AM PST,,Google fee refund,Full,Premium (Example),com.example.app,subscription,premium_monthly,phone,US,CA,94103,USD,1.50,1,USD,1.50
+";
+        let table = Table::parse(contents).unwrap();
+        let parsed = parse_earnings(&table).unwrap();
+
+        assert_eq!(parsed.report_type, StoreReportType::GoogleEarnings);
+        assert_eq!(parsed.skipped, 0);
+        assert_eq!(parsed.rows.len(), 3);
+
+        let first = &parsed.rows[0];
+        assert_eq!(first.kind, "sale");
+        assert_eq!(first.order_id.as_deref(), Some("GPA.1234-5678-9012-34567"));
+        assert_eq!(first.event_date, NaiveDate::from_ymd_opt(2024, 1, 15).unwrap());
+        assert_eq!(first.amount, Some(9.99));
+        assert!((first.proceeds.unwrap() - 8.49).abs() < 1e-9);
+
+        let renewal = &parsed.rows[1];
+        assert_eq!(renewal.kind, "sale");
+        assert_eq!(renewal.order_id.as_deref(), Some("GPA.1234-5678-9012-34567..1"));
+
+        let refund = &parsed.rows[2];
+        assert_eq!(refund.kind, "refund");
+        assert_eq!(refund.order_id.as_deref(), Some("GPA.1234-5678-9012-34567..1"));
+        assert_eq!(refund.amount, Some(-9.99));
+        assert!((refund.proceeds.unwrap() + 8.49).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_sales_report() {
+        let contents = "\
+Order Number,Order Charged Date,Order Charged Timestamp,Financial Status,Device Model,Product Title,Product ID,Product Type,SKU ID,Currency of Sale,Item Price,Taxes Collected,Charged Amount,City of Buyer,State of Buyer,Postal Code of Buyer,Country of Buyer
+GPA.1111-2222-3333-44444,2024-01-15,1705312800,Charged,phone,Premium (Example),com.example.app,subscription,premium_monthly,EUR,\"1,099.00\",0.00,\"1,099.00\",Berlin,,10115,DE
+GPA.1111-2222-3333-44444..1,2024-02-15,1707991200,Refund,phone,Premium (Example),com.example.app,subscription,premium_monthly,EUR,9.99,0.00,9.99,Berlin,,10115,DE
+GPA.5555-6666-7777-88888,2024-01-16,1705399200,Cancelled,phone,Premium (Example),com.example.app,subscription,premium_monthly,USD,9.99,0.00,9.99,Austin,TX,73301,US
+";
+        let table = Table::parse(contents).unwrap();
+        let parsed = parse_sales(&table).unwrap();
+
+        assert_eq!(parsed.report_type, StoreReportType::GoogleSales);
+        assert_eq!(parsed.skipped, 1);
+        assert_eq!(parsed.rows.len(), 2);
+
+        let sale = &parsed.rows[0];
+        assert_eq!(sale.kind, "sale");
+        assert_eq!(sale.amount, Some(1099.0));
+        assert_eq!(sale.currency.as_deref(), Some("EUR"));
+        assert_eq!(sale.country_code.as_deref(), Some("DE"));
+
+        // Refunds are listed with the charged amount, which is flipped
+        let refund = &parsed.rows[1];
+        assert_eq!(refund.kind, "refund");
+        assert_eq!(refund.amount, Some(-9.99));
+        assert_eq!(refund.order_id.as_deref(), Some("GPA.1111-2222-3333-44444..1"));
+    }
+}
@@ -0,0 +1,508 @@
+// Importing Apple and Google financial reports and reconciling them with
+// the transaction ledger. Each report format is parsed into the same
+// normalized sales and refunds, which are then matched against ledger
+// transactions by order ID, or by date and product when the report has no IDs.
+
+pub mod apple;
+pub mod google;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use std::collections::{HashMap, HashSet};
+
+use crate::db::models::{ReportEventKind, StoreReport, StoreReportRow, StoreReportType, TransactionType};
+use crate::error::{AppError, Result};
+use crate::utils::{csv, hashing::fnv1a_64};
+
+// How far a ledger date may be from the report's date. Stores report in
+// their own time zones, so a purchase can land on the neighbouring day.
+const MATCH_WINDOW_DAYS: i64 = 1;
+
+// Amounts closer than this are treated as equal
+const AMOUNT_TOLERANCE: f64 = 0.01;
+
+// A parsed report file, ready to be stored
+pub struct ParsedReport {
+    pub report_type: StoreReportType,
+    pub rows: Vec<StoreReportRow>,
+    pub skipped: i64,
+}
+
+// A delimited file with named columns
+pub struct Table {
+    columns: HashMap<String, usize>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents
+            .lines()
+            .map(|line| line.trim_start_matches('\u{feff}'))
+            .filter(|line| !line.trim().is_empty());
+
+        let header = lines.next()?;
+        let delimiter = if header.contains('\t') { '\t' } else { ',' };
+
+        let columns = csv::parse_line(header, delimiter)
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| (name.trim().to_lowercase(), index))
+            .collect();
+
+        let rows = lines.map(|line| csv::parse_line(line, delimiter)).collect();
+
+        Some(Self { columns, rows })
+    }
+
+    fn has_columns(&self, names: &[&str]) -> bool {
+        names.iter().all(|name| self.columns.contains_key(&name.to_lowercase()))
+    }
+
+    // The first non-empty value among the named columns
+    pub fn get<'a>(&self, row: &'a [String], names: &[&str]) -> Option<&'a str> {
+        names
+            .iter()
+            .filter_map(|name| self.columns.get(&name.to_lowercase()))
+            .filter_map(|index| row.get(*index))
+            .map(|value| value.trim())
+            .find(|value| !value.is_empty())
+    }
+
+    // The row as a JSON object of column name to value, kept for reference
+    pub fn raw_data(&self, row: &[String]) -> Option<String> {
+        let object: serde_json::Map<String, serde_json::Value> = self
+            .columns
+            .iter()
+            .filter_map(|(name, index)| {
+                row.get(*index)
+                    .map(|value| (name.clone(), serde_json::Value::String(value.clone())))
+            })
+            .collect();
+
+        serde_json::to_string(&object).ok()
+    }
+}
+
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    ["%Y-%m-%d", "%m/%d/%Y", "%b %d, %Y", "%Y%m%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value.trim(), format).ok())
+}
+
+pub fn parse_amount(value: &str) -> Option<f64> {
+    value.trim().replace(',', "").parse().ok()
+}
+
+// Work out the report format from its columns and parse it
+pub fn parse_report(contents: &str) -> std::result::Result<ParsedReport, String> {
+    let table = Table::parse(contents).ok_or_else(|| "Report is empty".to_string())?;
+
+    if table.has_columns(&["Units", "Developer Proceeds", "Begin Date"]) {
+        apple::parse_sales(&table)
+    } else if table.has_columns(&["Event Date", "Event"]) {
+        apple::parse_subscription_events(&table)
+    } else if table.has_columns(&["Description", "Transaction Type", "Transaction Date"]) {
+        google::parse_earnings(&table)
+    } else if table.has_columns(&["Order Number", "Financial Status"]) {
+        google::parse_sales(&table)
+    } else {
+        Err("Unrecognized report format".to_string())
+    }
+}
+
+// Parse and store a report, then match it against the ledger
+pub async fn import_report(
+    contents: &str,
+    filename: Option<String>,
+    pool: &SqlitePool,
+) -> Result<(StoreReport, Reconciliation)> {
+    let parsed = parse_report(contents).map_err(AppError::ValidationError)?;
+
+    let content_hash = format!("{:016x}", fnv1a_64(contents));
+    let store = parsed.report_type.store();
+
+    if let Some(existing) = StoreReport::find_by_content_hash(store, &content_hash, pool).await? {
+        return Err(AppError::BadRequest(format!(
+            "Report was already imported: {}",
+            existing.id
+        )));
+    }
+
+    let mut report = StoreReport::new(parsed.report_type, filename, content_hash);
+    report.start_date = parsed.rows.iter().map(|row| row.event_date).min();
+    report.end_date = parsed.rows.iter().map(|row| row.event_date).max();
+    report.row_count = parsed.rows.len() as i64;
+    report.skipped_count = parsed.skipped;
+
+    let rows: Vec<StoreReportRow> = parsed
+        .rows
+        .into_iter()
+        .map(|row| StoreReportRow {
+            report_id: report.id.clone(),
+            ..row
+        })
+        .collect();
+
+    report.create_with_rows(&rows, pool).await?;
+
+    let reconciliation = reconcile(&report, pool).await?;
+
+    Ok((report, reconciliation))
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingFromLedger {
+    pub row_id: String,
+    pub event_date: NaiveDate,
+    pub kind: String,
+    pub order_id: Option<String>,
+    pub store_product_id: Option<String>,
+    pub quantity: i64,  // Units the ledger has no transaction for
+    pub amount: Option<f64>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingFromReport {
+    pub transaction_id: String,
+    pub store_transaction_id: String,
+    pub type_: String,
+    pub transaction_date: DateTime<Utc>,
+    pub store_product_id: Option<String>,
+    pub amount: Option<f64>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AmountMismatch {
+    pub row_id: String,
+    pub transaction_id: String,
+    pub currency: Option<String>,
+    pub report_amount: f64,
+    pub ledger_amount: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Reconciliation {
+    pub report_id: String,
+    pub store: String,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub report_events: i64,
+    pub matched_events: i64,
+    pub missing_from_ledger: Vec<MissingFromLedger>,
+    pub missing_from_report: Vec<MissingFromReport>,
+    pub amount_mismatches: Vec<AmountMismatch>,
+}
+
+// A ledger transaction along with the store's ID for its product
+#[derive(Debug, sqlx::FromRow)]
+struct LedgerEntry {
+    id: String,
+    store_transaction_id: String,
+    #[sqlx(rename = "type")]
+    type_: String,
+    amount: Option<f64>,
+    currency: Option<String>,
+    transaction_date: DateTime<Utc>,
+    store_product_id: Option<String>,
+}
+
+impl LedgerEntry {
+    fn kind(&self) -> ReportEventKind {
+        if self.type_ == TransactionType::Refund.to_string() {
+            ReportEventKind::Refund
+        } else {
+            ReportEventKind::Sale
+        }
+    }
+
+    fn days_from(&self, date: NaiveDate) -> i64 {
+        (self.transaction_date.date_naive() - date).num_days().abs()
+    }
+}
+
+async fn load_ledger(
+    store: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> std::result::Result<Vec<LedgerEntry>, sqlx::Error> {
+    sqlx::query_as::<_, LedgerEntry>(
+        r#"
+        SELECT
+            t.id, t.store_transaction_id, t.type, t.amount, t.currency, t.transaction_date,
+            CASE t.store WHEN 'apple' THEN p.apple_product_id ELSE p.google_product_id END AS store_product_id
+        FROM transactions t
+        LEFT JOIN subscriptions s ON s.id = t.subscription_id
+        LEFT JOIN products p ON p.id = s.product_id
+        WHERE t.store = ? AND t.transaction_date >= ? AND t.transaction_date < ?
+        ORDER BY t.transaction_date
+        "#,
+    )
+    .bind(store)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+}
+
+// Google renewals add a `..N` suffix to the order ID of the first purchase
+fn base_order_id(order_id: &str) -> &str {
+    order_id.split("..").next().unwrap_or(order_id)
+}
+
+// Find the closest unmatched ledger transaction for one unit of a report row
+fn find_match(row: &StoreReportRow, ledger: &[LedgerEntry], matched: &HashSet<usize>) -> Option<usize> {
+    let candidates = ledger
+        .iter()
+        .enumerate()
+        .filter(|(index, entry)| !matched.contains(index) && entry.kind().to_string() == row.kind);
+
+    match &row.order_id {
+        Some(order_id) => candidates
+            .filter(|(_, entry)| base_order_id(&entry.store_transaction_id) == base_order_id(order_id))
+            .min_by_key(|(_, entry)| (entry.store_transaction_id != *order_id, entry.days_from(row.event_date)))
+            .map(|(index, _)| index),
+        None => candidates
+            .filter(|(_, entry)| entry.days_from(row.event_date) <= MATCH_WINDOW_DAYS)
+            .filter(|(_, entry)| {
+                row.store_product_id.is_none() || entry.store_product_id == row.store_product_id
+            })
+            .min_by_key(|(_, entry)| entry.days_from(row.event_date))
+            .map(|(index, _)| index),
+    }
+}
+
+// Match the report's rows against the ledger, store the matches, and list
+// what only one side saw
+pub async fn reconcile(report: &StoreReport, pool: &SqlitePool) -> Result<Reconciliation> {
+    let mut rows = report.get_rows(pool).await?;
+
+    let (start_date, end_date) = match (report.start_date, report.end_date) {
+        (Some(start_date), Some(end_date)) => (start_date, end_date),
+        _ => (Utc::now().date_naive(), Utc::now().date_naive()),
+    };
+
+    let window = Duration::days(MATCH_WINDOW_DAYS);
+    let from = crate::analytics::start_of_day(start_date) - window;
+    let to = crate::analytics::start_of_day(end_date) + Duration::days(1) + window;
+    let ledger = load_ledger(&report.store, from, to, pool).await?;
+
+    let mut matched: HashSet<usize> = HashSet::new();
+    let mut missing_from_ledger = Vec::new();
+    let mut amount_mismatches = Vec::new();
+    let mut report_events = 0;
+    let mut matched_events = 0;
+
+    for row in &mut rows {
+        row.matched_quantity = 0;
+        row.matched_transaction_id = None;
+
+        for _ in 0..row.quantity {
+            report_events += 1;
+
+            let index = match find_match(row, &ledger, &matched) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            matched.insert(index);
+            matched_events += 1;
+            row.matched_quantity += 1;
+
+            let entry = &ledger[index];
+            if row.matched_transaction_id.is_none() {
+                row.matched_transaction_id = Some(entry.id.clone());
+            }
+
+            let same_currency = match (&row.currency, &entry.currency) {
+                (Some(row_currency), Some(entry_currency)) => row_currency.eq_ignore_ascii_case(entry_currency),
+                _ => false,
+            };
+
+            if let (Some(report_amount), Some(ledger_amount), true) = (row.amount, entry.amount, same_currency) {
+                if (report_amount - ledger_amount).abs() > AMOUNT_TOLERANCE {
+                    amount_mismatches.push(AmountMismatch {
+                        row_id: row.id.clone(),
+                        transaction_id: entry.id.clone(),
+                        currency: row.currency.clone(),
+                        report_amount,
+                        ledger_amount,
+                    });
+                }
+            }
+        }
+
+        if row.matched_quantity < row.quantity {
+            missing_from_ledger.push(MissingFromLedger {
+                row_id: row.id.clone(),
+                event_date: row.event_date,
+                kind: row.kind.clone(),
+                order_id: row.order_id.clone(),
+                store_product_id: row.store_product_id.clone(),
+                quantity: row.quantity - row.matched_quantity,
+                amount: row.amount,
+                currency: row.currency.clone(),
+            });
+        }
+    }
+
+    report.update_matches(&rows, pool).await?;
+
+    // Ledger transactions just outside the report's dates were only loaded
+    // to allow for time zones, so they aren't expected in the report
+    let missing_from_report = ledger
+        .into_iter()
+        .enumerate()
+        .filter(|(index, entry)| {
+            let date = entry.transaction_date.date_naive();
+            !matched.contains(index) && date >= start_date && date <= end_date
+        })
+        .map(|(_, entry)| MissingFromReport {
+            transaction_id: entry.id,
+            store_transaction_id: entry.store_transaction_id,
+            type_: entry.type_,
+            transaction_date: entry.transaction_date,
+            store_product_id: entry.store_product_id,
+            amount: entry.amount,
+            currency: entry.currency,
+        })
+        .collect();
+
+    Ok(Reconciliation {
+        report_id: report.id.clone(),
+        store: report.store.clone(),
+        start_date: report.start_date,
+        end_date: report.end_date,
+        report_events,
+        matched_events,
+        missing_from_ledger,
+        missing_from_report,
+        amount_mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    fn entry(store_transaction_id: &str, type_: TransactionType, day: u32, product: Option<&str>) -> LedgerEntry {
+        LedgerEntry {
+            id: format!("ledger-{}-{}", store_transaction_id, day),
+            store_transaction_id: store_transaction_id.to_string(),
+            type_: type_.to_string(),
+            amount: Some(9.99),
+            currency: Some("USD".to_string()),
+            transaction_date: crate::analytics::start_of_day(date(day)) + Duration::hours(12),
+            store_product_id: product.map(str::to_string),
+        }
+    }
+
+    fn row(kind: ReportEventKind, day: u32, order_id: Option<&str>, product: Option<&str>) -> StoreReportRow {
+        StoreReportRow::new(
+            date(day),
+            kind,
+            order_id.map(str::to_string),
+            product.map(str::to_string),
+            1,
+            Some(9.99),
+            Some("USD".to_string()),
+            None,
+        )
+    }
+
+    #[test]
+    fn parses_report_dates_and_amounts() {
+        assert_eq!(parse_date("2024-01-15"), Some(date(15)));
+        assert_eq!(parse_date("01/15/2024"), Some(date(15)));
+        assert_eq!(parse_date("Jan 15, 2024"), Some(date(15)));
+        assert_eq!(parse_date("20240115"), Some(date(15)));
+        assert_eq!(parse_date("15.01.2024"), None);
+
+        assert_eq!(parse_amount(" 1,099.50 "), Some(1099.5));
+        assert_eq!(parse_amount("-9.99"), Some(-9.99));
+        assert_eq!(parse_amount(""), None);
+    }
+
+    #[test]
+    fn detects_report_format_from_columns() {
+        let apple_sales = "Units\tDeveloper Proceeds\tBegin Date\tProduct Type Identifier\n1\t0.7\t01/15/2024\tIAY\n";
+        let apple_events = "Event Date\tEvent\n2024-01-15\tSubscribe\n";
+        let google_earnings = "Description,Transaction Type,Transaction Date\nGPA.1,Charge,2024-01-15\n";
+        let google_sales = "Order Number,Financial Status,Order Charged Date\nGPA.1,Charged,2024-01-15\n";
+
+        assert_eq!(parse_report(apple_sales).unwrap().report_type, StoreReportType::AppleSales);
+        assert_eq!(parse_report(apple_events).unwrap().report_type, StoreReportType::AppleSubscriptionEvents);
+        assert_eq!(parse_report(google_earnings).unwrap().report_type, StoreReportType::GoogleEarnings);
+        assert_eq!(parse_report(google_sales).unwrap().report_type, StoreReportType::GoogleSales);
+
+        // A byte order mark on the header doesn't hide the columns
+        assert!(parse_report(&format!("\u{feff}{}", google_sales)).is_ok());
+        assert!(parse_report("").is_err());
+        assert!(parse_report("Foo,Bar\n1,2\n").is_err());
+    }
+
+    #[test]
+    fn strips_renewal_suffix_from_order_ids() {
+        assert_eq!(base_order_id("GPA.1234-5678-9012-34567"), "GPA.1234-5678-9012-34567");
+        assert_eq!(base_order_id("GPA.1234-5678-9012-34567..0"), "GPA.1234-5678-9012-34567");
+        assert_eq!(base_order_id("GPA.1234-5678-9012-34567..12"), "GPA.1234-5678-9012-34567");
+    }
+
+    #[test]
+    fn matches_google_orders_by_order_id() {
+        let ledger = vec![
+            entry("GPA.1234", TransactionType::InitialPurchase, 1, None),
+            entry("GPA.1234..1", TransactionType::Renewal, 15, None),
+            entry("GPA.1234..1", TransactionType::Refund, 16, None),
+            entry("GPA.9999", TransactionType::InitialPurchase, 15, None),
+        ];
+        let mut matched = HashSet::new();
+
+        // The exact order ID wins over the other renewals of the same purchase
+        let renewal = row(ReportEventKind::Sale, 15, Some("GPA.1234..1"), None);
+        assert_eq!(find_match(&renewal, &ledger, &matched), Some(1));
+
+        // Refunds only match refunds
+        let refund = row(ReportEventKind::Refund, 16, Some("GPA.1234..1"), None);
+        assert_eq!(find_match(&refund, &ledger, &matched), Some(2));
+
+        // Once the exact order is taken, another renewal of the purchase is used
+        matched.insert(1);
+        assert_eq!(find_match(&renewal, &ledger, &matched), Some(0));
+
+        let unknown = row(ReportEventKind::Sale, 15, Some("GPA.0000"), None);
+        assert_eq!(find_match(&unknown, &ledger, &matched), None);
+    }
+
+    #[test]
+    fn matches_apple_rows_by_date_and_product() {
+        let ledger = vec![
+            entry("1000", TransactionType::InitialPurchase, 10, Some("premium_monthly")),
+            entry("1001", TransactionType::Renewal, 14, Some("premium_annual")),
+            entry("1002", TransactionType::Renewal, 16, Some("premium_monthly")),
+        ];
+        let matched = HashSet::new();
+
+        // A day either side is allowed for time zones
+        let sale = row(ReportEventKind::Sale, 15, None, Some("premium_monthly"));
+        assert_eq!(find_match(&sale, &ledger, &matched), Some(2));
+
+        let too_far = row(ReportEventKind::Sale, 12, None, Some("premium_monthly"));
+        assert_eq!(find_match(&too_far, &ledger, &matched), None);
+
+        // Rows without a product match on date alone
+        let event = row(ReportEventKind::Sale, 14, None, None);
+        assert_eq!(find_match(&event, &ledger, &matched), Some(1));
+
+        let refund = row(ReportEventKind::Refund, 15, None, Some("premium_monthly"));
+        assert_eq!(find_match(&refund, &ledger, &matched), None);
+    }
+}
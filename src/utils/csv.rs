@@ -0,0 +1,40 @@
+// Minimal CSV reading and writing for report imports and exports
+
+// Quote a field if it contains a separator, quote or line break
+pub fn escape_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Join the fields into one CSV line, including the trailing newline
+pub fn write_row<S: AsRef<str>>(output: &mut String, fields: &[S]) {
+    let line: Vec<String> = fields.iter().map(|field| escape_field(field.as_ref())).collect();
+    output.push_str(&line.join(","));
+    output.push('\n');
+}
+
+// Split one line into fields, handling quoted fields with escaped quotes
+pub fn parse_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    fields.push(field);
+    fields
+}
@@ -0,0 +1,84 @@
+// Stable hashing for deterministic bucketing (e.g. experiment variants).
+// The standard library hasher is randomly seeded per process, so we use
+// 64-bit FNV-1a which gives the same result across restarts and machines.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub fn fnv1a_64(input: &str) -> u64 {
+    input.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// Map a key onto one of the weighted buckets, returning the bucket index.
+// Returns None when there are no buckets or all weights are zero.
+pub fn weighted_bucket(key: &str, weights: &[u32]) -> Option<usize> {
+    let total: u64 = weights.iter().map(|weight| *weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = fnv1a_64(key) % total;
+
+    for (index, weight) in weights.iter().enumerate() {
+        let weight = *weight as u64;
+        if point < weight {
+            return Some(index);
+        }
+        point -= weight;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_64_matches_reference_vectors() {
+        assert_eq!(fnv1a_64(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_64("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a_64("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn weighted_bucket_is_stable() {
+        let weights = [1, 1, 2];
+        let first = weighted_bucket("experiment:user-1", &weights);
+
+        assert!(first.is_some());
+        for _ in 0..10 {
+            assert_eq!(weighted_bucket("experiment:user-1", &weights), first);
+        }
+    }
+
+    #[test]
+    fn weighted_bucket_rejects_empty_weights() {
+        assert_eq!(weighted_bucket("key", &[]), None);
+        assert_eq!(weighted_bucket("key", &[0, 0]), None);
+    }
+
+    #[test]
+    fn weighted_bucket_skips_zero_weights() {
+        for i in 0..100 {
+            assert_eq!(weighted_bucket(&format!("user-{}", i), &[0, 3, 0]), Some(1));
+        }
+    }
+
+    #[test]
+    fn weighted_bucket_follows_weights() {
+        let weights = [1, 3];
+        let mut counts = [0; 2];
+
+        for i in 0..10_000 {
+            let index = weighted_bucket(&format!("experiment:user-{}", i), &weights).unwrap();
+            counts[index] += 1;
+        }
+
+        // Expect roughly a 25/75 split
+        assert!((2_250..=2_750).contains(&counts[0]), "counts: {:?}", counts);
+        assert!((7_250..=7_750).contains(&counts[1]), "counts: {:?}", counts);
+    }
+}
@@ -1,4 +1,7 @@
-// This module will contain utility functions for validation, etc.
-// For now, it's just a placeholder to prevent compilation errors.
+// Utility functions shared across the API, webhooks and models
 
 pub mod validation;
+pub mod hashing;
+pub mod stats;
+pub mod csv;
+pub mod offer_signature;
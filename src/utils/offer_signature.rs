@@ -0,0 +1,64 @@
+// Signatures for Apple promotional offers. StoreKit expects an ECDSA P-256
+// signature (SHA-256, ASN.1 DER, base64 encoded) over the offer parameters
+// joined with U+2063 INVISIBLE SEPARATOR, made with a subscription offer key
+// from App Store Connect.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+const SEPARATOR: char = '\u{2063}';
+
+pub struct OfferSignatureParams<'a> {
+    pub app_bundle_id: &'a str,
+    pub key_identifier: &'a str,
+    pub product_identifier: &'a str,
+    pub offer_identifier: &'a str,
+    pub application_username: &'a str,
+    pub nonce: &'a str,
+    pub timestamp: i64,
+}
+
+// Build the string that gets signed. The application username must match
+// what the app passes to StoreKit, the nonce is a lowercase UUID and the
+// timestamp is in milliseconds since the epoch.
+pub fn payload(params: &OfferSignatureParams<'_>) -> String {
+    [
+        params.app_bundle_id,
+        params.key_identifier,
+        params.product_identifier,
+        params.offer_identifier,
+        params.application_username,
+        &params.nonce.to_lowercase(),
+        &params.timestamp.to_string(),
+    ]
+    .join(&SEPARATOR.to_string())
+}
+
+// Sign the offer parameters with a PKCS#8 private key in PEM form (the
+// contents of the .p8 file), returning the base64 encoded signature.
+pub fn sign(params: &OfferSignatureParams<'_>, private_key_pem: &str) -> Result<String, String> {
+    let der = pem_to_der(private_key_pem)?;
+    let rng = SystemRandom::new();
+
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &der, &rng)
+        .map_err(|e| format!("Invalid subscription offer key: {}", e))?;
+
+    let signature = key_pair
+        .sign(&rng, payload(params).as_bytes())
+        .map_err(|_| "Failed to sign promotional offer".to_string())?;
+
+    Ok(STANDARD.encode(signature.as_ref()))
+}
+
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, String> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+
+    STANDARD
+        .decode(body)
+        .map_err(|e| format!("Invalid subscription offer key: {}", e))
+}
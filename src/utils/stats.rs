@@ -0,0 +1,148 @@
+// Basic statistics used for experiment results
+
+// Standard normal cumulative distribution function
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+// Abramowitz and Stegun approximation 7.1.26 (max error ~1.5e-7)
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let y = 1.0
+        - (((((1.061405429 * t - 1.453152027) * t) + 1.421413741) * t - 0.284496736) * t
+            + 0.254829592)
+            * t
+            * (-x * x).exp();
+
+    sign * y
+}
+
+// Ratio of successes to trials, or zero when there were no trials
+pub fn rate(successes: i64, trials: i64) -> f64 {
+    if trials == 0 {
+        0.0
+    } else {
+        successes as f64 / trials as f64
+    }
+}
+
+// 95% Wilson score interval for a proportion
+pub fn wilson_interval(successes: i64, trials: i64) -> Option<(f64, f64)> {
+    if trials == 0 {
+        return None;
+    }
+
+    let z = 1.96;
+    let n = trials as f64;
+    let p = successes as f64 / n;
+
+    let denominator = 1.0 + z * z / n;
+    let center = (p + z * z / (2.0 * n)) / denominator;
+    let margin = z * ((p * (1.0 - p) / n) + z * z / (4.0 * n * n)).sqrt() / denominator;
+
+    Some(((center - margin).max(0.0), (center + margin).min(1.0)))
+}
+
+// Two-sided two-proportion z-test, returning (z score, p-value).
+// Returns None when either group is empty or the pooled rate is 0 or 1.
+pub fn two_proportion_z_test(
+    successes_a: i64,
+    trials_a: i64,
+    successes_b: i64,
+    trials_b: i64,
+) -> Option<(f64, f64)> {
+    if trials_a == 0 || trials_b == 0 {
+        return None;
+    }
+
+    let n_a = trials_a as f64;
+    let n_b = trials_b as f64;
+    let pooled = (successes_a + successes_b) as f64 / (n_a + n_b);
+
+    let standard_error = (pooled * (1.0 - pooled) * (1.0 / n_a + 1.0 / n_b)).sqrt();
+    if standard_error == 0.0 {
+        return None;
+    }
+
+    let z = (rate(successes_b, trials_b) - rate(successes_a, trials_a)) / standard_error;
+    let p_value = 2.0 * (1.0 - normal_cdf(z.abs()));
+
+    Some((z, p_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "expected {} to be within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn erf_matches_known_values() {
+        assert_close(erf(0.0), 0.0, 1e-6);
+        assert_close(erf(0.5), 0.5204998778, 1e-6);
+        assert_close(erf(1.0), 0.8427007929, 1e-6);
+        assert_close(erf(-1.0), -0.8427007929, 1e-6);
+        assert_close(erf(3.0), 0.9999779095, 1e-6);
+    }
+
+    #[test]
+    fn normal_cdf_matches_known_values() {
+        assert_close(normal_cdf(0.0), 0.5, 1e-7);
+        assert_close(normal_cdf(1.96), 0.9750021, 1e-6);
+        assert_close(normal_cdf(-1.96), 0.0249979, 1e-6);
+    }
+
+    #[test]
+    fn rate_handles_empty_groups() {
+        assert_eq!(rate(0, 0), 0.0);
+        assert_eq!(rate(1, 4), 0.25);
+    }
+
+    #[test]
+    fn wilson_interval_matches_known_values() {
+        assert_eq!(wilson_interval(0, 0), None);
+
+        let (low, high) = wilson_interval(5, 10).unwrap();
+        assert_close(low, 0.2365896, 1e-6);
+        assert_close(high, 0.7634104, 1e-6);
+
+        // Bounds are clamped to [0, 1] at the extremes
+        let (low, high) = wilson_interval(0, 10).unwrap();
+        assert_eq!(low, 0.0);
+        assert_close(high, 0.2775402, 1e-6);
+
+        let (low, high) = wilson_interval(10, 10).unwrap();
+        assert_close(low, 0.7224598, 1e-6);
+        assert_eq!(high, 1.0);
+    }
+
+    #[test]
+    fn two_proportion_z_test_matches_textbook_example() {
+        // 20% vs 30% conversion with 200 users each
+        let (z, p_value) = two_proportion_z_test(40, 200, 60, 200).unwrap();
+        assert_close(z, 2.3094011, 1e-6);
+        assert_close(p_value, 0.0209213, 1e-6);
+
+        // The sign follows the direction of the difference
+        let (z, _) = two_proportion_z_test(60, 200, 40, 200).unwrap();
+        assert_close(z, -2.3094011, 1e-6);
+    }
+
+    #[test]
+    fn two_proportion_z_test_rejects_degenerate_input() {
+        assert_eq!(two_proportion_z_test(0, 0, 5, 10), None);
+        assert_eq!(two_proportion_z_test(0, 10, 0, 10), None);
+        assert_eq!(two_proportion_z_test(10, 10, 10, 10), None);
+    }
+}
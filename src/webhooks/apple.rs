@@ -3,21 +3,28 @@ use axum::{
     http::{HeaderMap, StatusCode},
 };
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sqlx::sqlite::SqlitePool;
 use uuid::Uuid;
 
 use crate::db::models::{
-    User, Product, Subscription, SubscriptionStatus, UserEntitlement,
+    App, ConsumptionRequest, ConsumptionRequestStatus, ExpirationReason, OwnershipType, User, Product, ProductType, Subscription, SubscriptionEvent,
+    SubscriptionEventType, SubscriptionStatus, Transaction, TransactionType, UnattributedPurchase, UserEntitlement,
+    WalletEntry,
 };
 use crate::error::{AppError, Result};
+use crate::providers::apple::{self as app_store, ConsumptionInformation};
 
-#[derive(Debug, Deserialize)]
+// Apple only considers consumption data sent within 12 hours of the request
+const CONSUMPTION_RESPONSE_WINDOW_HOURS: i64 = 12;
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AppleNotificationPayload {
     #[serde(rename = "notificationType")]
     notification_type: String,
     #[serde(rename = "subtype")]
-    sub_type: Option<String>,
+    sub_type: Option<AppleNotificationSubtype>,
     #[serde(rename = "notificationUUID")]
     notification_uuid: String,
     #[serde(rename = "notificationVersion")]
@@ -27,7 +34,47 @@ pub struct AppleNotificationPayload {
     signed_date: i64, // Unix timestamp in milliseconds
 }
 
-#[derive(Debug, Deserialize)]
+// Subtypes refine what a notification means, e.g. whether an EXPIRED
+// subscription was cancelled or its payment failed
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AppleNotificationSubtype {
+    InitialBuy,
+    Resubscribe,
+    Downgrade,
+    Upgrade,
+    AutoRenewEnabled,
+    AutoRenewDisabled,
+    Voluntary,
+    BillingRetry,
+    PriceIncrease,
+    GracePeriod,
+    Pending,
+    Accepted,
+    BillingRecovery,
+    ProductNotForSale,
+    Summary,
+    Failure,
+    Unreported,
+    // Subtypes Apple adds later
+    #[serde(other)]
+    Unknown,
+}
+
+impl AppleNotificationSubtype {
+    // Why an EXPIRED subscription stopped renewing
+    fn expiration_reason(&self) -> ExpirationReason {
+        match self {
+            AppleNotificationSubtype::Voluntary => ExpirationReason::Voluntary,
+            AppleNotificationSubtype::BillingRetry => ExpirationReason::BillingError,
+            AppleNotificationSubtype::PriceIncrease => ExpirationReason::PriceIncrease,
+            AppleNotificationSubtype::ProductNotForSale => ExpirationReason::ProductNotForSale,
+            _ => ExpirationReason::Other,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AppleNotificationData {
     #[serde(rename = "appAppleId")]
     app_apple_id: Option<String>,
@@ -95,7 +142,7 @@ pub struct AppleRenewalInfo {
     #[serde(rename = "gracePeriodExpiresDate")]
     grace_period_expires_date: Option<i64>, // Unix timestamp in milliseconds
     #[serde(rename = "isInBillingRetryPeriod")]
-    is_in_billing_retry_period: Option<i32>, // 1 = yes, 0 = no
+    is_in_billing_retry_period: Option<bool>,
     #[serde(rename = "offerIdentifier")]
     offer_identifier: Option<String>,
     #[serde(rename = "offerType")]
@@ -123,80 +170,155 @@ pub async fn handle_apple_webhook(
     // In a real implementation, verify the webhook signature
     // For now, we'll just process the notification
 
+    process_notification(&payload, &pool).await?;
+
+    // Return success response
+    Ok((
+        StatusCode::OK,
+        Json(WebhookResponse {
+            message: "Webhook processed successfully".to_string(),
+        }),
+    ))
+}
+
+async fn process_notification(payload: &AppleNotificationPayload, pool: &SqlitePool) -> Result<()> {
+    // Consumption requests wait on a call to Apple, so they're saved on
+    // their own instead of holding a transaction open
+    if payload.notification_type == "CONSUMPTION_REQUEST" {
+        return process_consumption_request(payload, pool).await;
+    }
+
+    // Save all of a notification's changes or none of them, so a failed
+    // notification can be retried from scratch
+    let mut tx = pool.begin().await?;
+    apply_notification(payload, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn apply_notification(
+    payload: &AppleNotificationPayload,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    // Family members get their own notifications about the purchaser's
+    // subscription. Their access follows the purchaser's subscription, so
+    // only the ones that start or end it are handled.
+    if ownership_type(payload) == OwnershipType::FamilyShared {
+        match payload.notification_type.as_str() {
+            "SUBSCRIBED" => process_family_share(payload, tx).await?,
+            "REVOKE" => process_family_share_revocation(payload, tx).await?,
+            _ => tracing::info!(
+                "Family shared Apple notification {}: {}",
+                payload.notification_type,
+                payload.notification_uuid
+            ),
+        }
+
+        return Ok(());
+    }
+
     // Process based on notification type
     match payload.notification_type.as_str() {
-        "CONSUMPTION_REQUEST" => {
-            // Handle consumption request (e.g., check entitlement)
-            // This is typically used for consumable products
-        }
         "DID_CHANGE_RENEWAL_PREF" => {
             // Handle subscription renewal preference change
-            process_renewal_change(&payload, &pool).await?;
+            process_renewal_change(payload, tx).await?;
         }
         "DID_CHANGE_RENEWAL_STATUS" => {
             // Handle subscription renewal status change
-            process_renewal_status_change(&payload, &pool).await?;
+            process_renewal_status_change(payload, tx).await?;
         }
         "DID_FAIL_TO_RENEW" => {
             // Handle subscription renewal failure
-            process_renewal_failure(&payload, &pool).await?;
+            process_renewal_failure(payload, tx).await?;
         }
         "DID_RENEW" => {
             // Handle subscription renewal
-            process_subscription_renewal(&payload, &pool).await?;
+            process_subscription_renewal(payload, tx).await?;
         }
         "EXPIRED" => {
             // Handle subscription expiration
-            process_subscription_expiration(&payload, &pool).await?;
+            process_subscription_expiration(payload, tx).await?;
         }
         "GRACE_PERIOD_EXPIRED" => {
             // Handle grace period expiration
-            process_grace_period_expiration(&payload, &pool).await?;
+            process_grace_period_expiration(payload, tx).await?;
         }
         "OFFER_REDEEMED" => {
             // Handle offer redemption
-            process_offer_redemption(&payload, &pool).await?;
+            process_offer_redemption(payload, tx).await?;
         }
         "PRICE_INCREASE" => {
             // Handle price increase
-            process_price_increase(&payload, &pool).await?;
+            process_price_increase(payload, tx).await?;
         }
         "REFUND" => {
             // Handle refund
-            process_refund(&payload, &pool).await?;
+            process_refund(payload, tx).await?;
+        }
+        "REFUND_REVERSED" => {
+            // Handle a refund Apple reversed after a dispute
+            process_refund_reversal(payload, tx).await?;
+        }
+        "ONE_TIME_CHARGE" => {
+            // Handle consumable, non-consumable and non-renewing purchases
+            process_one_time_charge(payload, tx).await?;
+        }
+        "RENEWAL_EXTENSION" => {
+            // Apple reports on a renewal date extension requested for many
+            // subscribers at once. Each extended subscription also gets its
+            // own RENEWAL_EXTENDED notification.
+            tracing::info!(
+                "Apple renewal extension {:?}: {}",
+                payload.sub_type.unwrap_or(AppleNotificationSubtype::Unknown),
+                payload.notification_uuid
+            );
+        }
+        "EXTERNAL_PURCHASE_TOKEN" => {
+            // Only sent for apps using external purchase links, which we don't handle
+            tracing::info!("Apple external purchase token: {}", payload.notification_uuid);
+        }
+        "TEST" => {
+            // Sent when testing connectivity from App Store Connect or the API
+            tracing::info!("Apple test notification received: {}", payload.notification_uuid);
         }
         "REFUND_DECLINED" => {
             // Handle refund decline
-            process_refund_declined(&payload, &pool).await?;
+            process_refund_declined(payload, tx).await?;
         }
         "RENEWAL_EXTENDED" => {
             // Handle renewal extension
-            process_renewal_extension(&payload, &pool).await?;
+            process_renewal_extension(payload, tx).await?;
         }
         "REVOKE" => {
             // Handle subscription revocation
-            process_subscription_revocation(&payload, &pool).await?;
+            process_subscription_revocation(payload, tx).await?;
         }
         "SUBSCRIBED" => {
             // Handle new subscription
-            process_new_subscription(&payload, &pool).await?;
+            process_new_subscription(payload, tx).await?;
         }
         _ => {
-            // Unknown notification type
-            return Err(AppError::BadRequest(format!(
-                "Unknown notification type: {}",
-                payload.notification_type
-            )));
+            // Apple adds notification types over time. Acknowledge them so
+            // they aren't retried, and log them so they can be handled.
+            tracing::warn!(
+                "Unhandled Apple notification type {} ({:?}): {}",
+                payload.notification_type,
+                payload.sub_type,
+                payload.notification_uuid
+            );
         }
     }
 
-    // Return success response
-    Ok((
-        StatusCode::OK,
-        Json(WebhookResponse {
-            message: "Webhook processed successfully".to_string(),
-        }),
-    ))
+    Ok(())
+}
+
+// Process a notification stored with an unattributed purchase
+pub(super) async fn replay_notification(notification_json: &str, pool: &SqlitePool) -> Result<()> {
+    let payload: AppleNotificationPayload = serde_json::from_str(notification_json)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid stored notification: {}", e)))?;
+
+    process_notification(&payload, pool).await
 }
 
 // Helper function to decode and verify the transaction info JWT
@@ -221,10 +343,87 @@ async fn decode_renewal_info(signed_renewal_info: &str) -> Result<AppleRenewalIn
     ))
 }
 
+// The claims of a signed JWS, without verifying its signature
+fn decode_jws_claims<T: DeserializeOwned>(token: &str) -> Option<T> {
+    let claims = token.split('.').nth(1)?;
+    let bytes = URL_SAFE_NO_PAD.decode(claims.trim_end_matches('=')).ok()?;
+
+    serde_json::from_slice(&bytes).ok()
+}
+
+// Whether the transaction is the purchaser's own or a family member's
+// access to it through Family Sharing
+fn ownership_type(payload: &AppleNotificationPayload) -> OwnershipType {
+    #[derive(Deserialize)]
+    struct OwnershipClaims {
+        #[serde(rename = "inAppOwnershipType")]
+        in_app_ownership_type: Option<String>,
+    }
+
+    payload
+        .data
+        .signed_transaction_info
+        .as_deref()
+        .and_then(decode_jws_claims::<OwnershipClaims>)
+        .and_then(|claims| claims.in_app_ownership_type)
+        .map(|in_app_ownership_type| OwnershipType::from_apple(&in_app_ownership_type))
+        .unwrap_or(OwnershipType::Purchased)
+}
+
+// The UUID the app set on the purchase to identify the user, if it set one
+fn app_account_token(payload: &AppleNotificationPayload) -> Option<String> {
+    #[derive(Deserialize)]
+    struct AccountTokenClaims {
+        #[serde(rename = "appAccountToken")]
+        app_account_token: Option<String>,
+    }
+
+    payload
+        .data
+        .signed_transaction_info
+        .as_deref()
+        .and_then(decode_jws_claims::<AccountTokenClaims>)
+        .and_then(|claims| claims.app_account_token)
+}
+
+// Find the user a purchase belongs to from its appAccountToken. Purchases
+// we can't match are parked until the user is known, and None is returned.
+async fn find_user(
+    payload: &AppleNotificationPayload,
+    apple_product_id: &str,
+    transaction_id: &str,
+    original_transaction_id: &str,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<Option<String>> {
+    let app_account_token = app_account_token(payload);
+    let account_tokens: Vec<&str> = app_account_token.as_deref().into_iter().collect();
+    if let Some(user_id) = super::resolve_user("apple", transaction_id, &account_tokens, tx).await? {
+        return Ok(Some(user_id));
+    }
+
+    let notification_json = serde_json::to_string(payload)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to store notification: {}", e)))?;
+
+    super::park_purchase(
+        UnattributedPurchase::new(
+            "apple".to_string(),
+            app_account_token,
+            apple_product_id.to_string(),
+            transaction_id.to_string(),
+            Some(original_transaction_id.to_string()),
+            notification_json,
+        ),
+        tx,
+    )
+    .await?;
+
+    Ok(None)
+}
+
 // Process a new subscription
 async fn process_new_subscription(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // In a real implementation, we would decode the JWT token
@@ -234,39 +433,40 @@ async fn process_new_subscription(
         let transaction_id = "mock_transaction_id";
         let original_transaction_id = "mock_original_transaction_id";
         let apple_product_id = "mock_product_id";
-        let app_account_token = Some("mock_app_account_token"); // This could be used to identify the user
         let purchase_date = Utc::now();
         let expires_date = Some(Utc::now() + chrono::Duration::days(30)); // 30 days subscription
         
         // Find the product by Apple product ID
-        let product = Product::find_by_store_product_id("apple", apple_product_id, pool)
+        let product = Product::find_by_store_product_id("apple", apple_product_id, &mut **tx)
             .await?
             .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", apple_product_id)))?;
         
-        // Find or create the user
-        // In a real app, you'd have a way to map app_account_token to your own user IDs
-        // For this example, we'll just create a user if none exists
-        let user_id = if let Some(token) = app_account_token {
-            // Try to find a user by app_account_token
-            // This is just a mock - in a real app, you'd have your own mapping
-            let user = User::find_by_app_user_id(token, pool).await?;
-            
-            match user {
-                Some(user) => user.id,
-                None => {
-                    // Create a new user
-                    let new_user = User::new(token.to_string(), None);
-                    new_user.create(pool).await?;
-                    new_user.id
-                }
-            }
+        let Some(user_id) = find_user(
+            payload,
+            apple_product_id,
+            transaction_id,
+            original_transaction_id,
+            tx,
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+        
+        // Non-renewing subscriptions grant a fixed period, stacked after any
+        // time the user has left
+        let period_start_date = if product.type_ == ProductType::NonRenewing.to_string() {
+            Some(Subscription::next_period_start(&user_id, &product.id, purchase_date, &mut **tx).await?)
         } else {
-            // Without an app_account_token, we can't identify the user
-            return Err(AppError::BadRequest("Missing app_account_token".to_string()));
+            None
+        };
+        let expires_date = match (period_start_date, product.duration_days) {
+            (Some(start), Some(duration_days)) => Some(start + chrono::Duration::days(duration_days as i64)),
+            _ => expires_date,
         };
         
         // Create a new subscription
-        let subscription = Subscription::new(
+        let mut subscription = Subscription::new(
             user_id.clone(),
             product.id.clone(),
             Some(original_transaction_id.to_string()),
@@ -275,17 +475,38 @@ async fn process_new_subscription(
             purchase_date,
             expires_date,
             SubscriptionStatus::Active,
-            Some(true), // Auto-renew is on for new subscriptions
+            Some(period_start_date.is_none()), // Auto-renew is on for new auto-renewable subscriptions
             None,      // Price paid (not available in this mock)
             None,      // Currency (not available in this mock)
             false,     // Is trial
             false,     // Is intro offer
         );
+        subscription.period_start_date = period_start_date;
+        
+        // A resubscribe starts a new subscription after the previous one in
+        // the group lapsed. Apple keeps the original transaction, so link it.
+        let previous = if payload.sub_type == Some(AppleNotificationSubtype::Resubscribe) {
+            Subscription::find_by_store_transaction("apple", original_transaction_id, &mut **tx).await?
+        } else {
+            None
+        };
+        subscription.previous_subscription_id = previous.as_ref().map(|previous| previous.id.clone());
         
-        subscription.create(pool).await?;
+        subscription.create(&mut **tx).await?;
+        
+        if previous.is_some() {
+            SubscriptionEvent::new(&subscription, SubscriptionEventType::Resubscribed, purchase_date)
+                .create(&mut **tx)
+                .await?;
+        }
+        
+        // Record the purchase in the transaction ledger
+        Transaction::for_subscription(&subscription, TransactionType::InitialPurchase, purchase_date)
+            .create(tx)
+            .await?;
         
         // Get the entitlements for this product
-        let entitlement_ids = product.get_entitlements(pool).await?;
+        let entitlement_ids = product.get_entitlements(&mut **tx).await?;
         
         // Grant entitlements to the user
         for entitlement_id in entitlement_ids {
@@ -293,11 +514,123 @@ async fn process_new_subscription(
                 user_id.clone(),
                 entitlement_id,
                 Some(subscription.id.clone()),
-                purchase_date,
+                period_start_date.unwrap_or(purchase_date),
                 expires_date,
             );
             
-            user_entitlement.create(pool).await?;
+            user_entitlement.create(&mut **tx).await?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Process a family member getting access to a purchase through Family
+// Sharing. The family member's subscription is linked to the purchaser's and
+// follows it. It isn't a sale, so nothing is recorded in the ledger.
+async fn process_family_share(
+    payload: &AppleNotificationPayload,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    if payload.data.signed_transaction_info.is_some() {
+        // Mock the decoded data. Family members get their own transaction ID,
+        // with the purchaser's original transaction ID.
+        let transaction_id = "mock_transaction_id";
+        let original_transaction_id = "mock_original_transaction_id";
+        let apple_product_id = "mock_product_id";
+        let purchase_date = Utc::now();
+        
+        // Apple retries notifications, so only share a purchase once
+        if Subscription::find_family_share("apple", transaction_id, &mut **tx).await?.is_some() {
+            return Ok(());
+        }
+        
+        let owner = Subscription::find_by_store_transaction("apple", original_transaction_id, &mut **tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(
+                format!("Subscription not found: {}", original_transaction_id)
+            ))?;
+        
+        let Some(user_id) = find_user(
+            payload,
+            apple_product_id,
+            transaction_id,
+            original_transaction_id,
+            tx,
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+        
+        let mut subscription = Subscription::new(
+            user_id.clone(),
+            owner.product_id.clone(),
+            owner.original_transaction_id.clone(),
+            Some(transaction_id.to_string()),
+            "apple".to_string(),
+            purchase_date,
+            owner.expires_date,
+            SubscriptionStatus::Active,
+            None,  // Only the purchaser can turn off auto-renew
+            None,  // Family members don't pay
+            None,
+            false, // Is trial
+            false, // Is intro offer
+        );
+        subscription.status = owner.status.clone();
+        subscription.ownership_type = OwnershipType::FamilyShared.to_string();
+        subscription.family_owner_subscription_id = Some(owner.id.clone());
+        subscription.create(&mut **tx).await?;
+        
+        let product = Product::find_by_id(&owner.product_id, &mut **tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", owner.product_id)))?;
+        
+        for entitlement_id in product.get_entitlements(&mut **tx).await? {
+            UserEntitlement::new(
+                user_id.clone(),
+                entitlement_id,
+                Some(subscription.id.clone()),
+                purchase_date,
+                owner.expires_date,
+            )
+            .create(&mut **tx)
+            .await?;
+        }
+        
+        // Line the entitlements up with the purchaser's, e.g. when the
+        // purchaser's are suspended during billing retry
+        owner.sync_family_shares(tx).await?;
+    }
+    
+    Ok(())
+}
+
+// Process a family member losing access, because the purchaser turned off
+// Family Sharing for the product or the member left the family
+async fn process_family_share_revocation(
+    payload: &AppleNotificationPayload,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    if payload.data.signed_transaction_info.is_some() {
+        // Mock the decoded data
+        let transaction_id = "mock_transaction_id";
+        let now = Utc::now();
+        
+        let mut subscription = Subscription::find_family_share("apple", transaction_id, &mut **tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(
+                format!("Family shared subscription not found: {}", transaction_id)
+            ))?;
+        
+        // Marks the share as revoked, so it stops following the purchaser
+        subscription.cancel(now, &mut **tx).await?;
+        
+        for mut entitlement in UserEntitlement::list_active_for_user(&subscription.user_id, now, &mut **tx).await? {
+            if entitlement.subscription_id.as_deref() == Some(subscription.id.as_str()) {
+                entitlement.revoke(&mut **tx).await?;
+            }
         }
     }
     
@@ -307,7 +640,7 @@ async fn process_new_subscription(
 // Process subscription renewal
 async fn process_subscription_renewal(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // In a real implementation, we would decode the JWT token
@@ -322,33 +655,72 @@ async fn process_subscription_renewal(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
             format!("Subscription not found: {}", original_transaction_id)
         ))?;
         
+        // A pending downgrade takes effect with this renewal
+        if let Some(pending_product_id) = subscription.pending_product_id.clone() {
+            let pending_product = Product::find_by_id(&pending_product_id, &mut **tx)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", pending_product_id)))?;
+            let now = Utc::now();
+            
+            let mut renewed = Subscription::new(
+                subscription.user_id.clone(),
+                pending_product.id.clone(),
+                subscription.original_transaction_id.clone(),
+                Some(transaction_id.to_string()),
+                "apple".to_string(),
+                now,
+                expires_date,
+                SubscriptionStatus::Active,
+                Some(true),
+                None,  // Price paid (not available in this mock)
+                None,  // Currency (not available in this mock)
+                false, // Is trial
+                false, // Is intro offer
+            );
+            renewed.country_code = subscription.country_code.clone();
+            
+            super::replace_subscription(&mut subscription, &mut renewed, &pending_product, now, tx).await?;
+            
+            // Record the renewal in the transaction ledger
+            Transaction::for_subscription(&renewed, TransactionType::Renewal, now)
+                .create(tx)
+                .await?;
+            
+            return Ok(());
+        }
+        
         // Update subscription details
         subscription.store_transaction_id = Some(transaction_id.to_string());
         subscription.expires_date = expires_date;
         subscription.status = SubscriptionStatus::Active.to_string();
-        subscription.update(pool).await?;
+        subscription.expiration_reason = None;
+        subscription.clear_billing_issue();
+        subscription.update(&mut **tx).await?;
         
-        // Update user entitlements
-        let user_entitlements = UserEntitlement::list_active_for_user(
-            &subscription.user_id, 
-            Utc::now(), 
-            pool
-        ).await?;
-        
-        for mut entitlement in user_entitlements {
-            if let Some(sub_id) = &entitlement.subscription_id {
-                if sub_id == &subscription.id {
-                    entitlement.update_expiry(expires_date, pool).await?;
-                }
-            }
+        // The payment went through after billing retry or grace
+        if payload.sub_type == Some(AppleNotificationSubtype::BillingRecovery) {
+            SubscriptionEvent::new(&subscription, SubscriptionEventType::BillingRecovered, Utc::now())
+                .create(&mut **tx)
+                .await?;
         }
+        
+        // Record the renewal in the transaction ledger
+        Transaction::for_subscription(&subscription, TransactionType::Renewal, Utc::now())
+            .create(tx)
+            .await?;
+        
+        // Update user entitlements, including ones suspended during billing retry
+        UserEntitlement::update_expiry_for_subscription(&subscription.id, expires_date, &mut **tx).await?;
+        
+        // Family members' access follows the purchaser's subscription
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
@@ -357,7 +729,7 @@ async fn process_subscription_renewal(
 // Process subscription expiration
 async fn process_subscription_expiration(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // Mock the decoded data
@@ -367,30 +739,41 @@ async fn process_subscription_expiration(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
             format!("Subscription not found: {}", original_transaction_id)
         ))?;
         
-        // Update subscription status
-        subscription.update_status(SubscriptionStatus::Expired, pool).await?;
+        // Record why it expired so voluntary and involuntary churn can be told apart
+        let reason = payload
+            .sub_type
+            .map(|sub_type| sub_type.expiration_reason())
+            .unwrap_or(ExpirationReason::Other);
+        
+        subscription.status = SubscriptionStatus::Expired.to_string();
+        subscription.auto_renew_status = Some(false);
+        subscription.expiration_reason = Some(reason.to_string());
+        subscription.update(&mut **tx).await?;
         
         // Expire user entitlements
         let user_entitlements = UserEntitlement::list_active_for_user(
             &subscription.user_id, 
             Utc::now(), 
-            pool
+            &mut **tx
         ).await?;
         
         for mut entitlement in user_entitlements {
             if let Some(sub_id) = &entitlement.subscription_id {
                 if sub_id == &subscription.id {
-                    entitlement.update_expiry(Some(Utc::now()), pool).await?;
+                    entitlement.update_expiry(Some(Utc::now()), &mut **tx).await?;
                 }
             }
         }
+        
+        // Family members lose access with the purchaser
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
@@ -399,7 +782,7 @@ async fn process_subscription_expiration(
 // Process renewal status change
 async fn process_renewal_status_change(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_renewal_info) = &payload.data.signed_renewal_info {
         // Mock the decoded data
@@ -410,64 +793,213 @@ async fn process_renewal_status_change(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
             format!("Subscription not found: {}", original_transaction_id)
         ))?;
         
+        let auto_renew = match payload.sub_type {
+            Some(AppleNotificationSubtype::AutoRenewEnabled) => true,
+            Some(AppleNotificationSubtype::AutoRenewDisabled) => false,
+            _ => auto_renew_status == 1,
+        };
+        
         // Update auto-renew status
-        subscription.update_auto_renew_status(auto_renew_status == 1, pool).await?;
+        subscription.update_auto_renew_status(auto_renew, &mut **tx).await?;
+        
+        // Turning auto-renew off is the first sign of voluntary churn
+        let event_type = if auto_renew {
+            SubscriptionEventType::AutoRenewEnabled
+        } else {
+            SubscriptionEventType::AutoRenewDisabled
+        };
+        SubscriptionEvent::new(&subscription, event_type, Utc::now())
+            .create(&mut **tx)
+            .await?;
     }
     
     Ok(())
 }
 
-// Process renewal change
+// Process renewal change. The subtype says when the change takes effect:
+// UPGRADE (and crossgrades with the same duration) right away, DOWNGRADE at
+// the next renewal, and no subtype means the user went back to their current
+// product, cancelling a pending downgrade.
 async fn process_renewal_change(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
-    // Similar to process_renewal_status_change
-    // but might handle product changes
+    if let Some(signed_renewal_info) = &payload.data.signed_renewal_info {
+        // Mock the decoded data
+        let original_transaction_id = "mock_original_transaction_id";
+        let auto_renew_product_id = "mock_product_id";
+        let transaction_id = "mock_transaction_id"; // From signedTransactionInfo for upgrades
+        let now = Utc::now();
+        
+        // Find the subscription by original transaction ID
+        let mut subscription = Subscription::find_by_store_transaction(
+            "apple", 
+            original_transaction_id, 
+            &mut **tx
+        )
+        .await?
+        .ok_or_else(|| AppError::NotFound(
+            format!("Subscription not found: {}", original_transaction_id)
+        ))?;
+        
+        let auto_renew_product = Product::find_by_store_product_id("apple", auto_renew_product_id, &mut **tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", auto_renew_product_id)))?;
+        
+        if auto_renew_product.id == subscription.product_id {
+            // Back on the current product, so drop any pending change
+            if let Some(pending_product_id) = subscription.pending_product_id.take() {
+                subscription.update(&mut **tx).await?;
+                
+                SubscriptionEvent::product_change(
+                    &subscription,
+                    SubscriptionEventType::ProductChangeCanceled,
+                    &subscription.product_id,
+                    &pending_product_id,
+                    None,
+                    now,
+                )
+                .create(&mut **tx)
+                .await?;
+            }
+        } else if payload.sub_type == Some(AppleNotificationSubtype::Upgrade) {
+            // The upgrade starts a new period for the new product right away
+            let mut upgraded = Subscription::new(
+                subscription.user_id.clone(),
+                auto_renew_product.id.clone(),
+                subscription.original_transaction_id.clone(),
+                Some(transaction_id.to_string()),
+                "apple".to_string(),
+                now,
+                Some(now + chrono::Duration::days(30)),
+                SubscriptionStatus::Active,
+                Some(true),
+                None,  // Price paid (not available in this mock)
+                None,  // Currency (not available in this mock)
+                false, // Is trial
+                false, // Is intro offer
+            );
+            upgraded.country_code = subscription.country_code.clone();
+            
+            super::replace_subscription(&mut subscription, &mut upgraded, &auto_renew_product, now, tx).await?;
+            
+            // Record the purchase of the new product in the transaction ledger
+            Transaction::for_subscription(&upgraded, TransactionType::InitialPurchase, now)
+                .create(tx)
+                .await?;
+        } else {
+            // Downgrades keep the current product until the next renewal
+            let current_product = Product::find_by_id(&subscription.product_id, &mut **tx).await?;
+            let change_type = current_product.and_then(|product| product.change_type(&auto_renew_product));
+            
+            subscription.pending_product_id = Some(auto_renew_product.id.clone());
+            subscription.update(&mut **tx).await?;
+            
+            SubscriptionEvent::product_change(
+                &subscription,
+                SubscriptionEventType::ProductChangeScheduled,
+                &subscription.product_id,
+                &auto_renew_product.id,
+                change_type,
+                subscription.expires_date.unwrap_or(now),
+            )
+            .create(&mut **tx)
+            .await?;
+        }
+    }
+    
     Ok(())
 }
 
-// Process renewal failure
+// Process renewal failure. With Billing Grace Period enabled the user keeps
+// access until the grace period ends; otherwise access is suspended while
+// Apple retries the payment.
 async fn process_renewal_failure(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
-    if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
+    if let Some(signed_renewal_info) = &payload.data.signed_renewal_info {
+        #[derive(Default, Deserialize)]
+        struct RetryClaims {
+            #[serde(rename = "gracePeriodExpiresDate")]
+            grace_period_expires_date: Option<i64>, // Only set during a grace period
+            #[serde(rename = "isInBillingRetryPeriod")]
+            is_in_billing_retry_period: Option<bool>,
+        }
+        
+        let claims = decode_jws_claims::<RetryClaims>(signed_renewal_info).unwrap_or_default();
+        let now = Utc::now();
+        
         // Mock the decoded data
         let original_transaction_id = "mock_original_transaction_id";
-        let grace_period_expires_date = Some(Utc::now() + chrono::Duration::days(16)); // 16 days grace period
+        
+        let grace_period_expires_date = claims
+            .grace_period_expires_date
+            .and_then(DateTime::<Utc>::from_timestamp_millis)
+            .filter(|grace_period_expires_date| *grace_period_expires_date > now);
         
         // Find the subscription by original transaction ID
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
             format!("Subscription not found: {}", original_transaction_id)
         ))?;
         
-        // Update subscription status to grace period
-        subscription.status = SubscriptionStatus::GracePeriod.to_string();
+        subscription.detect_billing_issue(now);
         subscription.renewal_grace_period_expires_date = grace_period_expires_date;
-        subscription.update(pool).await?;
+        
+        // The GRACE_PERIOD subtype or a grace end date means the billing
+        // grace period is on and the customer keeps access while Apple
+        // retries the payment. Without one, Apple retries with access
+        // suspended, unless it says it has stopped retrying.
+        let in_grace_period = payload.sub_type == Some(AppleNotificationSubtype::GracePeriod)
+            || grace_period_expires_date.is_some();
+        let status = match (in_grace_period, claims.is_in_billing_retry_period) {
+            (true, _) => SubscriptionStatus::GracePeriod,
+            (false, Some(false)) => SubscriptionStatus::Expired,
+            (false, _) => SubscriptionStatus::BillingRetry,
+        };
+        if matches!(status, SubscriptionStatus::Expired) {
+            subscription.auto_renew_status = Some(false);
+            subscription.expiration_reason = Some(ExpirationReason::BillingError.to_string());
+        }
+        subscription.status = status.to_string();
+        subscription.update(&mut **tx).await?;
+        
+        // Keep entitlements until the grace period ends. Otherwise they're
+        // suspended until the payment is recovered, or ended if Apple has
+        // stopped retrying.
+        let entitlements_expire_at = match (in_grace_period, grace_period_expires_date) {
+            (true, Some(grace_period_expires_date)) => Some(grace_period_expires_date),
+            (true, None) => None, // Keep the current expiry until Apple says when grace ends
+            (false, _) => Some(now),
+        };
+        if let Some(expires_at) = entitlements_expire_at {
+            UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(expires_at), &mut **tx).await?;
+        }
+        
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
 }
 
-// Process grace period expiration
+// Process grace period expiration. Apple keeps retrying the payment, so the
+// subscription moves to billing retry with its entitlements suspended.
 async fn process_grace_period_expiration(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // Mock the decoded data
@@ -477,30 +1009,22 @@ async fn process_grace_period_expiration(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
             format!("Subscription not found: {}", original_transaction_id)
         ))?;
         
-        // Update subscription status to expired
-        subscription.update_status(SubscriptionStatus::Expired, pool).await?;
+        // Update subscription status to billing retry
+        subscription.status = SubscriptionStatus::BillingRetry.to_string();
+        subscription.detect_billing_issue(Utc::now());
+        subscription.update(&mut **tx).await?;
         
-        // Expire user entitlements
-        let user_entitlements = UserEntitlement::list_active_for_user(
-            &subscription.user_id, 
-            Utc::now(), 
-            pool
-        ).await?;
+        // Suspend user entitlements
+        UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(Utc::now()), &mut **tx).await?;
         
-        for mut entitlement in user_entitlements {
-            if let Some(sub_id) = &entitlement.subscription_id {
-                if sub_id == &subscription.id {
-                    entitlement.update_expiry(Some(Utc::now()), pool).await?;
-                }
-            }
-        }
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
@@ -509,7 +1033,7 @@ async fn process_grace_period_expiration(
 // Process offer redemption
 async fn process_offer_redemption(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     // Handle offer redemption
     // Similar to process_new_subscription but with offer details
@@ -519,17 +1043,42 @@ async fn process_offer_redemption(
 // Process price increase
 async fn process_price_increase(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
-    // Handle price increase notification
-    // Typically just store the information for tracking
+    if payload.data.signed_renewal_info.is_some() {
+        // Mock the decoded data
+        let original_transaction_id = "mock_original_transaction_id";
+        
+        let subscription = Subscription::find_by_store_transaction(
+            "apple", 
+            original_transaction_id, 
+            &mut **tx
+        )
+        .await?
+        .ok_or_else(|| AppError::NotFound(
+            format!("Subscription not found: {}", original_transaction_id)
+        ))?;
+        
+        // PENDING means the customer hasn't responded to a price increase
+        // that needs their consent yet. ACCEPTED means they agreed, or the
+        // increase didn't need consent.
+        let event_type = match payload.sub_type {
+            Some(AppleNotificationSubtype::Accepted) => SubscriptionEventType::PriceChangeConfirmed,
+            _ => SubscriptionEventType::PriceChangePending,
+        };
+        
+        SubscriptionEvent::new(&subscription, event_type, subscription.expires_date.unwrap_or_else(Utc::now))
+            .create(&mut **tx)
+            .await?;
+    }
+    
     Ok(())
 }
 
 // Process refund
 async fn process_refund(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // Mock the decoded data
@@ -540,7 +1089,7 @@ async fn process_refund(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
@@ -548,31 +1097,160 @@ async fn process_refund(
         ))?;
         
         // Update subscription status to refunded
-        subscription.update_status(SubscriptionStatus::Refunded, pool).await?;
+        subscription.update_status(SubscriptionStatus::Refunded, &mut **tx).await?;
+        
+        // Record the refund in the transaction ledger
+        Transaction::for_subscription(&subscription, TransactionType::Refund, Utc::now())
+            .create(tx)
+            .await?;
+        
+        // Take back any virtual currency the purchase credited
+        WalletEntry::reverse_purchase(&subscription, tx).await?;
+        
+        // Give back the unused part of a non-renewing period to later purchases
+        subscription.roll_back_period(Utc::now(), tx).await?;
         
         // Revoke user entitlements
         let user_entitlements = UserEntitlement::list_active_for_user(
             &subscription.user_id, 
             Utc::now(), 
-            pool
+            &mut **tx
         ).await?;
         
         for mut entitlement in user_entitlements {
             if let Some(sub_id) = &entitlement.subscription_id {
                 if sub_id == &subscription.id {
-                    entitlement.revoke(pool).await?;
+                    entitlement.revoke(&mut **tx).await?;
                 }
             }
         }
+        
+        // A refund ends family members' access too
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
 }
 
+// Process a refund Apple reversed. The purchase is reinstated: its
+// entitlements and any currency it credited come back, and the revenue is
+// recorded again.
+async fn process_refund_reversal(
+    payload: &AppleNotificationPayload,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    if payload.data.signed_transaction_info.is_some() {
+        // Mock the decoded data
+        let original_transaction_id = "mock_original_transaction_id";
+        let now = Utc::now();
+
+        let mut subscription = Subscription::find_by_store_transaction(
+            "apple",
+            original_transaction_id,
+            &mut **tx
+        )
+        .await?
+        .ok_or_else(|| AppError::NotFound(
+            format!("Subscription not found: {}", original_transaction_id)
+        ))?;
+
+        // Apple retries notifications, so only reverse a refund once
+        if subscription.status != SubscriptionStatus::Refunded.to_string() {
+            return Ok(());
+        }
+
+        // Non-renewing periods were cut short by the refund
+        let product = Product::find_by_id(&subscription.product_id, &mut **tx).await?;
+        match product.and_then(|product| product.duration_days) {
+            Some(duration_days) if subscription.period_start_date.is_some() => {
+                subscription
+                    .restore_period(chrono::Duration::days(duration_days as i64), now, tx)
+                    .await?;
+            }
+            _ => {
+                UserEntitlement::update_expiry_for_subscription(&subscription.id, subscription.expires_date, &mut **tx)
+                    .await?;
+            }
+        }
+
+        let status = if subscription.expires_date.is_none_or(|expires_date| expires_date > now) {
+            SubscriptionStatus::Active
+        } else {
+            SubscriptionStatus::Expired
+        };
+        subscription.update_status(status, &mut **tx).await?;
+
+        Transaction::for_subscription(&subscription, TransactionType::RefundReversal, now)
+            .create(tx)
+            .await?;
+
+        WalletEntry::restore_purchase(&subscription, tx).await?;
+
+        subscription.sync_family_shares(tx).await?;
+    }
+
+    Ok(())
+}
+
+// Process a one-time charge for a consumable, non-consumable or
+// non-renewing product
+async fn process_one_time_charge(
+    payload: &AppleNotificationPayload,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    if payload.data.signed_transaction_info.is_some() {
+        // Mock the decoded data
+        let transaction_id = "mock_transaction_id";
+        let original_transaction_id = "mock_transaction_id"; // Same as the transaction for one-time purchases
+        let apple_product_id = "mock_product_id";
+        let purchase_date = Utc::now();
+
+        // Apple retries notifications, so skip purchases we've already recorded
+        if Subscription::find_by_store_transaction("apple", transaction_id, &mut **tx).await?.is_some() {
+            return Ok(());
+        }
+
+        let product = Product::find_by_store_product_id("apple", apple_product_id, &mut **tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", apple_product_id)))?;
+
+        // Auto-renewable subscriptions arrive as SUBSCRIBED instead
+        if product.type_ == ProductType::Subscription.to_string() {
+            tracing::warn!("One-time charge for a subscription product: {}", apple_product_id);
+            return Ok(());
+        }
+
+        let Some(user_id) = find_user(
+            payload,
+            apple_product_id,
+            transaction_id,
+            original_transaction_id,
+            tx,
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+
+        super::record_one_time_purchase(
+            &user_id,
+            &product,
+            "apple",
+            original_transaction_id,
+            transaction_id,
+            purchase_date,
+            tx,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
 // Process refund declined
 async fn process_refund_declined(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     // Handle refund declined notification
     // Typically just store the information for tracking
@@ -582,7 +1260,7 @@ async fn process_refund_declined(
 // Process renewal extension
 async fn process_renewal_extension(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // Mock the decoded data
@@ -593,7 +1271,7 @@ async fn process_renewal_extension(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
@@ -601,31 +1279,180 @@ async fn process_renewal_extension(
         ))?;
         
         // Update expiry date
-        subscription.update_expiry(new_expires_date.unwrap(), pool).await?;
+        subscription.update_expiry(new_expires_date.unwrap(), &mut **tx).await?;
         
         // Update user entitlements
         let user_entitlements = UserEntitlement::list_active_for_user(
             &subscription.user_id, 
             Utc::now(), 
-            pool
+            &mut **tx
         ).await?;
         
         for mut entitlement in user_entitlements {
             if let Some(sub_id) = &entitlement.subscription_id {
                 if sub_id == &subscription.id {
-                    entitlement.update_expiry(new_expires_date, pool).await?;
+                    entitlement.update_expiry(new_expires_date, &mut **tx).await?;
                 }
             }
         }
+        
+        // Extend family members' access as well
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
 }
 
+// Process a consumption request. Apple asks for this when a customer requests
+// a refund, and uses what we send back to decide it. We only answer for apps
+// that opted in, and only for customers who consented to sharing the data.
+async fn process_consumption_request(
+    payload: &AppleNotificationPayload,
+    pool: &SqlitePool,
+) -> Result<()> {
+    let Some(bundle_id) = &payload.data.bundle_id else {
+        tracing::info!("Consumption request without a bundle ID: {}", payload.notification_uuid);
+        return Ok(());
+    };
+
+    let Some(app) = App::find_by_apple_bundle_id(bundle_id, pool).await? else {
+        tracing::info!("Consumption request for unknown app: {}", bundle_id);
+        return Ok(());
+    };
+
+    if payload.data.signed_transaction_info.is_some() {
+        // Mock the decoded data
+        let transaction_id = "mock_transaction_id";
+        let original_transaction_id = "mock_original_transaction_id";
+
+        let requested_at = DateTime::from_timestamp_millis(payload.signed_date).unwrap_or_else(Utc::now);
+        let mut request = ConsumptionRequest::new(
+            app.id.clone(),
+            payload.notification_uuid.clone(),
+            payload.data.environment.clone().unwrap_or_else(|| "Production".to_string()),
+            transaction_id.to_string(),
+            requested_at + chrono::Duration::hours(CONSUMPTION_RESPONSE_WINDOW_HOURS),
+        );
+
+        let subscription = Subscription::find_by_store_transaction("apple", original_transaction_id, pool).await?;
+        let user = match &subscription {
+            Some(subscription) => User::find_by_id(&subscription.user_id, pool).await?,
+            None => None,
+        };
+
+        match (subscription, user) {
+            _ if !app.apple_consumption_opt_in => {
+                request.skip("App has not opted in to sending consumption information".to_string());
+            }
+            (Some(subscription), Some(user)) => {
+                request.user_id = Some(user.id.clone());
+                request.subscription_id = Some(subscription.id.clone());
+
+                if user.consumption_consent {
+                    let information = consumption_information(&subscription, &user, pool).await?;
+                    request.request_json = Some(
+                        serde_json::to_string(&information)
+                            .map_err(|e| AppError::InternalServerError(e.to_string()))?,
+                    );
+                } else {
+                    request.skip("Customer has not consented to sharing consumption data".to_string());
+                }
+            }
+            _ => {
+                request.skip(format!("Purchase not found: {}", original_transaction_id));
+            }
+        }
+
+        // Apple retries notifications, but one answer per request is enough
+        if !request.create(pool).await? {
+            return Ok(());
+        }
+
+        if request.status == ConsumptionRequestStatus::Pending.to_string() {
+            app_store::send_consumption_request(&mut request, &app).await;
+        }
+
+        request.update(pool).await?;
+
+        tracing::info!(
+            "Consumption request {} for transaction {}: {}",
+            request.notification_uuid,
+            request.transaction_id,
+            request.status
+        );
+    }
+
+    Ok(())
+}
+
+// Gather what Apple asks about the customer and the purchase they want refunded
+async fn consumption_information(
+    subscription: &Subscription,
+    user: &User,
+    pool: &SqlitePool,
+) -> Result<ConsumptionInformation> {
+    let now = Utc::now();
+    let (purchased_usd, refunded_usd) = Transaction::lifetime_usd_for_user(&user.id, pool).await?;
+
+    Ok(ConsumptionInformation {
+        account_tenure: app_store::account_tenure(user.created_at, now),
+        // Apple only accepts the token as a UUID, so other user IDs are left out
+        app_account_token: Uuid::parse_str(&user.app_user_id)
+            .map(|token| token.to_string())
+            .unwrap_or_default(),
+        consumption_status: consumption_status(subscription, now, pool).await?,
+        customer_consented: user.consumption_consent,
+        delivery_status: app_store::DELIVERED,
+        lifetime_dollars_purchased: app_store::lifetime_dollars(purchased_usd),
+        lifetime_dollars_refunded: app_store::lifetime_dollars(refunded_usd),
+        platform: app_store::PLATFORM_APPLE,
+        play_time: app_store::play_time(user.play_time_minutes),
+        sample_content_provided: subscription.is_trial,
+        user_status: app_store::USER_ACTIVE,
+    })
+}
+
+// How much of the purchase has been used. For consumables that's how much of
+// the currency it credited has been spent, and for time-based access how much
+// of the period has passed.
+async fn consumption_status(subscription: &Subscription, now: DateTime<Utc>, pool: &SqlitePool) -> Result<i32> {
+    let Some(product) = Product::find_by_id(&subscription.product_id, pool).await? else {
+        return Ok(app_store::CONSUMPTION_UNDECLARED);
+    };
+
+    if product.type_ == ProductType::Consumable.to_string() {
+        let Some(credit) = WalletEntry::find_purchase(&subscription.id, pool).await? else {
+            return Ok(app_store::CONSUMPTION_UNDECLARED);
+        };
+        let balance = WalletEntry::balance(&credit.user_id, &credit.currency, pool).await?;
+
+        return Ok(match balance {
+            b if b >= credit.amount => app_store::NOT_CONSUMED,
+            b if b <= 0 => app_store::FULLY_CONSUMED,
+            _ => app_store::PARTIALLY_CONSUMED,
+        });
+    }
+
+    if product.type_ == ProductType::Subscription.to_string()
+        || product.type_ == ProductType::NonRenewing.to_string()
+    {
+        let start = subscription.period_start_date.unwrap_or(subscription.purchase_date);
+
+        return Ok(match subscription.expires_date {
+            None => app_store::CONSUMPTION_UNDECLARED,
+            Some(_) if now <= start => app_store::NOT_CONSUMED,
+            Some(expires_date) if now >= expires_date => app_store::FULLY_CONSUMED,
+            Some(_) => app_store::PARTIALLY_CONSUMED,
+        });
+    }
+
+    Ok(app_store::CONSUMPTION_UNDECLARED)
+}
+
 // Process subscription revocation
 async fn process_subscription_revocation(
     payload: &AppleNotificationPayload,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     if let Some(signed_transaction_info) = &payload.data.signed_transaction_info {
         // Mock the decoded data
@@ -635,7 +1462,7 @@ async fn process_subscription_revocation(
         let mut subscription = Subscription::find_by_store_transaction(
             "apple", 
             original_transaction_id, 
-            pool
+            &mut **tx
         )
         .await?
         .ok_or_else(|| AppError::NotFound(
@@ -643,22 +1470,24 @@ async fn process_subscription_revocation(
         ))?;
         
         // Cancel the subscription
-        subscription.cancel(Utc::now(), pool).await?;
+        subscription.cancel(Utc::now(), &mut **tx).await?;
         
         // Expire user entitlements
         let user_entitlements = UserEntitlement::list_active_for_user(
             &subscription.user_id, 
             Utc::now(), 
-            pool
+            &mut **tx
         ).await?;
         
         for mut entitlement in user_entitlements {
             if let Some(sub_id) = &entitlement.subscription_id {
                 if sub_id == &subscription.id {
-                    entitlement.update_expiry(Some(Utc::now()), pool).await?;
+                    entitlement.update_expiry(Some(Utc::now()), &mut **tx).await?;
                 }
             }
         }
+        
+        subscription.sync_family_shares(tx).await?;
     }
     
     Ok(())
@@ -7,14 +7,15 @@ use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqlitePool;
 
 use crate::db::models::{
-    User, Product, Subscription, SubscriptionStatus, UserEntitlement,
+    Product, Subscription, SubscriptionEvent, SubscriptionEventType,
+    SubscriptionStatus, Transaction, TransactionType, UnattributedPurchase, UserEntitlement, WalletEntry,
 };
 use crate::error::{AppError, Result};
 
 // Google Play Real-time Developer Notifications (RTDN)
 // https://developer.android.com/google/play/billing/rtdn
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GoogleNotificationPayload {
     version: String,
     #[serde(rename = "packageName")]
@@ -29,7 +30,7 @@ pub struct GoogleNotificationPayload {
     test_notification: Option<GoogleTestNotification>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GoogleSubscriptionNotification {
     version: String,
     #[serde(rename = "notificationType")]
@@ -40,7 +41,7 @@ pub struct GoogleSubscriptionNotification {
     subscription_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GoogleOneTimeProductNotification {
     version: String,
     #[serde(rename = "notificationType")]
@@ -51,7 +52,7 @@ pub struct GoogleOneTimeProductNotification {
     sku: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GoogleTestNotification {
     version: String,
 }
@@ -89,6 +90,8 @@ pub struct GoogleSubscriptionPurchase {
     acknowledgement_state: Option<i32>,
     #[serde(rename = "obfuscatedExternalAccountId")]
     obfuscated_external_account_id: Option<String>,
+    #[serde(rename = "obfuscatedExternalProfileId")]
+    obfuscated_external_profile_id: Option<String>,
     #[serde(rename = "linkedPurchaseToken")]
     linked_purchase_token: Option<String>,
 }
@@ -116,25 +119,7 @@ pub async fn handle_google_webhook(
         ));
     }
 
-    // Process subscription notifications
-    if let Some(subscription_notification) = &payload.subscription_notification {
-        process_subscription_notification(
-            &payload.package_name,
-            subscription_notification,
-            &pool,
-        )
-        .await?;
-    }
-
-    // Process one-time product notifications
-    if let Some(one_time_notification) = &payload.one_time_product_notification {
-        process_one_time_notification(
-            &payload.package_name,
-            one_time_notification,
-            &pool,
-        )
-        .await?;
-    }
+    process_notification(&payload, &pool).await?;
 
     // Return success response
     Ok((
@@ -145,10 +130,73 @@ pub async fn handle_google_webhook(
     ))
 }
 
+async fn process_notification(payload: &GoogleNotificationPayload, pool: &SqlitePool) -> Result<()> {
+    // Save all of a notification's changes or none of them, so a failed
+    // notification can be retried from scratch
+    let mut tx = pool.begin().await?;
+
+    // Process subscription notifications
+    if let Some(subscription_notification) = &payload.subscription_notification {
+        process_subscription_notification(payload, subscription_notification, &mut tx).await?;
+    }
+
+    // Process one-time product notifications
+    if let Some(one_time_notification) = &payload.one_time_product_notification {
+        process_one_time_notification(payload, one_time_notification, &mut tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// Process a notification stored with an unattributed purchase
+pub(super) async fn replay_notification(notification_json: &str, pool: &SqlitePool) -> Result<()> {
+    let payload: GoogleNotificationPayload = serde_json::from_str(notification_json)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid stored notification: {}", e)))?;
+
+    process_notification(&payload, pool).await
+}
+
+// Find the user a purchase belongs to from the obfuscated account or profile
+// ID the app set on it. Purchases we can't match are parked until the user
+// is known, and None is returned.
+async fn find_user(
+    payload: &GoogleNotificationPayload,
+    google_product_id: &str,
+    purchase_token: &str,
+    account_tokens: &[Option<String>],
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<Option<String>> {
+    let account_tokens: Vec<&str> = account_tokens.iter().flatten().map(String::as_str).collect();
+    if let Some(user_id) = super::resolve_user("google", purchase_token, &account_tokens, tx).await? {
+        return Ok(Some(user_id));
+    }
+
+    let notification_json = serde_json::to_string(payload)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to store notification: {}", e)))?;
+
+    // Purchase tokens identify Google purchases, like transaction IDs do for Apple
+    super::park_purchase(
+        UnattributedPurchase::new(
+            "google".to_string(),
+            account_tokens.first().map(|token| token.to_string()),
+            google_product_id.to_string(),
+            purchase_token.to_string(),
+            Some(purchase_token.to_string()),
+            notification_json,
+        ),
+        tx,
+    )
+    .await?;
+
+    Ok(None)
+}
+
 async fn process_subscription_notification(
-    package_name: &str,
+    payload: &GoogleNotificationPayload,
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     // Google subscription notification types
     // 1: SUBSCRIPTION_RECOVERED - A subscription was recovered from account hold.
@@ -164,20 +212,40 @@ async fn process_subscription_notification(
     // 11: SUBSCRIPTION_PAUSE_SCHEDULE_CHANGED - A subscription pause schedule was changed.
     // 12: SUBSCRIPTION_REVOKED - A subscription was revoked.
     // 13: SUBSCRIPTION_EXPIRED - A subscription expired.
+    // 17: SUBSCRIPTION_ITEMS_CHANGED - A subscription's items changed.
+    // 19: SUBSCRIPTION_PRICE_CHANGE_UPDATED - A subscription price change was updated.
+    // 20: SUBSCRIPTION_PENDING_PURCHASE_CANCELED - A pending purchase was canceled.
 
     // In a real implementation, query the Google Play Developer API to get purchase details
     // For this example, we'll use mock data based on notification type
 
     match notification.notification_type {
-        1 => process_subscription_recovered(notification, pool).await?,
-        2 => process_subscription_renewed(notification, pool).await?,
-        3 => process_subscription_canceled(notification, pool).await?,
-        4 => process_subscription_purchased(notification, pool).await?,
-        5 => process_subscription_on_hold(notification, pool).await?,
-        6 => process_subscription_in_grace_period(notification, pool).await?,
-        7 => process_subscription_restarted(notification, pool).await?,
-        12 => process_subscription_revoked(notification, pool).await?,
-        13 => process_subscription_expired(notification, pool).await?,
+        1 => process_subscription_recovered(notification, tx).await?,
+        2 => process_subscription_renewed(notification, tx).await?,
+        3 => process_subscription_canceled(notification, tx).await?,
+        4 => process_subscription_purchased(payload, notification, tx).await?,
+        5 => process_subscription_on_hold(notification, tx).await?,
+        6 => process_subscription_in_grace_period(notification, tx).await?,
+        7 => process_subscription_restarted(notification, tx).await?,
+        8 => {
+            process_subscription_price_change(notification, SubscriptionEventType::PriceChangeConfirmed, tx)
+                .await?
+        }
+        9 => process_subscription_deferred(notification, tx).await?,
+        10 => process_subscription_paused(notification, tx).await?,
+        11 => process_subscription_pause_schedule_changed(notification, tx).await?,
+        12 => process_subscription_revoked(notification, tx).await?,
+        13 => process_subscription_expired(notification, tx).await?,
+        17 => process_subscription_items_changed(notification, tx).await?,
+        19 => {
+            process_subscription_price_change(notification, SubscriptionEventType::PriceChangeUpdated, tx)
+                .await?
+        }
+        20 => {
+            // A purchase waiting on payment, e.g. cash, was cancelled before it
+            // completed. Nothing is granted until then, so there's nothing to undo.
+            tracing::info!("Pending purchase canceled: {}", notification.purchase_token);
+        }
         _ => {
             // Other notification types can be handled as needed
             // For now, we'll just log them
@@ -188,17 +256,17 @@ async fn process_subscription_notification(
 }
 
 async fn process_one_time_notification(
-    package_name: &str,
+    payload: &GoogleNotificationPayload,
     notification: &GoogleOneTimeProductNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     // Google one-time product notification types
     // 1: PURCHASED - A one-time product was purchased.
     // 2: CANCELED - A one-time product was canceled.
 
     match notification.notification_type {
-        1 => process_one_time_purchased(notification, pool).await?,
-        2 => process_one_time_canceled(notification, pool).await?,
+        1 => process_one_time_purchased(payload, notification, tx).await?,
+        2 => process_one_time_canceled(notification, tx).await?,
         _ => {
             // Unknown notification type
             return Err(AppError::BadRequest(format!(
@@ -211,12 +279,18 @@ async fn process_one_time_notification(
     Ok(())
 }
 
+// Google reports prices in micro-units of the currency
+fn price_from_micros(micros: i64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
 // Implementing each notification type handling function
 // For brevity, we'll just implement a few key ones with mock data
 
 async fn process_subscription_purchased(
+    payload: &GoogleNotificationPayload,
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     // Mock purchase details that would come from Google API
     let purchase_token = &notification.purchase_token;
@@ -225,32 +299,39 @@ async fn process_subscription_purchased(
     let purchase_time = Utc::now();
     let expiry_time = Utc::now() + chrono::Duration::days(30); // 30 days subscription
     let auto_renewing = true;
+    let country_code: Option<String> = None; // This would come from the Google API (countryCode)
+    let price_amount_micros: Option<i64> = None; // This would come from the Google API (priceAmountMicros)
+    let price_currency_code: Option<String> = None; // This would come from the Google API (priceCurrencyCode)
+    let linked_purchase_token: Option<String> = None; // This would come from the Google API (linkedPurchaseToken)
+    let obfuscated_external_account_id: Option<String> = None; // This would come from the Google API (obfuscatedExternalAccountId)
+    let obfuscated_external_profile_id: Option<String> = None; // This would come from the Google API (obfuscatedExternalProfileId)
+    
+    // An upgrade or downgrade replaces the subscription bought with the linked token
+    let replaced_subscription = match &linked_purchase_token {
+        Some(linked_purchase_token) => {
+            Subscription::find_by_store_transaction("google", linked_purchase_token, &mut **tx).await?
+        }
+        None => None,
+    };
     
-    // In a real app, you'd also have a way to map the purchase to a user
-    // For this example, we'll create a dummy user if needed
-    let user_id = {
-        // Check if we have a user associated with this purchase token
-        // In a real app, you'd have a better way to do this
-        let user = User::find_by_app_user_id(purchase_token, pool).await?;
-        
-        match user {
-            Some(user) => user.id,
-            None => {
-                // Create a new user
-                let new_user = User::new(purchase_token.to_string(), None);
-                new_user.create(pool).await?;
-                new_user.id
-            }
+    // A replacement keeps the user of the subscription it replaces
+    let user_id = if let Some(replaced_subscription) = &replaced_subscription {
+        replaced_subscription.user_id.clone()
+    } else {
+        let account_tokens = [obfuscated_external_account_id, obfuscated_external_profile_id];
+        match find_user(payload, google_product_id, purchase_token, &account_tokens, tx).await? {
+            Some(user_id) => user_id,
+            None => return Ok(()),
         }
     };
     
     // Find the product by Google product ID
-    let product = Product::find_by_store_product_id("google", google_product_id, pool)
+    let product = Product::find_by_store_product_id("google", google_product_id, &mut **tx)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", google_product_id)))?;
     
     // Create a new subscription
-    let subscription = Subscription::new(
+    let mut subscription = Subscription::new(
         user_id.clone(),
         product.id.clone(),
         Some(purchase_token.to_string()), // Use purchase token as original transaction ID
@@ -260,78 +341,86 @@ async fn process_subscription_purchased(
         Some(expiry_time),
         SubscriptionStatus::Active,
         Some(auto_renewing),
-        None, // Price paid (not available in this mock)
-        None, // Currency (not available in this mock)
+        price_amount_micros.map(price_from_micros), // Price paid in the local currency
+        price_currency_code,
         false, // Is trial
         false, // Is intro offer
     );
+    subscription.country_code = country_code;
     
-    subscription.create(pool).await?;
-    
-    // Get the entitlements for this product
-    let entitlement_ids = product.get_entitlements(pool).await?;
-    
-    // Grant entitlements to the user
-    for entitlement_id in entitlement_ids {
-        let user_entitlement = UserEntitlement::new(
-            user_id.clone(),
-            entitlement_id,
-            Some(subscription.id.clone()),
-            purchase_time,
-            Some(expiry_time),
-        );
+    if let Some(mut replaced_subscription) = replaced_subscription {
+        super::replace_subscription(&mut replaced_subscription, &mut subscription, &product, purchase_time, tx)
+            .await?;
+    } else {
+        subscription.create(&mut **tx).await?;
+        
+        // Get the entitlements for this product
+        let entitlement_ids = product.get_entitlements(&mut **tx).await?;
         
-        user_entitlement.create(pool).await?;
+        // Grant entitlements to the user
+        for entitlement_id in entitlement_ids {
+            let user_entitlement = UserEntitlement::new(
+                user_id.clone(),
+                entitlement_id,
+                Some(subscription.id.clone()),
+                purchase_time,
+                Some(expiry_time),
+            );
+            
+            user_entitlement.create(&mut **tx).await?;
+        }
     }
     
+    // Record the purchase in the transaction ledger
+    Transaction::for_subscription(&subscription, TransactionType::InitialPurchase, purchase_time)
+        .create(tx)
+        .await?;
+    
     Ok(())
 }
 
 async fn process_subscription_renewed(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     // Mock purchase details that would come from Google API
     let purchase_token = &notification.purchase_token;
     let new_expiry_time = Utc::now() + chrono::Duration::days(30); // 30 more days
+    let order_id = "GPA.1234-5678-9012-34567..0"; // latestOrderId from the Google API, new for each renewal
     
     // Find the subscription by purchase token (which we used as original_transaction_id)
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
         format!("Subscription not found for token: {}", purchase_token)
     ))?;
     
-    // Update subscription details
+    // Update subscription details. A paused subscription resumes with a renewal.
+    subscription.store_transaction_id = Some(order_id.to_string());
     subscription.expires_date = Some(new_expiry_time);
     subscription.status = SubscriptionStatus::Active.to_string();
-    subscription.update(pool).await?;
+    subscription.auto_resume_date = None;
+    subscription.clear_billing_issue();
+    subscription.update(&mut **tx).await?;
     
-    // Update user entitlements
-    let user_entitlements = UserEntitlement::list_active_for_user(
-        &subscription.user_id, 
-        Utc::now(), 
-        pool
-    ).await?;
+    // Record the renewal in the transaction ledger
+    Transaction::for_subscription(&subscription, TransactionType::Renewal, Utc::now())
+        .create(tx)
+        .await?;
     
-    for mut entitlement in user_entitlements {
-        if let Some(sub_id) = &entitlement.subscription_id {
-            if sub_id == &subscription.id {
-                entitlement.update_expiry(Some(new_expiry_time), pool).await?;
-            }
-        }
-    }
+    // Update user entitlements, including ones suspended by a pause
+    UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(new_expiry_time), &mut **tx).await?;
     
     Ok(())
 }
 
 async fn process_subscription_canceled(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     
@@ -339,7 +428,7 @@ async fn process_subscription_canceled(
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -347,7 +436,7 @@ async fn process_subscription_canceled(
     ))?;
     
     // Update subscription status
-    subscription.cancel(Utc::now(), pool).await?;
+    subscription.cancel(Utc::now(), &mut **tx).await?;
     
     // Note: We don't immediately revoke entitlements when canceled
     // They should remain active until the expiration date
@@ -357,7 +446,7 @@ async fn process_subscription_canceled(
 
 async fn process_subscription_expired(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     
@@ -365,7 +454,7 @@ async fn process_subscription_expired(
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -373,19 +462,19 @@ async fn process_subscription_expired(
     ))?;
     
     // Update subscription status
-    subscription.update_status(SubscriptionStatus::Expired, pool).await?;
+    subscription.update_status(SubscriptionStatus::Expired, &mut **tx).await?;
     
     // Expire user entitlements
     let user_entitlements = UserEntitlement::list_active_for_user(
         &subscription.user_id, 
         Utc::now(), 
-        pool
+        &mut **tx
     ).await?;
     
     for mut entitlement in user_entitlements {
         if let Some(sub_id) = &entitlement.subscription_id {
             if sub_id == &subscription.id {
-                entitlement.update_expiry(Some(Utc::now()), pool).await?;
+                entitlement.update_expiry(Some(Utc::now()), &mut **tx).await?;
             }
         }
     }
@@ -395,16 +484,16 @@ async fn process_subscription_expired(
 
 async fn process_subscription_in_grace_period(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
-    let grace_period_end = Utc::now() + chrono::Duration::days(16); // 16 days grace period
+    let grace_period_end = Utc::now() + chrono::Duration::days(7); // This would come from the Google API (expiryTimeMillis, which is extended to the end of the grace period)
     
     // Find the subscription by purchase token
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -414,25 +503,28 @@ async fn process_subscription_in_grace_period(
     // Update subscription status to grace period
     subscription.status = SubscriptionStatus::GracePeriod.to_string();
     subscription.renewal_grace_period_expires_date = Some(grace_period_end);
-    subscription.update(pool).await?;
+    subscription.detect_billing_issue(Utc::now());
+    subscription.update(&mut **tx).await?;
     
-    // Note: Entitlements remain active during grace period
+    // Entitlements remain active until the grace period ends
+    UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(grace_period_end), &mut **tx).await?;
     
     Ok(())
 }
 
 async fn process_subscription_recovered(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     let new_expiry_time = Utc::now() + chrono::Duration::days(30); // 30 more days
+    let order_id = "GPA.1234-5678-9012-34567..0"; // latestOrderId from the Google API, new for each renewal
     
     // Find the subscription by purchase token
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -440,32 +532,26 @@ async fn process_subscription_recovered(
     ))?;
     
     // Update subscription details
+    subscription.store_transaction_id = Some(order_id.to_string());
     subscription.expires_date = Some(new_expiry_time);
     subscription.status = SubscriptionStatus::Active.to_string();
-    subscription.renewal_grace_period_expires_date = None; // Clear grace period
-    subscription.update(pool).await?;
+    subscription.clear_billing_issue();
+    subscription.update(&mut **tx).await?;
     
-    // Update user entitlements
-    let user_entitlements = UserEntitlement::list_active_for_user(
-        &subscription.user_id, 
-        Utc::now(), 
-        pool
-    ).await?;
+    // Recovering from account hold means the renewal payment went through
+    Transaction::for_subscription(&subscription, TransactionType::Renewal, Utc::now())
+        .create(tx)
+        .await?;
     
-    for mut entitlement in user_entitlements {
-        if let Some(sub_id) = &entitlement.subscription_id {
-            if sub_id == &subscription.id {
-                entitlement.update_expiry(Some(new_expiry_time), pool).await?;
-            }
-        }
-    }
+    // Restore user entitlements suspended during account hold
+    UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(new_expiry_time), &mut **tx).await?;
     
     Ok(())
 }
 
 async fn process_subscription_on_hold(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     
@@ -473,38 +559,28 @@ async fn process_subscription_on_hold(
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
         format!("Subscription not found for token: {}", purchase_token)
     ))?;
     
-    // Update subscription status to paused
-    subscription.status = SubscriptionStatus::Paused.to_string();
-    subscription.update(pool).await?;
+    // Update subscription status to on hold
+    subscription.status = SubscriptionStatus::OnHold.to_string();
+    subscription.renewal_grace_period_expires_date = None;
+    subscription.detect_billing_issue(Utc::now());
+    subscription.update(&mut **tx).await?;
     
-    // Expire user entitlements
-    let user_entitlements = UserEntitlement::list_active_for_user(
-        &subscription.user_id, 
-        Utc::now(), 
-        pool
-    ).await?;
-    
-    for mut entitlement in user_entitlements {
-        if let Some(sub_id) = &entitlement.subscription_id {
-            if sub_id == &subscription.id {
-                entitlement.update_expiry(Some(Utc::now()), pool).await?;
-            }
-        }
-    }
+    // Suspend user entitlements until the payment is recovered
+    UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(Utc::now()), &mut **tx).await?;
     
     Ok(())
 }
 
 async fn process_subscription_restarted(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     let new_expiry_time = Utc::now() + chrono::Duration::days(30); // 30 more days
@@ -513,7 +589,7 @@ async fn process_subscription_restarted(
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -523,15 +599,15 @@ async fn process_subscription_restarted(
     // Update subscription details
     subscription.expires_date = Some(new_expiry_time);
     subscription.status = SubscriptionStatus::Active.to_string();
-    subscription.update(pool).await?;
+    subscription.update(&mut **tx).await?;
     
     // Grant entitlements again
-    let product = Product::find_by_id(&subscription.product_id, pool).await?
+    let product = Product::find_by_id(&subscription.product_id, &mut **tx).await?
         .ok_or_else(|| AppError::NotFound(
             format!("Product not found: {}", subscription.product_id)
         ))?;
     
-    let entitlement_ids = product.get_entitlements(pool).await?;
+    let entitlement_ids = product.get_entitlements(&mut **tx).await?;
     
     for entitlement_id in entitlement_ids {
         let user_entitlement = UserEntitlement::new(
@@ -542,7 +618,7 @@ async fn process_subscription_restarted(
             Some(new_expiry_time),
         );
         
-        user_entitlement.create(pool).await?;
+        user_entitlement.create(&mut **tx).await?;
     }
     
     Ok(())
@@ -550,7 +626,7 @@ async fn process_subscription_restarted(
 
 async fn process_subscription_revoked(
     notification: &GoogleSubscriptionNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     
@@ -558,7 +634,7 @@ async fn process_subscription_revoked(
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -566,19 +642,24 @@ async fn process_subscription_revoked(
     ))?;
     
     // Update subscription status
-    subscription.update_status(SubscriptionStatus::Refunded, pool).await?;
+    subscription.update_status(SubscriptionStatus::Refunded, &mut **tx).await?;
+    
+    // Record the refund in the transaction ledger
+    Transaction::for_subscription(&subscription, TransactionType::Refund, Utc::now())
+        .create(tx)
+        .await?;
     
     // Revoke user entitlements immediately
     let user_entitlements = UserEntitlement::list_active_for_user(
         &subscription.user_id, 
         Utc::now(), 
-        pool
+        &mut **tx
     ).await?;
     
     for mut entitlement in user_entitlements {
         if let Some(sub_id) = &entitlement.subscription_id {
             if sub_id == &subscription.id {
-                entitlement.revoke(pool).await?;
+                entitlement.revoke(&mut **tx).await?;
             }
         }
     }
@@ -586,77 +667,250 @@ async fn process_subscription_revoked(
     Ok(())
 }
 
+// Price changes take effect at the next renewal. They're recorded as events
+// so the customer's history shows what they agreed to pay.
+async fn process_subscription_price_change(
+    notification: &GoogleSubscriptionNotification,
+    event_type: SubscriptionEventType,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    let purchase_token = &notification.purchase_token;
+    let new_price_micros: Option<i64> = None; // This would come from the Google API (the new price's priceMicros)
+    let new_price_currency_code: Option<String> = None; // This would come from the Google API (the new price's currency)
+    
+    // Find the subscription by purchase token
+    let subscription = Subscription::find_by_store_transaction(
+        "google", 
+        purchase_token, 
+        &mut **tx
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound(
+        format!("Subscription not found for token: {}", purchase_token)
+    ))?;
+    
+    let mut event = SubscriptionEvent::new(
+        &subscription,
+        event_type,
+        subscription.expires_date.unwrap_or_else(Utc::now),
+    );
+    event.price = new_price_micros.map(price_from_micros);
+    event.currency = new_price_currency_code;
+    event.create(&mut **tx).await?;
+    
+    Ok(())
+}
+
+// The developer deferred the next renewal, extending access without a payment
+async fn process_subscription_deferred(
+    notification: &GoogleSubscriptionNotification,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    let purchase_token = &notification.purchase_token;
+    let new_expiry_time = Utc::now() + chrono::Duration::days(30); // This would come from the Google API (expiryTimeMillis)
+    
+    // Find the subscription by purchase token
+    let mut subscription = Subscription::find_by_store_transaction(
+        "google", 
+        purchase_token, 
+        &mut **tx
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound(
+        format!("Subscription not found for token: {}", purchase_token)
+    ))?;
+    
+    subscription.update_expiry(new_expiry_time, &mut **tx).await?;
+    
+    // Extend user entitlements
+    UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(new_expiry_time), &mut **tx).await?;
+    
+    SubscriptionEvent::new(&subscription, SubscriptionEventType::RenewalDeferred, new_expiry_time)
+        .create(&mut **tx)
+        .await?;
+    
+    Ok(())
+}
+
+async fn process_subscription_paused(
+    notification: &GoogleSubscriptionNotification,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    let purchase_token = &notification.purchase_token;
+    let now = Utc::now();
+    let auto_resume_time = Some(now + chrono::Duration::days(30)); // This would come from the Google API (pausedStateContext.autoResumeTime)
+    
+    // Find the subscription by purchase token
+    let mut subscription = Subscription::find_by_store_transaction(
+        "google", 
+        purchase_token, 
+        &mut **tx
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound(
+        format!("Subscription not found for token: {}", purchase_token)
+    ))?;
+    
+    subscription.status = SubscriptionStatus::Paused.to_string();
+    subscription.auto_resume_date = auto_resume_time;
+    subscription.update(&mut **tx).await?;
+    
+    // Suspend user entitlements until the subscription resumes
+    UserEntitlement::update_expiry_for_subscription(&subscription.id, Some(now), &mut **tx).await?;
+    
+    SubscriptionEvent::new(&subscription, SubscriptionEventType::Paused, now)
+        .create(&mut **tx)
+        .await?;
+    
+    Ok(())
+}
+
+// The user scheduled, changed or cancelled a pause. Scheduled pauses start at
+// the next renewal, so access isn't affected yet.
+async fn process_subscription_pause_schedule_changed(
+    notification: &GoogleSubscriptionNotification,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    let purchase_token = &notification.purchase_token;
+    let auto_resume_time: Option<DateTime<Utc>> = None; // This would come from the Google API, and is missing if the pause was cancelled
+    
+    // Find the subscription by purchase token
+    let mut subscription = Subscription::find_by_store_transaction(
+        "google", 
+        purchase_token, 
+        &mut **tx
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound(
+        format!("Subscription not found for token: {}", purchase_token)
+    ))?;
+    
+    subscription.auto_resume_date = auto_resume_time;
+    subscription.update(&mut **tx).await?;
+    
+    SubscriptionEvent::new(
+        &subscription,
+        SubscriptionEventType::PauseScheduleChanged,
+        subscription.expires_date.unwrap_or_else(Utc::now),
+    )
+    .create(&mut **tx)
+    .await?;
+    
+    Ok(())
+}
+
+// The subscription's base plan or items changed without a new purchase token
+async fn process_subscription_items_changed(
+    notification: &GoogleSubscriptionNotification,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    let purchase_token = &notification.purchase_token;
+    let google_product_id = &notification.subscription_id;
+    let now = Utc::now();
+    
+    // Find the subscription by purchase token
+    let mut subscription = Subscription::find_by_store_transaction(
+        "google", 
+        purchase_token, 
+        &mut **tx
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound(
+        format!("Subscription not found for token: {}", purchase_token)
+    ))?;
+    
+    let product = Product::find_by_store_product_id("google", google_product_id, &mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", google_product_id)))?;
+    
+    if product.id == subscription.product_id {
+        return Ok(());
+    }
+    
+    let previous_product = Product::find_by_id(&subscription.product_id, &mut **tx).await?;
+    let change_type = previous_product.and_then(|previous_product| previous_product.change_type(&product));
+    
+    SubscriptionEvent::product_change(
+        &subscription,
+        SubscriptionEventType::ProductChange,
+        &subscription.product_id,
+        &product.id,
+        change_type,
+        now,
+    )
+    .create(&mut **tx)
+    .await?;
+    
+    subscription.product_id = product.id.clone();
+    subscription.update(&mut **tx).await?;
+    
+    // Swap the previous product's entitlements for the new product's
+    let user_entitlements = UserEntitlement::list_active_for_user(
+        &subscription.user_id, 
+        now, 
+        &mut **tx
+    ).await?;
+    
+    for mut entitlement in user_entitlements {
+        if entitlement.subscription_id.as_deref() == Some(subscription.id.as_str()) {
+            entitlement.revoke(&mut **tx).await?;
+        }
+    }
+    
+    for entitlement_id in product.get_entitlements(&mut **tx).await? {
+        UserEntitlement::new(
+            subscription.user_id.clone(),
+            entitlement_id,
+            Some(subscription.id.clone()),
+            now,
+            subscription.expires_date,
+        )
+        .create(&mut **tx)
+        .await?;
+    }
+    
+    Ok(())
+}
+
 async fn process_one_time_purchased(
+    payload: &GoogleNotificationPayload,
     notification: &GoogleOneTimeProductNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     let google_product_id = &notification.sku;
     let order_id = "GPA.9876-5432-1098-76543"; // This would come from the Google API
     let purchase_time = Utc::now();
+    let obfuscated_external_account_id: Option<String> = None; // This would come from the Google API (obfuscatedExternalAccountId)
+    let obfuscated_external_profile_id: Option<String> = None; // This would come from the Google API (obfuscatedExternalProfileId)
     
-    // Find or create user
-    let user_id = {
-        // Check if we have a user associated with this purchase token
-        let user = User::find_by_app_user_id(purchase_token, pool).await?;
-        
-        match user {
-            Some(user) => user.id,
-            None => {
-                // Create a new user
-                let new_user = User::new(purchase_token.to_string(), None);
-                new_user.create(pool).await?;
-                new_user.id
-            }
-        }
+    let account_tokens = [obfuscated_external_account_id, obfuscated_external_profile_id];
+    let Some(user_id) = find_user(payload, google_product_id, purchase_token, &account_tokens, tx).await? else {
+        return Ok(());
     };
     
     // Find the product by Google product ID
-    let product = Product::find_by_store_product_id("google", google_product_id, pool)
+    let product = Product::find_by_store_product_id("google", google_product_id, &mut **tx)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("Product not found: {}", google_product_id)))?;
     
-    // Create a non-renewing subscription (one-time purchase)
-    let subscription = Subscription::new(
-        user_id.clone(),
-        product.id.clone(),
-        Some(purchase_token.to_string()),
-        Some(order_id.to_string()),
-        "google".to_string(),
+    super::record_one_time_purchase(
+        &user_id,
+        &product,
+        "google",
+        purchase_token,
+        order_id,
         purchase_time,
-        None, // No expiration for one-time purchases
-        SubscriptionStatus::Active,
-        Some(false), // Not auto-renewing
-        None,        // Price paid (not available in this mock)
-        None,        // Currency (not available in this mock)
-        false,       // Is trial
-        false,       // Is intro offer
-    );
-    
-    subscription.create(pool).await?;
-    
-    // Get the entitlements for this product
-    let entitlement_ids = product.get_entitlements(pool).await?;
-    
-    // Grant lifetime entitlements to the user
-    for entitlement_id in entitlement_ids {
-        let user_entitlement = UserEntitlement::new(
-            user_id.clone(),
-            entitlement_id,
-            Some(subscription.id.clone()),
-            purchase_time,
-            None, // No expiration (lifetime)
-        );
-        
-        user_entitlement.create(pool).await?;
-    }
+        tx,
+    )
+    .await?;
     
     Ok(())
 }
 
 async fn process_one_time_canceled(
     notification: &GoogleOneTimeProductNotification,
-    pool: &SqlitePool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
 ) -> Result<()> {
     let purchase_token = &notification.purchase_token;
     
@@ -664,7 +918,7 @@ async fn process_one_time_canceled(
     let mut subscription = Subscription::find_by_store_transaction(
         "google", 
         purchase_token, 
-        pool
+        &mut **tx
     )
     .await?
     .ok_or_else(|| AppError::NotFound(
@@ -672,19 +926,30 @@ async fn process_one_time_canceled(
     ))?;
     
     // Update subscription status
-    subscription.update_status(SubscriptionStatus::Refunded, pool).await?;
+    subscription.update_status(SubscriptionStatus::Refunded, &mut **tx).await?;
+    
+    // Record the refund in the transaction ledger
+    Transaction::for_subscription(&subscription, TransactionType::Refund, Utc::now())
+        .create(tx)
+        .await?;
+    
+    // Take back any virtual currency the purchase credited
+    WalletEntry::reverse_purchase(&subscription, tx).await?;
+    
+    // Give back the unused part of a non-renewing period to later purchases
+    subscription.roll_back_period(Utc::now(), tx).await?;
     
     // Revoke user entitlements
     let user_entitlements = UserEntitlement::list_active_for_user(
         &subscription.user_id, 
         Utc::now(), 
-        pool
+        &mut **tx
     ).await?;
     
     for mut entitlement in user_entitlements {
         if let Some(sub_id) = &entitlement.subscription_id {
             if sub_id == &subscription.id {
-                entitlement.revoke(pool).await?;
+                entitlement.revoke(&mut **tx).await?;
             }
         }
     }
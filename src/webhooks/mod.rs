@@ -3,3 +3,222 @@ pub mod google;
 
 pub use apple::handle_apple_webhook;
 pub use google::handle_google_webhook;
+
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::models::{
+    AccountToken, Product, ProductType, Subscription, SubscriptionEvent, SubscriptionEventType, SubscriptionStatus,
+    Transaction, TransactionType, UnattributedPurchase, UnattributedPurchaseStatus, User, UserEntitlement,
+    WalletEntry,
+};
+use crate::error::{AppError, Result};
+
+// Find the user a purchase belongs to. A parked purchase may have been
+// assigned to a user already. Otherwise the account tokens on the purchase
+// are looked up, and then users whose app user ID is the token, for apps
+// that use their own user IDs as tokens.
+async fn resolve_user(
+    store: &str,
+    transaction_id: &str,
+    account_tokens: &[&str],
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<Option<String>> {
+    if let Some(purchase) = UnattributedPurchase::find_by_transaction(store, transaction_id, &mut **tx).await? {
+        if let Some(user_id) = purchase.user_id {
+            return Ok(Some(user_id));
+        }
+    }
+
+    for token in account_tokens {
+        if let Some(normalized) = AccountToken::normalize(store, token) {
+            if let Some(account_token) = AccountToken::find_by_token(store, &normalized, &mut **tx).await? {
+                return Ok(Some(account_token.user_id));
+            }
+        }
+
+        if let Some(user) = User::find_by_app_user_id(token, &mut **tx).await? {
+            return Ok(Some(user.id));
+        }
+    }
+
+    Ok(None)
+}
+
+// Keep a purchase we couldn't match to a user, instead of making up a user
+// for it. It's processed once the app registers its account token or it's
+// assigned to a user.
+async fn park_purchase(mut purchase: UnattributedPurchase, tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<()> {
+    purchase.account_token = purchase
+        .account_token
+        .take()
+        .map(|token| AccountToken::normalize(&purchase.store, &token).unwrap_or(token));
+
+    tracing::info!(
+        "Unattributed {} purchase {} (account token {:?})",
+        purchase.store,
+        purchase.transaction_id,
+        purchase.account_token
+    );
+
+    purchase.create(&mut **tx).await?;
+
+    Ok(())
+}
+
+// Process a parked purchase for a user by replaying its notification
+pub async fn attribute_purchase(purchase: &mut UnattributedPurchase, user_id: &str, pool: &SqlitePool) -> Result<()> {
+    if !purchase.is_pending() {
+        return Err(AppError::BadRequest(format!(
+            "Purchase {} was already attributed",
+            purchase.id
+        )));
+    }
+
+    // Replaying the notification looks the user up from the parked purchase
+    purchase.user_id = Some(user_id.to_string());
+    purchase.update(pool).await?;
+
+    let result = match purchase.store.as_str() {
+        "apple" => apple::replay_notification(&purchase.notification_json, pool).await,
+        "google" => google::replay_notification(&purchase.notification_json, pool).await,
+        store => Err(AppError::ValidationError(format!("Unknown store: {}", store))),
+    };
+
+    if let Err(e) = result {
+        purchase.user_id = None;
+        purchase.update(pool).await?;
+        return Err(e);
+    }
+
+    purchase.status = UnattributedPurchaseStatus::Attributed.to_string();
+    purchase.attributed_at = Some(Utc::now());
+    purchase.update(pool).await?;
+
+    Ok(())
+}
+
+// Move a user from one subscription to another after a product change. The
+// old subscription ends and loses its entitlements, and the new one is linked
+// to it and granted its product's entitlements.
+async fn replace_subscription(
+    old: &mut Subscription,
+    new: &mut Subscription,
+    new_product: &Product,
+    effective_date: DateTime<Utc>,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<()> {
+    let old_product = Product::find_by_id(&old.product_id, &mut **tx).await?;
+    let change_type = old_product.and_then(|old_product| old_product.change_type(new_product));
+
+    new.previous_subscription_id = Some(old.id.clone());
+    new.create(&mut **tx).await?;
+
+    old.expires_date = Some(effective_date);
+    old.status = SubscriptionStatus::Expired.to_string();
+    old.auto_renew_status = Some(false);
+    old.pending_product_id = None;
+    old.update(&mut **tx).await?;
+
+    let user_entitlements = UserEntitlement::list_active_for_user(&old.user_id, Utc::now(), &mut **tx).await?;
+
+    for mut entitlement in user_entitlements {
+        if entitlement.subscription_id.as_deref() == Some(old.id.as_str()) {
+            entitlement.revoke(&mut **tx).await?;
+        }
+    }
+
+    // Family shares end with the old subscription
+    old.sync_family_shares(tx).await?;
+
+    for entitlement_id in new_product.get_entitlements(&mut **tx).await? {
+        UserEntitlement::new(
+            new.user_id.clone(),
+            entitlement_id,
+            Some(new.id.clone()),
+            effective_date,
+            new.expires_date,
+        )
+        .create(&mut **tx)
+        .await?;
+    }
+
+    SubscriptionEvent::product_change(
+        new,
+        SubscriptionEventType::ProductChange,
+        &old.product_id,
+        &new_product.id,
+        change_type,
+        effective_date,
+    )
+    .create(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Record a one-time purchase. Consumables credit the user's wallet,
+// non-renewing products grant a fixed period stacked after any time the user
+// has left, and other one-time products grant their entitlements for life.
+async fn record_one_time_purchase(
+    user_id: &str,
+    product: &Product,
+    store: &str,
+    original_transaction_id: &str,
+    transaction_id: &str,
+    purchase_date: DateTime<Utc>,
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<Subscription> {
+    let period = if product.type_ == ProductType::NonRenewing.to_string() {
+        let duration_days = product.duration_days.ok_or_else(|| {
+            AppError::ValidationError(format!("Non-renewing product has no duration: {}", product.id))
+        })?;
+        let start = Subscription::next_period_start(user_id, &product.id, purchase_date, &mut **tx).await?;
+        Some((start, start + chrono::Duration::days(duration_days as i64)))
+    } else {
+        None
+    };
+
+    let mut subscription = Subscription::new(
+        user_id.to_string(),
+        product.id.clone(),
+        Some(original_transaction_id.to_string()),
+        Some(transaction_id.to_string()),
+        store.to_string(),
+        purchase_date,
+        period.map(|(_, end)| end),
+        SubscriptionStatus::Active,
+        Some(false), // Not auto-renewing
+        None,        // Price paid (not available in this mock)
+        None,        // Currency (not available in this mock)
+        false,       // Is trial
+        false,       // Is intro offer
+    );
+    subscription.period_start_date = period.map(|(start, _)| start);
+
+    subscription.create(&mut **tx).await?;
+
+    Transaction::for_subscription(&subscription, TransactionType::InitialPurchase, purchase_date)
+        .create(tx)
+        .await?;
+
+    // Consumables credit the user's wallet instead of granting access
+    if product.type_ == ProductType::Consumable.to_string() {
+        WalletEntry::credit_purchase(&subscription, product, &mut **tx).await?;
+        return Ok(subscription);
+    }
+
+    for entitlement_id in product.get_entitlements(&mut **tx).await? {
+        UserEntitlement::new(
+            user_id.to_string(),
+            entitlement_id,
+            Some(subscription.id.clone()),
+            subscription.period_start_date.unwrap_or(purchase_date),
+            subscription.expires_date,
+        )
+        .create(&mut **tx)
+        .await?;
+    }
+
+    Ok(subscription)
+}